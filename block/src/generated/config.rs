@@ -1,10 +1,30 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
+pub struct Headers0Config {
+    #[serde(alias = "name")]
+    pub name: String,
+    #[serde(alias = "value")]
+    pub value: String,
+}
+#[derive(Deserialize, Clone, Debug)]
+pub struct RejectionConfig {
+    #[serde(alias = "bodyFormat")]
+    pub body_format: Option<String>,
+    #[serde(alias = "headers")]
+    pub headers: Option<Vec<Headers0Config>>,
+    #[serde(alias = "statusCode")]
+    pub status_code: Option<i64>,
+}
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
     #[serde(alias = "frequency")]
     pub frequency: i64,
     #[serde(alias = "ip", deserialize_with = "de_ip_0")]
     pub ip: pdk::script::Script,
+    #[serde(alias = "rejection")]
+    pub rejection: Option<RejectionConfig>,
+    #[serde(alias = "reportOnly")]
+    pub report_only: Option<bool>,
     #[serde(alias = "source", deserialize_with = "pdk::serde::deserialize_service")]
     pub source: pdk::hl::Service,
 }
@@ -1,5 +1,6 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
 mod generated;
+mod rejection;
 
 use anyhow::{anyhow, Result};
 use futures::join;
@@ -14,9 +15,11 @@ use pdk::hl::timer::{Clock, Timer};
 use pdk::hl::*;
 use pdk::lock::{LockBuilder, TryLock};
 use pdk::logger;
+use pdk::policy_violation::PolicyViolations;
 use pdk::script::{HandlerAttributesBinding, PayloadBinding, Value};
 
 use crate::generated::config::Config;
+use crate::rejection::{BodyFormat, Rejection};
 
 /// Identifier for the cache and the lock to share data between workers.
 const ID: &str = "block";
@@ -161,17 +164,22 @@ async fn request_filter(
     config: &Config,
     properties: StreamProperties,
     blocked_ips: &BlockedIPs,
+    rejection: &Rejection,
+    violations: &PolicyViolations,
 ) -> Flow<()> {
     let state = state.into_headers_state().await;
     let mut eval = config.ip.evaluator();
     eval.bind_attributes(&HandlerAttributesBinding::new(state.handler(), &properties));
 
-    if let Ok(Value::String(val)) = eval.eval() {
-        if blocked_ips.allowed(val.as_str()) {
-            return Flow::Continue(());
-        }
+    let Ok(Value::String(val)) = eval.eval() else {
+        return rejection.reject(violations, "Missing IP!");
+    };
+
+    if blocked_ips.allowed(val.as_str()) {
+        return Flow::Continue(());
     }
-    Flow::Break(Response::new(403))
+
+    rejection.reject(violations, "Blocked IP!")
 }
 
 #[entrypoint]
@@ -182,6 +190,7 @@ async fn configure(
     cache: CacheBuilder, // Inject the cache to be able to share data between the workers.
     clock: Clock,        // Inject the clock to be able to launch async tasks.
     lock: LockBuilder,   // Inject the lock to be able to synchronise the workers.
+    violations: PolicyViolations,
 ) -> Result<()> {
     let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
         anyhow!(
@@ -210,6 +219,13 @@ async fn configure(
 
     let blocked_ips = BlockedIPs::default();
 
+    // Response for rejected requests, empty unless configured otherwise.
+    let rejection = Rejection::from_config(
+        config.rejection.as_ref(),
+        config.report_only.unwrap_or_default(),
+        BodyFormat::Empty,
+    )?;
+
     // Create the future tasks.
     // Note: We don't do individual 'await's here because we want both task to progress their execution.
 
@@ -218,7 +234,7 @@ async fn configure(
 
     // Future that will handle the requests
     let launched = launcher.launch(on_request(|rs, st| {
-        request_filter(rs, &config, st, &blocked_ips)
+        request_filter(rs, &config, st, &blocked_ips, &rejection, &violations)
     }));
 
     // Await for both futures to finish
@@ -232,8 +248,11 @@ async fn configure(
 
 #[cfg(test)]
 mod tests {
-    use pdk_unit::{dw2pel, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder};
+    use pdk_unit::{
+        dw2pel, TraceBackend, UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder,
+    };
     use serde_json::json;
+    use std::rc::Rc;
 
     fn blocklist_backend(_: UnitHttpRequest) -> UnitHttpResponse {
         UnitHttpResponse::new(200).with_body("192.168.1.1/32\n10.0.0.1/32\n")
@@ -304,4 +323,58 @@ mod tests {
 
         assert_eq!(response.status_code(), 403);
     }
+
+    #[test]
+    fn rejection_response_is_configurable() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "source": "http://blocklist",
+                    "ip": dw2pel("attributes.headers['x-forwarded-for']"),
+                    "frequency": 60,
+                    "rejection": {
+                        "statusCode": 451,
+                        "bodyFormat": "json"
+                    }
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("blocklist", blocklist_backend)
+            .with_entrypoint(crate::configure);
+
+        tester.tick();
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.168.1.1"));
+
+        assert_eq!(response.status_code(), 451);
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body(), br#"{"error":"Blocked IP!"}"#);
+    }
+
+    #[test]
+    fn report_only_mode_does_not_block() {
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "source": "http://blocklist",
+                    "ip": dw2pel("attributes.headers['x-forwarded-for']"),
+                    "frequency": 60,
+                    "reportOnly": true
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("blocklist", blocklist_backend)
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        tester.tick();
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.168.1.1"));
+
+        assert_eq!(response.status_code(), 200);
+        assert!(backend.next().unwrap().violation().is_some());
+    }
 }
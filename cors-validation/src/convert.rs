@@ -1,12 +1,22 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
-use anyhow::Result;
+
+//! This module implements conversions from policy [`Config`] into a [`cors::Configuration`].
+
+use anyhow::{anyhow, Result};
 use pdk::cors;
+use regex::Regex;
 
 use crate::generated::config::AllowedMethods0Config as AllowedMethod;
 use crate::generated::config::Config;
 use crate::generated::config::OriginGroups0Config as OriginGroup;
 
-/// This module implements conversions from policy [`Config`] into a [`cors::Configuration`].
+/// Origin that allows any origin.
+const ANY_ORIGIN: &str = "*";
+
+/// Pattern that replaces a `*` in the host of a wildcard origin. It matches one or more DNS
+/// labels, so `https://*.example.com` matches `https://api.example.com` and
+/// `https://eu.api.example.com`, but never crosses into the scheme or the port.
+const HOST_WILDCARD: &str = "[a-z0-9-]+(?:\\.[a-z0-9-]+)*";
 
 impl Config {
     /// Translates the policy [`Config`] into a [`cors::Configuration`].
@@ -29,6 +39,12 @@ impl Config {
 
 impl OriginGroup {
     fn into_cors(self) -> Result<cors::OriginGroup<'static>> {
+        let (plain_origins, regex_origins) = compile_origins(
+            &self.origins,
+            self.regex_origins.as_deref().unwrap_or_default(),
+        )
+        .map_err(|err| anyhow!("Origin group '{}': {err}", self.name))?;
+
         let allowed_methods: Result<Vec<_>> = self
             .allowed_methods
             .into_iter()
@@ -37,8 +53,8 @@ impl OriginGroup {
 
         let origin_group = cors::OriginGroup::builder()
             .origin_group_name(self.name)
-            .plain_origins(self.origins)
-            .regex_origins(Vec::new())
+            .plain_origins(plain_origins)
+            .regex_origins(regex_origins)
             .access_control_max_age(self.access_control_max_age as u32)
             .allowed_methods(allowed_methods?)
             .headers(self.headers)
@@ -58,3 +74,119 @@ impl AllowedMethod {
         Ok(method)
     }
 }
+
+/// Splits the configured origins into plain origins and the regexes compiled from wildcard
+/// origins and regex origins.
+fn compile_origins(
+    origins: &[String],
+    regex_origins: &[String],
+) -> Result<(Vec<String>, Vec<Regex>)> {
+    let (wildcards, plain): (Vec<_>, Vec<_>) = origins
+        .iter()
+        .cloned()
+        .partition(|origin| origin != ANY_ORIGIN && origin.contains('*'));
+
+    let wildcards = wildcards.iter().map(|origin| wildcard_origin(origin));
+    let regexes = regex_origins.iter().map(|pattern| regex_origin(pattern));
+
+    Ok((plain, wildcards.chain(regexes).collect::<Result<_>>()?))
+}
+
+/// Compiles a regex origin. The pattern must match the whole origin.
+fn regex_origin(pattern: &str) -> Result<Regex> {
+    Regex::new(&format!("^(?:{pattern})$"))
+        .map_err(|err| anyhow!("'{pattern}' is not a valid regular expression: {err}"))
+}
+
+/// Compiles a wildcard origin such as `https://*.example.com` or `http://localhost:*`.
+/// Wildcards are only allowed in the host and as the whole port.
+fn wildcard_origin(origin: &str) -> Result<Regex> {
+    let invalid = |reason: &str| anyhow!("'{origin}' is not a valid wildcard origin: {reason}.");
+
+    let (scheme, authority) = origin
+        .split_once("://")
+        .ok_or_else(|| invalid("missing scheme"))?;
+
+    if scheme.is_empty() || scheme.contains('*') {
+        return Err(invalid("the scheme must not contain wildcards"));
+    }
+
+    if authority.contains('/') {
+        return Err(invalid("origins must not contain a path"));
+    }
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (authority, None),
+    };
+
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+
+    let port = match port {
+        None => String::new(),
+        Some("*") => ":[0-9]+".to_string(),
+        Some(port) if port.parse::<u16>().is_ok() => format!(":{port}"),
+        Some(_) => return Err(invalid("the port must be a number or '*'")),
+    };
+
+    let host = host
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(HOST_WILDCARD);
+
+    Regex::new(&format!("(?i)^{}://{host}{port}$", regex::escape(scheme)))
+        .map_err(|err| invalid(&err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile_origins, wildcard_origin};
+
+    #[test]
+    fn wildcard_origin_matches_subdomains_only() {
+        let regex = wildcard_origin("https://*.example.com").unwrap();
+
+        assert!(regex.is_match("https://api.example.com"));
+        assert!(regex.is_match("https://eu.api.example.com"));
+        assert!(!regex.is_match("https://example.com"));
+        assert!(!regex.is_match("http://api.example.com"));
+        assert!(!regex.is_match("https://api.example.com.evil.com"));
+        assert!(!regex.is_match("https://api.example.com:8443"));
+
+        let regex = wildcard_origin("http://localhost:*").unwrap();
+        assert!(regex.is_match("http://localhost:3000"));
+        assert!(!regex.is_match("http://localhost"));
+    }
+
+    #[test]
+    fn invalid_origins_are_rejected() {
+        assert!(wildcard_origin("*.example.com").is_err());
+        assert!(wildcard_origin("*://example.com").is_err());
+        assert!(wildcard_origin("https://*.example.com/path").is_err());
+        assert!(wildcard_origin("https://*.example.com:port").is_err());
+
+        let err = compile_origins(&[], &["https://(unclosed".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("not a valid regular expression"));
+    }
+
+    #[test]
+    fn plain_origins_are_kept() {
+        let (plain, regexes) = compile_origins(
+            &[
+                "*".to_string(),
+                "https://example.com".to_string(),
+                "https://*.example.com".to_string(),
+            ],
+            &[r"https://app-[0-9]+\.example\.com".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(plain, ["*", "https://example.com"]);
+        assert_eq!(regexes.len(), 2);
+        assert!(regexes[1].is_match("https://app-1.example.com"));
+        assert!(!regexes[1].is_match("https://app-1.example.com.evil.com"));
+    }
+}
@@ -20,13 +20,21 @@ pub struct OriginGroups0Config {
     pub name: String,
     #[serde(alias = "origins")]
     pub origins: Vec<String>,
+    #[serde(alias = "regexOrigins")]
+    pub regex_origins: Option<Vec<String>>,
 }
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "allowPrivateNetwork")]
+    pub allow_private_network: Option<bool>,
     #[serde(alias = "originGroups")]
     pub origin_groups: Vec<OriginGroups0Config>,
     #[serde(alias = "publicResource")]
     pub public_resource: bool,
+    #[serde(alias = "reportOnly")]
+    pub report_only: Option<bool>,
+    #[serde(alias = "stripUpstreamCorsHeaders")]
+    pub strip_upstream_cors_headers: Option<bool>,
     #[serde(alias = "supportCredentials")]
     pub support_credentials: bool,
 }
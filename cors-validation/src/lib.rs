@@ -1,27 +1,70 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use pdk::cors;
 use pdk::hl::*;
 use pdk::logger;
+use pdk::policy_violation::PolicyViolations;
 
 pub mod convert;
 pub mod generated;
 use generated::config::Config;
 
+/// Header that carries the reason of a CORS rejection.
+const CORS_ERROR_HEADER: &str = "x-cors-error";
+
+/// Preflight request header sent by browsers before reaching a more private network.
+const REQUEST_PRIVATE_NETWORK_HEADER: &str = "access-control-request-private-network";
+
+/// Preflight response header that grants access to a more private network.
+const ALLOW_PRIVATE_NETWORK_HEADER: &str = "access-control-allow-private-network";
+
+/// Request headers that change the CORS response, and must be listed in `Vary`.
+const PREFLIGHT_VARY: &[&str] = &[
+    "Origin",
+    "Access-Control-Request-Method",
+    "Access-Control-Request-Headers",
+    "Access-Control-Request-Private-Network",
+];
+
+/// Policy behaviour not covered by the CORS library.
+#[derive(Clone, Copy)]
+struct Options {
+    report_only: bool,
+    allow_private_network: bool,
+    strip_upstream_cors_headers: bool,
+}
+
 async fn request_filter(
     state: RequestHeadersState,
     cors: &cors::Cors<'_>,
+    options: Options,
+    violations: &PolicyViolations,
 ) -> Flow<Vec<(String, String)>> {
     logger::info!("Validating CORS on request.");
 
+    let handler = state.handler();
+
     // Determine what kind of request is incoming.
-    match cors.check_headers(state.handler().headers().as_slice()) {
+    match cors.check_headers(handler.headers().as_slice()) {
         Ok(check) => match check.response_type() {
             // A preflight request must return a 200 OK.
             cors::ResponseType::Preflight => {
                 logger::info!("Preflight CORS response.");
-                Flow::Break(Response::new(200).with_headers(check.into_headers()))
+                let mut headers = check.into_headers();
+
+                // Private Network Access preflights are only granted if configured.
+                let private_network = handler
+                    .header(REQUEST_PRIVATE_NETWORK_HEADER)
+                    .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+                if private_network && options.allow_private_network {
+                    logger::info!("Granting private network access.");
+                    headers.push((ALLOW_PRIVATE_NETWORK_HEADER.to_string(), "true".to_string()));
+                }
+
+                headers.push(("vary".to_string(), PREFLIGHT_VARY.join(", ")));
+
+                Flow::Break(Response::new(200).with_headers(headers))
             }
 
             // A main request must continue.
@@ -31,15 +74,29 @@ async fn request_filter(
             }
         },
 
-        // A validation problem ocurred. Must return early.
-        Err(message) => {
-            logger::debug!("Request finished with the following error {message}");
-            Flow::Break(Response::new(200))
+        // A validation problem ocurred. The request is rejected unless we are only reporting.
+        Err(error) => {
+            violations.generate_policy_violation();
+
+            if options.report_only {
+                logger::warn!("Report-only mode, CORS request would have been rejected: {error}");
+                return Flow::Continue(Vec::new());
+            }
+
+            logger::info!("CORS request rejected: {error}");
+            Flow::Break(
+                Response::new(403)
+                    .with_headers([(CORS_ERROR_HEADER.to_string(), error.to_string())]),
+            )
         }
     }
 }
 
-async fn response_filter(state: ResponseHeadersState, data: RequestData<Vec<(String, String)>>) {
+async fn response_filter(
+    state: ResponseHeadersState,
+    data: RequestData<Vec<(String, String)>>,
+    options: Options,
+) {
     logger::info!("Applying CORS on response.");
 
     // Take the validation headers from the request.
@@ -49,59 +106,117 @@ async fn response_filter(state: ResponseHeadersState, data: RequestData<Vec<(Str
         return;
     };
 
+    let handler = state.handler();
+
+    // Remove the CORS headers set by the upstream, so the policy is the single source of truth.
+    if options.strip_upstream_cors_headers {
+        for (name, _) in handler.headers() {
+            if name.to_lowercase().starts_with("access-control-") {
+                logger::info!("Removing upstream header {name}");
+                handler.remove_header(&name);
+            }
+        }
+    }
+
     // Add all the validation headers into the response.
     for (name, value) in headers_to_add.iter() {
         logger::info!("Adding header {name} = {value}");
-        state.handler().set_header(name, value);
+        handler.set_header(name, value);
+    }
+
+    // The response depends on the request origin, so shared caches must not reuse it for other
+    // origins. The upstream Vary values are kept.
+    let vary: Vec<String> = handler
+        .headers()
+        .into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("vary"))
+        .map(|(_, value)| value)
+        .collect();
+    if let Some(vary) = merge_vary(&vary, "Origin") {
+        handler.set_header("vary", &vary);
+    }
+}
+
+/// Adds a value to the `Vary` header values. Returns [None] if no change is needed.
+fn merge_vary(values: &[String], value: &str) -> Option<String> {
+    let tokens: Vec<&str> = values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    if tokens
+        .iter()
+        .any(|t| *t == "*" || t.eq_ignore_ascii_case(value))
+    {
+        return None;
     }
+
+    Some(
+        tokens
+            .into_iter()
+            .chain([value])
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
 }
 
 #[entrypoint]
 async fn configure(
     launcher: Launcher,
-    Configuration(config): Configuration,
-) -> Result<(), LaunchError> {
+    Configuration(bytes): Configuration,
+    violations: PolicyViolations,
+) -> Result<()> {
     logger::info!("Deserializing new configuration.");
-    let config: Config = match serde_json::from_slice(&config) {
-        Ok(config) => config,
-        Err(err) => {
-            logger::error!("Problem deserializing configuration: {err}");
-            return Ok(());
-        }
+    let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
+        anyhow!(
+            "Failed to parse configuration '{}'. Cause: {}",
+            String::from_utf8_lossy(&bytes),
+            err
+        )
+    })?;
+
+    let options = Options {
+        report_only: config.report_only.unwrap_or_default(),
+        allow_private_network: config.allow_private_network.unwrap_or_default(),
+        strip_upstream_cors_headers: config.strip_upstream_cors_headers.unwrap_or_default(),
     };
 
     logger::info!("Translating deserialized configuration.");
 
     // Translate the filter configuration into a CORS Configuration.
-    let cors_config = match config.into_cors() {
-        Ok(config) => config,
-        Err(err) => {
-            logger::error!("Problem translating configuration: {err}");
-            return Ok(());
-        }
-    };
+    let cors_config = config
+        .into_cors()
+        .map_err(|err| anyhow!("Invalid CORS configuration. Cause: {err}"))?;
 
     let cors = cors::Cors::new(&cors_config);
 
-    let filter = on_request(|rs| request_filter(rs, &cors)).on_response(response_filter);
+    let filter = on_request(|rs| request_filter(rs, &cors, options, &violations))
+        .on_response(|rs, data| response_filter(rs, data, options));
 
     // Launch the filter.
-    launcher.launch(filter).await
+    launcher.launch(filter).await?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use pdk_unit::{UnitHttpRequest, UnitTestBuilder};
+    use pdk_unit::{UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder};
     use serde_json::json;
 
     fn config_with_origin(origin: &str) -> String {
-        json!({
+        config_with_origins(json!([origin]), json!({}))
+    }
+
+    fn config_with_origins(origins: serde_json::Value, extra: serde_json::Value) -> String {
+        let mut config = json!({
             "publicResource": false,
             "supportCredentials": false,
             "originGroups": [
                 {
                     "name": "group1",
-                    "origins": [origin],
+                    "origins": origins,
                     "allowedMethods": [
                         {"methodName": "GET", "allowed": true}
                     ],
@@ -110,8 +225,11 @@ mod tests {
                     "accessControlMaxAge": 300.0
                 }
             ]
-        })
-        .to_string()
+        });
+        if let (Some(config), Some(extra)) = (config.as_object_mut(), extra.as_object()) {
+            config.extend(extra.clone());
+        }
+        config.to_string()
     }
 
     #[test]
@@ -151,4 +269,148 @@ mod tests {
 
         assert_eq!(response.status_code(), 200);
     }
+
+    #[test]
+    fn request_from_disallowed_origin_is_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origin("https://example.com"))
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("origin", "https://evil.com"));
+
+        assert_eq!(response.status_code(), 403);
+        assert_eq!(
+            response.header("x-cors-error"),
+            Some("Incoming request Origin does not match with any allowed Origin.")
+        );
+    }
+
+    #[test]
+    fn preflight_with_disallowed_method_is_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origin("https://example.com"))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::options()
+                .with_header("origin", "https://example.com")
+                .with_header("access-control-request-method", "DELETE"),
+        );
+
+        assert_eq!(response.status_code(), 403);
+        assert!(response.header("x-cors-error").is_some());
+    }
+
+    #[test]
+    fn report_only_mode_does_not_reject() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origins(
+                json!(["https://example.com"]),
+                json!({ "reportOnly": true }),
+            ))
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("origin", "https://evil.com"));
+
+        assert_eq!(response.status_code(), 200);
+        assert!(response.header("access-control-allow-origin").is_none());
+    }
+
+    #[test]
+    fn invalid_configuration_fails() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origins(json!(["*://example.com"]), json!({})))
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("origin", "https://example.com"));
+
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn private_network_preflight_is_granted() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origins(
+                json!(["https://example.com"]),
+                json!({ "allowPrivateNetwork": true }),
+            ))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::options()
+                .with_header("origin", "https://example.com")
+                .with_header("access-control-request-method", "GET")
+                .with_header("access-control-request-private-network", "true"),
+        );
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            response.header("access-control-allow-private-network"),
+            Some("true")
+        );
+        assert!(response.header("vary").unwrap().contains("Origin"));
+    }
+
+    #[test]
+    fn private_network_preflight_is_not_granted_by_default() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origin("https://example.com"))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::options()
+                .with_header("origin", "https://example.com")
+                .with_header("access-control-request-method", "GET")
+                .with_header("access-control-request-private-network", "true"),
+        );
+
+        assert!(response
+            .header("access-control-allow-private-network")
+            .is_none());
+    }
+
+    #[test]
+    fn upstream_vary_is_merged_and_cors_headers_stripped() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config_with_origins(
+                json!(["https://example.com"]),
+                json!({ "stripUpstreamCorsHeaders": true }),
+            ))
+            .with_backend(|_| {
+                UnitHttpResponse::new(200)
+                    .with_header("vary", "Accept-Encoding")
+                    .with_header("access-control-allow-origin", "*")
+                    .with_header("access-control-allow-credentials", "true")
+            })
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("origin", "https://example.com"));
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.header("vary"), Some("Accept-Encoding, Origin"));
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert!(response
+            .header("access-control-allow-credentials")
+            .is_none());
+    }
+
+    #[test]
+    fn vary_values_are_merged() {
+        use crate::merge_vary;
+
+        assert_eq!(merge_vary(&[], "Origin"), Some("Origin".to_string()));
+        assert_eq!(
+            merge_vary(&["Accept".to_string(), "Cookie".to_string()], "Origin"),
+            Some("Accept, Cookie, Origin".to_string())
+        );
+        assert_eq!(merge_vary(&["accept, origin".to_string()], "Origin"), None);
+        assert_eq!(merge_vary(&["*".to_string()], "Origin"), None);
+    }
 }
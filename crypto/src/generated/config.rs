@@ -1,11 +1,36 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
-pub struct Config {
+pub struct Keys0Config {
     #[serde(alias = "aes_key")]
-    pub aes_key: String,
+    pub aes_key: Option<String>,
+    #[serde(alias = "aes_key_encoding")]
+    pub aes_key_encoding: Option<String>,
+    #[serde(alias = "kid")]
+    pub kid: String,
     #[serde(alias = "rsa_key")]
     pub rsa_key: String,
 }
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    #[serde(alias = "active_kid")]
+    pub active_kid: Option<String>,
+    #[serde(alias = "aes_key")]
+    pub aes_key: Option<String>,
+    #[serde(alias = "aes_key_encoding")]
+    pub aes_key_encoding: Option<String>,
+    #[serde(alias = "decrypt_request")]
+    pub decrypt_request: Option<bool>,
+    #[serde(alias = "format")]
+    pub format: Option<String>,
+    #[serde(alias = "jwe_public_key")]
+    pub jwe_public_key: Option<String>,
+    #[serde(alias = "keys")]
+    pub keys: Option<Vec<Keys0Config>>,
+    #[serde(alias = "request_encoding")]
+    pub request_encoding: Option<String>,
+    #[serde(alias = "rsa_key")]
+    pub rsa_key: Option<String>,
+}
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
     abi.setup()?;
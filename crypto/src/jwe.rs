@@ -0,0 +1,174 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! JWE compact serialization with RSA-OAEP-256 key wrapping and A256GCM content encryption,
+//! as described in RFC 7516 and RFC 7518.
+
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use rsa::sha2::Sha256;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+
+/// Key management algorithm supported by the policy.
+const ALG: &str = "RSA-OAEP-256";
+
+/// Content encryption algorithm supported by the policy.
+const ENC: &str = "A256GCM";
+
+/// Size in bytes of the A256GCM content encryption key.
+const CEK_SIZE: usize = 32;
+
+/// Size in bytes of the A256GCM initialization vector.
+const IV_SIZE: usize = 12;
+
+/// Size in bytes of the A256GCM authentication tag.
+const TAG_SIZE: usize = 16;
+
+/// Media type of a JWE in compact serialization.
+pub const CONTENT_TYPE: &str = "application/jose";
+
+/// JOSE protected header.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    enc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    zip: Option<String>,
+}
+
+/// Decrypts a JWE in compact serialization with the key selected by the `kid` of its header.
+/// Returns the plaintext.
+pub fn decrypt<'a>(
+    compact: &str,
+    key_for: impl FnOnce(Option<&str>) -> Option<&'a RsaPrivateKey>,
+) -> Result<Vec<u8>> {
+    let parts: Vec<&str> = compact.trim().split('.').collect();
+    let [protected, encrypted_key, iv, ciphertext, tag] = parts.as_slice() else {
+        return Err(anyhow!("Expected 5 parts, found {}.", parts.len()));
+    };
+
+    let decode = |part: &str, name: &str| {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(part)
+            .map_err(|err| anyhow!("Invalid {name} encoding. Cause: {err}"))
+    };
+
+    let header: Header = serde_json::from_slice(&decode(protected, "header")?)
+        .map_err(|err| anyhow!("Invalid header. Cause: {err}"))?;
+    if header.alg != ALG || header.enc != ENC {
+        return Err(anyhow!(
+            "Unsupported algorithms '{}' and '{}'.",
+            header.alg,
+            header.enc
+        ));
+    }
+    if header.zip.is_some() {
+        return Err(anyhow!("Compressed payloads are not supported."));
+    }
+
+    let key = key_for(header.kid.as_deref()).ok_or_else(|| anyhow!("Unknown kid."))?;
+    let cek = key
+        .decrypt(
+            Oaep::new::<Sha256>(),
+            &decode(encrypted_key, "encrypted key")?,
+        )
+        .map_err(|_| anyhow!("Failed to decrypt the content encryption key."))?;
+    let iv = decode(iv, "initialization vector")?;
+    let tag = decode(tag, "authentication tag")?;
+    if cek.len() != CEK_SIZE || iv.len() != IV_SIZE || tag.len() != TAG_SIZE {
+        return Err(anyhow!("Invalid key, initialization vector or tag size."));
+    }
+
+    // The aes-gcm library expects the tag appended to the ciphertext.
+    let mut msg = decode(ciphertext, "ciphertext")?;
+    msg.extend_from_slice(&tag);
+
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&cek))
+        .decrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &msg,
+                aad: protected.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Failed to decrypt the content."))
+}
+
+/// Encrypts the plaintext for the recipient public key. Returns the JWE in compact serialization.
+pub fn encrypt(plaintext: &[u8], recipient: &RsaPublicKey) -> Result<String> {
+    let header = serde_json::to_vec(&Header {
+        alg: ALG.to_string(),
+        enc: ENC.to_string(),
+        kid: None,
+        zip: None,
+    })?;
+    let protected = BASE64_URL_SAFE_NO_PAD.encode(header);
+
+    // A fresh content encryption key and initialization vector are generated for each message.
+    let cek = Aes256Gcm::generate_key(OsRng);
+    let iv = Aes256Gcm::generate_nonce(OsRng);
+
+    let mut ciphertext = Aes256Gcm::new(&cek)
+        .encrypt(
+            &iv,
+            Payload {
+                msg: plaintext,
+                aad: protected.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("Failed to encrypt the content."))?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_SIZE);
+
+    let encrypted_key = recipient
+        .encrypt(&mut OsRng, Oaep::new::<Sha256>(), cek.as_slice())
+        .map_err(|err| anyhow!("Failed to encrypt the content encryption key. Cause: {err}"))?;
+
+    Ok([
+        protected,
+        BASE64_URL_SAFE_NO_PAD.encode(encrypted_key),
+        BASE64_URL_SAFE_NO_PAD.encode(iv),
+        BASE64_URL_SAFE_NO_PAD.encode(ciphertext),
+        BASE64_URL_SAFE_NO_PAD.encode(tag),
+    ]
+    .join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+    use rsa::RsaPrivateKey;
+
+    use super::{decrypt, encrypt};
+
+    #[test]
+    fn encrypted_payload_round_trips() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+
+        let compact = encrypt(b"secret payload", &key.to_public_key()).unwrap();
+
+        assert_eq!(compact.split('.').count(), 5);
+        assert_eq!(
+            decrypt(&compact, |_| Some(&key)).unwrap(),
+            b"secret payload"
+        );
+    }
+
+    #[test]
+    fn tampered_or_unsupported_payloads_are_rejected() {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let compact = encrypt(b"secret payload", &key.to_public_key()).unwrap();
+
+        // Changing the protected header invalidates the authentication tag.
+        let mut parts: Vec<String> = compact.split('.').map(String::from).collect();
+        parts[0] = BASE64_URL_SAFE_NO_PAD.encode(r#"{"enc":"A256GCM","alg":"RSA-OAEP-256"}"#);
+        assert!(decrypt(&parts.join("."), |_| Some(&key)).is_err());
+
+        parts[0] = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"RSA1_5","enc":"A256GCM"}"#);
+        assert!(decrypt(&parts.join("."), |_| Some(&key)).is_err());
+
+        assert!(decrypt("not.a.jwe", |_| Some(&key)).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use aes_gcm::{Aes256Gcm, Key, KeyInit};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::RsaPrivateKey;
+
+use crate::generated::config::Config;
+
+/// Size in bytes of an AES-256 key.
+const AES_KEY_SIZE: usize = 32;
+
+/// Decodes an AES key from its configured encoding. Raw keys are used as UTF-8 bytes.
+fn decode_aes_key(key: &str, encoding: Option<&str>) -> Result<Aes256Gcm> {
+    let bytes = match encoding.unwrap_or("raw") {
+        "raw" => key.as_bytes().to_vec(),
+        "hex" => hex::decode(key).map_err(|err| anyhow!("Invalid hex aes key. Cause: {err}"))?,
+        "base64" => BASE64_STANDARD
+            .decode(key)
+            .map_err(|err| anyhow!("Invalid base64 aes key. Cause: {err}"))?,
+        other => return Err(anyhow!("Unknown aes key encoding '{other}'.")),
+    };
+
+    if bytes.len() != AES_KEY_SIZE {
+        return Err(anyhow!(
+            "The aes key must be {AES_KEY_SIZE} bytes long, found {}.",
+            bytes.len()
+        ));
+    }
+
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes)))
+}
+
+/// RSA and AES keys identified by an optional key id.
+pub struct CryptoKey {
+    pub kid: Option<String>,
+    pub rsa: RsaPrivateKey,
+    pub aes: Option<Aes256Gcm>,
+}
+
+impl CryptoKey {
+    fn new(
+        kid: Option<String>,
+        rsa_key: &str,
+        aes_key: Option<&str>,
+        encoding: Option<&str>,
+    ) -> Result<Self> {
+        let rsa = RsaPrivateKey::from_pkcs1_pem(rsa_key)
+            .map_err(|err| anyhow!("Failed to parse rsa key. Cause: {err}"))?;
+        let aes = aes_key
+            .map(|key| decode_aes_key(key, encoding))
+            .transpose()?;
+
+        Ok(Self { kid, rsa, aes })
+    }
+}
+
+/// Set of keys where the active one is used to encrypt, and all of them are used to decrypt.
+pub struct Keyring {
+    keys: Vec<CryptoKey>,
+    active: usize,
+}
+
+impl Keyring {
+    /// Creates the [Keyring] from the `keys` list of a [Config], or from its single
+    /// `rsa_key` and `aes_key` when the list is not configured.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let keys = config.keys.as_deref().unwrap_or_default();
+
+        if keys.is_empty() {
+            let rsa_key = config
+                .rsa_key
+                .as_deref()
+                .ok_or_else(|| anyhow!("Either rsa_key or keys must be configured."))?;
+            let key = CryptoKey::new(
+                None,
+                rsa_key,
+                config.aes_key.as_deref(),
+                config.aes_key_encoding.as_deref(),
+            )?;
+
+            return Ok(Self {
+                keys: vec![key],
+                active: 0,
+            });
+        }
+
+        if config.rsa_key.is_some() || config.aes_key.is_some() {
+            return Err(anyhow!(
+                "The rsa_key and aes_key can't be configured together with keys."
+            ));
+        }
+
+        let keys = keys
+            .iter()
+            .map(|key| {
+                CryptoKey::new(
+                    Some(key.kid.clone()),
+                    &key.rsa_key,
+                    key.aes_key.as_deref(),
+                    key.aes_key_encoding.as_deref(),
+                )
+                .map_err(|err| anyhow!("Key '{}': {err}", key.kid))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The first key is the active one unless stated otherwise.
+        let active = match config.active_kid.as_deref() {
+            Some(kid) => keys
+                .iter()
+                .position(|key| key.kid.as_deref() == Some(kid))
+                .ok_or_else(|| anyhow!("Unknown active kid '{kid}'."))?,
+            None => 0,
+        };
+
+        Ok(Self { keys, active })
+    }
+
+    /// The key used to encrypt.
+    pub fn active(&self) -> &CryptoKey {
+        &self.keys[self.active]
+    }
+
+    /// Returns the key with the given id, or the active one if no id is provided.
+    pub fn get(&self, kid: Option<&str>) -> Option<&CryptoKey> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|key| key.kid.as_deref() == Some(kid)),
+            None => Some(self.active()),
+        }
+    }
+
+    /// Returns true if all the keys can be used for AES encryption.
+    pub fn has_aes_keys(&self) -> bool {
+        self.keys.iter().all(|key| key.aes.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_aes_key;
+
+    #[test]
+    fn aes_keys_are_decoded() {
+        assert!(decode_aes_key("42F56B955DEA9D821F2A38E3CCDCCQWE", None).is_ok());
+        assert!(decode_aes_key(&"ab".repeat(32), Some("hex")).is_ok());
+        assert!(decode_aes_key(&format!("{}=", "A".repeat(43)), Some("base64")).is_ok());
+        assert!(decode_aes_key(&"A".repeat(44), Some("base64")).is_err());
+        assert!(decode_aes_key("too short", None).is_err());
+        assert!(decode_aes_key("key", Some("rot13")).is_err());
+    }
+}
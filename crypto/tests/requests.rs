@@ -152,9 +152,11 @@ async fn crypto() -> anyhow::Result<()> {
         msg: &decoded,
         aad: &[],
     };
-    let nonce = GenericArray::from_slice(NONCE.as_bytes());
+    // The policy marks the response direction in the most significant bit of the nonce.
+    let mut nonce = NONCE.as_bytes().to_vec();
+    nonce[0] |= 0x80;
     let decrypted = aes
-        .decrypt(nonce, payload)
+        .decrypt(GenericArray::from_slice(&nonce), payload)
         .map_err(|_| anyhow!("Failed to decrypt."))?;
     let decrypted = String::from_utf8(decrypted)?;
 
@@ -0,0 +1,99 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Directives of the `Cache-Control` header that drive the caching decisions.
+
+/// Parsed `Cache-Control` directives. Unknown directives are ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    pub max_age: Option<i64>,
+    pub s_maxage: Option<i64>,
+    pub stale_while_revalidate: Option<i64>,
+    pub stale_if_error: Option<i64>,
+}
+
+impl CacheControl {
+    /// Parses the value of a `Cache-Control` header.
+    pub fn parse(value: &str) -> Self {
+        let mut result = Self::default();
+
+        for directive in value.split(',') {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            // Invalid or negative ages are ignored.
+            let seconds = || {
+                value
+                    .trim()
+                    .trim_matches('"')
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|seconds| *seconds >= 0)
+            };
+
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => result.no_store = true,
+                "no-cache" => result.no_cache = true,
+                "private" => result.private = true,
+                "public" => result.public = true,
+                "must-revalidate" => result.must_revalidate = true,
+                "max-age" => result.max_age = seconds(),
+                "s-maxage" => result.s_maxage = seconds(),
+                "stale-while-revalidate" => result.stale_while_revalidate = seconds(),
+                "stale-if-error" => result.stale_if_error = seconds(),
+                _ => {}
+            }
+        }
+
+        result
+    }
+
+    /// Whether the directives forbid storing the message in a shared cache.
+    pub fn forbids_storing(&self) -> bool {
+        self.no_store || self.private
+    }
+
+    /// Whether the directives allow storing the response to a request with `Authorization` in a
+    /// shared cache, as defined by RFC 9111 section 3.5.
+    pub fn allows_authorized(&self) -> bool {
+        self.public || self.must_revalidate || self.s_maxage.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CacheControl;
+
+    #[test]
+    fn directives_are_parsed() {
+        assert_eq!(
+            CacheControl::parse("No-Store, max-age=60"),
+            CacheControl {
+                no_store: true,
+                max_age: Some(60),
+                ..CacheControl::default()
+            }
+        );
+        assert_eq!(
+            CacheControl::parse(r#"public, s-maxage="120", max-age=-1"#),
+            CacheControl {
+                public: true,
+                s_maxage: Some(120),
+                ..CacheControl::default()
+            }
+        );
+        assert_eq!(
+            CacheControl::parse("max-age=1, stale-while-revalidate=30, stale-if-error=60"),
+            CacheControl {
+                max_age: Some(1),
+                stale_while_revalidate: Some(30),
+                stale_if_error: Some(60),
+                ..CacheControl::default()
+            }
+        );
+        assert!(CacheControl::parse(r#"private="set-cookie""#).forbids_storing());
+        assert!(CacheControl::parse("no-cache").no_cache);
+        assert!(!CacheControl::parse("public, max-age=60").forbids_storing());
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Responses stored in the cache and the conditional requests they answer.
+
+use chrono::{DateTime, Duration, Local};
+use pdk::hl::Response;
+use serde::{Deserialize, Serialize};
+
+use crate::freshness::{parse_http_date, Validity};
+
+/// Headers sent in the 304 responses, as defined by RFC 9110.
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-location",
+    "date",
+    "etag",
+    "expires",
+    "last-modified",
+    "vary",
+];
+
+/// Headers of a 304 response that must not replace the stored ones.
+const IGNORED_UPDATES: &[&str] = &["content-length", "content-encoding", "transfer-encoding"];
+
+/// This struct serializes the response in the cache.
+#[derive(Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub stored_at: DateTime<Local>,
+    pub valid_until: DateTime<Local>,
+    /// Seconds the response spent in other caches before it was stored.
+    pub initial_age: i64,
+    /// Seconds after the expiration during which the response is served while it's refreshed.
+    pub stale_while_revalidate: i64,
+    /// Seconds after the expiration during which the response replaces upstream errors.
+    pub stale_if_error: i64,
+    pub status_code: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl CachedResponse {
+    /// Creates the entry for a response received at `stored_at`, taking its validators from the
+    /// headers.
+    pub fn new(
+        stored_at: DateTime<Local>,
+        validity: &Validity,
+        status_code: u32,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    ) -> Self {
+        let etag = header(&headers, "etag").map(str::to_string);
+        let last_modified = header(&headers, "last-modified").map(str::to_string);
+
+        let mut entry = Self {
+            stored_at,
+            valid_until: stored_at,
+            initial_age: 0,
+            stale_while_revalidate: 0,
+            stale_if_error: 0,
+            status_code,
+            headers,
+            body,
+            etag,
+            last_modified,
+        };
+        entry.refresh(stored_at, validity);
+        entry
+    }
+
+    /// Resets the validity of the entry after the upstream confirmed it at `now`.
+    pub fn refresh(&mut self, now: DateTime<Local>, validity: &Validity) {
+        self.stored_at = now;
+        self.valid_until = validity.valid_until;
+        self.initial_age = validity.initial_age.num_seconds();
+        self.stale_while_revalidate = validity.stale_while_revalidate.num_seconds();
+        self.stale_if_error = validity.stale_if_error.num_seconds();
+    }
+
+    /// Given the current time check, if the value was cached in the current cache window.
+    pub fn has_expired(&self, now: &DateTime<Local>) -> bool {
+        self.valid_until.lt(now)
+    }
+
+    /// Current age of the response, as sent in the `Age` header.
+    pub fn age(&self, now: &DateTime<Local>) -> i64 {
+        self.initial_age + (*now - self.stored_at).num_seconds().max(0)
+    }
+
+    /// Whether the expired response can be served while it's refreshed in the background.
+    pub fn can_serve_while_revalidating(&self, now: &DateTime<Local>) -> bool {
+        *now <= self.valid_until + Duration::seconds(self.stale_while_revalidate)
+    }
+
+    /// Whether the expired response can replace an upstream error.
+    pub fn can_serve_on_error(&self, now: &DateTime<Local>) -> bool {
+        *now <= self.valid_until + Duration::seconds(self.stale_if_error)
+    }
+
+    /// Whether the upstream can answer a conditional request for this response.
+    pub fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    /// Conditional headers that revalidate this response with the upstream.
+    pub fn conditional_headers(&self) -> Vec<(&str, &str)> {
+        let mut headers = Vec::new();
+        if let Some(etag) = &self.etag {
+            headers.push(("if-none-match", etag.as_str()));
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.push(("if-modified-since", last_modified.as_str()));
+        }
+        headers
+    }
+
+    /// Whether the client already has this response, according to its `If-None-Match` or, when
+    /// absent, its `If-Modified-Since` header.
+    pub fn is_not_modified(
+        &self,
+        if_none_match: Option<&str>,
+        if_modified_since: Option<&str>,
+    ) -> bool {
+        if let Some(if_none_match) = if_none_match {
+            return self
+                .etag
+                .as_deref()
+                .is_some_and(|etag| etag_matches(if_none_match, etag));
+        }
+
+        match (
+            if_modified_since.and_then(parse_http_date),
+            &self.last_modified,
+        ) {
+            (Some(since), Some(last_modified)) => {
+                parse_http_date(last_modified).is_some_and(|modified| modified <= since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the stored headers with the ones of a 304 response, as defined by RFC 9111.
+    pub fn update_headers(&mut self, updates: Vec<(String, String)>) {
+        let updates: Vec<(String, String)> = updates
+            .into_iter()
+            .filter(|(name, _)| {
+                !name.starts_with(':')
+                    && !IGNORED_UPDATES
+                        .iter()
+                        .any(|ignored| name.eq_ignore_ascii_case(ignored))
+            })
+            .collect();
+
+        self.headers.retain(|(name, _)| {
+            !updates
+                .iter()
+                .any(|(updated, _)| updated.eq_ignore_ascii_case(name))
+        });
+        self.headers.extend(updates);
+
+        self.etag = header(&self.headers, "etag").map(str::to_string);
+        self.last_modified = header(&self.headers, "last-modified").map(str::to_string);
+    }
+
+    /// 304 response for a client that already has this response.
+    pub fn not_modified(&self, now: &DateTime<Local>) -> Response {
+        let mut headers: Vec<(String, String)> = self
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                NOT_MODIFIED_HEADERS
+                    .iter()
+                    .any(|kept| name.eq_ignore_ascii_case(kept))
+            })
+            .cloned()
+            .collect();
+        headers.push(("age".to_string(), self.age(now).to_string()));
+
+        Response::new(304).with_headers(headers)
+    }
+
+    /// Transforms the CachedResponse into a Response with its current age.
+    pub fn into_response(self, now: &DateTime<Local>) -> Response {
+        let age = self.age(now);
+        let mut headers: Vec<(String, String)> = self
+            .headers
+            .into_iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("age"))
+            .collect();
+        headers.push(("age".to_string(), age.to_string()));
+
+        Response::new(self.status_code)
+            .with_headers(headers)
+            .with_body(self.body)
+    }
+}
+
+/// First value of a header, ignoring the case of its name.
+pub fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Weak comparison of the entity tags of an `If-None-Match` header with the stored one.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachedResponse;
+    use crate::freshness::Validity;
+    use chrono::{Duration, Local};
+
+    fn entry(headers: &[(&str, &str)]) -> CachedResponse {
+        let headers = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let validity = Validity {
+            valid_until: Local::now(),
+            initial_age: Duration::zero(),
+            stale_while_revalidate: Duration::seconds(30),
+            stale_if_error: Duration::seconds(60),
+        };
+        CachedResponse::new(Local::now(), &validity, 200, headers, vec![])
+    }
+
+    #[test]
+    fn stale_windows_start_at_the_expiration() {
+        let entry = entry(&[]);
+        let after = |seconds| entry.valid_until + Duration::seconds(seconds);
+
+        assert!(entry.can_serve_while_revalidating(&after(30)));
+        assert!(!entry.can_serve_while_revalidating(&after(31)));
+        assert!(entry.can_serve_on_error(&after(60)));
+        assert!(!entry.can_serve_on_error(&after(61)));
+    }
+
+    #[test]
+    fn conditional_requests_are_evaluated() {
+        let entry = entry(&[
+            ("ETag", "W/\"v1\""),
+            ("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+
+        assert!(entry.is_not_modified(Some("\"v1\""), None));
+        assert!(entry.is_not_modified(Some("\"v0\", W/\"v1\""), None));
+        assert!(entry.is_not_modified(Some("*"), None));
+        assert!(!entry.is_not_modified(Some("\"v2\""), None));
+        // If-None-Match takes precedence over If-Modified-Since.
+        assert!(!entry.is_not_modified(Some("\"v2\""), Some("Sun, 06 Nov 1994 08:49:37 GMT")));
+
+        assert!(entry.is_not_modified(None, Some("Sun, 06 Nov 1994 08:49:37 GMT")));
+        assert!(!entry.is_not_modified(None, Some("Sat, 05 Nov 1994 08:49:37 GMT")));
+        assert!(!entry.is_not_modified(None, Some("invalid")));
+        assert!(!entry.is_not_modified(None, None));
+    }
+
+    #[test]
+    fn not_modified_responses_update_the_headers() {
+        let mut entry = entry(&[
+            ("etag", "\"v1\""),
+            ("cache-control", "max-age=60"),
+            ("content-length", "5"),
+        ]);
+
+        entry.update_headers(vec![
+            (":status".to_string(), "304".to_string()),
+            ("Cache-Control".to_string(), "max-age=120".to_string()),
+            ("ETag".to_string(), "\"v2\"".to_string()),
+            ("content-length".to_string(), "0".to_string()),
+        ]);
+
+        assert_eq!(
+            entry.headers,
+            vec![
+                ("content-length".to_string(), "5".to_string()),
+                ("Cache-Control".to_string(), "max-age=120".to_string()),
+                ("ETag".to_string(), "\"v2\"".to_string()),
+            ]
+        );
+        assert_eq!(entry.etag.as_deref(), Some("\"v2\""));
+        assert_eq!(
+            entry.conditional_headers(),
+            vec![("if-none-match", "\"v2\"")]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Freshness of the stored responses, as defined by RFC 9111.
+
+use chrono::{DateTime, Duration, Local};
+
+use crate::cache_control::CacheControl;
+
+/// Configured lifetimes of the stored responses.
+pub struct Ttl {
+    /// Lifetime of the responses that don't define their own.
+    pub default: Duration,
+    /// Maximum lifetime of any response.
+    pub max: Duration,
+    /// Time a stale response can be served while it's refreshed, unless the response defines it.
+    pub stale_while_revalidate: Duration,
+    /// Time a stale response can replace an upstream error, unless the response defines it.
+    pub stale_if_error: Duration,
+}
+
+/// Until when a stored response can be served.
+pub struct Validity {
+    pub valid_until: DateTime<Local>,
+    /// Age of the response when it was received.
+    pub initial_age: Duration,
+    /// Time after the expiration during which the response is served while it's refreshed.
+    pub stale_while_revalidate: Duration,
+    /// Time after the expiration during which the response replaces upstream errors.
+    pub stale_if_error: Duration,
+}
+
+impl Ttl {
+    /// Freshness lifetime of a response. `s-maxage` takes precedence over `max-age`, which takes
+    /// precedence over the `Expires` header.
+    pub fn lifetime(
+        &self,
+        cache_control: &CacheControl,
+        expires: Option<&str>,
+        date: Option<&str>,
+        now: DateTime<Local>,
+    ) -> Duration {
+        let lifetime = match (cache_control.s_maxage.or(cache_control.max_age), expires) {
+            (Some(seconds), _) => Duration::seconds(seconds),
+            // Invalid dates, such as "0", represent a time in the past.
+            (None, Some(expires)) => match parse_http_date(expires) {
+                Some(expires) => expires - date.and_then(parse_http_date).unwrap_or(now),
+                None => Duration::zero(),
+            },
+            (None, None) => self.default,
+        };
+
+        lifetime.clamp(Duration::zero(), self.max)
+    }
+
+    /// Windows after the expiration of a response during which it can still be served stale.
+    pub fn stale_windows(&self, cache_control: &CacheControl) -> (Duration, Duration) {
+        let window = |seconds: Option<i64>, default: Duration| {
+            seconds.map(Duration::seconds).unwrap_or(default)
+        };
+
+        (
+            window(
+                cache_control.stale_while_revalidate,
+                self.stale_while_revalidate,
+            ),
+            window(cache_control.stale_if_error, self.stale_if_error),
+        )
+    }
+}
+
+/// Parses an HTTP date, such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn parse_http_date(value: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Local))
+}
+
+/// Age of the response when it was received from the upstream, taken from its `Age` header.
+pub fn initial_age(age: Option<&str>) -> Duration {
+    age.and_then(|age| age.trim().parse::<i64>().ok())
+        .map(|seconds| Duration::seconds(seconds.max(0)))
+        .unwrap_or_else(Duration::zero)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{initial_age, Ttl};
+    use crate::cache_control::CacheControl;
+    use chrono::{DateTime, Duration, Local};
+
+    fn ttl() -> Ttl {
+        Ttl {
+            default: Duration::seconds(300),
+            max: Duration::seconds(3600),
+            stale_while_revalidate: Duration::zero(),
+            stale_if_error: Duration::seconds(60),
+        }
+    }
+
+    #[test]
+    fn lifetime_follows_the_upstream_headers() {
+        let now = DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:37 GMT")
+            .unwrap()
+            .with_timezone(&Local);
+        let lifetime = |cache_control: &str, expires: Option<&str>, date: Option<&str>| {
+            ttl()
+                .lifetime(&CacheControl::parse(cache_control), expires, date, now)
+                .num_seconds()
+        };
+
+        assert_eq!(lifetime("", None, None), 300);
+        assert_eq!(lifetime("max-age=60", None, None), 60);
+        assert_eq!(lifetime("max-age=60, s-maxage=120", None, None), 120);
+        // The cap applies to every source of the lifetime.
+        assert_eq!(lifetime("max-age=86400", None, None), 3600);
+
+        let expires = Some("Sun, 06 Nov 1994 08:59:37 GMT");
+        assert_eq!(lifetime("", expires, None), 600);
+        assert_eq!(
+            lifetime("", expires, Some("Sun, 06 Nov 1994 08:54:37 GMT")),
+            300
+        );
+        assert_eq!(lifetime("max-age=60", expires, None), 60);
+        assert_eq!(lifetime("", Some("0"), None), 0);
+        assert_eq!(lifetime("", Some("Sat, 05 Nov 1994 08:49:37 GMT"), None), 0);
+    }
+
+    #[test]
+    fn stale_windows_default_to_the_configured_ones() {
+        let windows = |cache_control: &str| {
+            let (revalidate, error) = ttl().stale_windows(&CacheControl::parse(cache_control));
+            (revalidate.num_seconds(), error.num_seconds())
+        };
+
+        assert_eq!(windows(""), (0, 60));
+        assert_eq!(
+            windows("stale-while-revalidate=30, stale-if-error=0"),
+            (30, 0)
+        );
+    }
+
+    #[test]
+    fn initial_age_is_read_from_the_age_header() {
+        assert_eq!(initial_age(Some("30")), Duration::seconds(30));
+        assert_eq!(initial_age(Some("invalid")), Duration::zero());
+        assert_eq!(initial_age(None), Duration::zero());
+    }
+}
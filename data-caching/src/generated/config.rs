@@ -1,15 +1,49 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "admin_path")]
+    pub admin_path: Option<String>,
+    #[serde(alias = "admin_token")]
+    pub admin_token: Option<String>,
+    #[serde(alias = "compression_threshold")]
+    pub compression_threshold: Option<i64>,
+    #[serde(alias = "default_ttl")]
+    pub default_ttl: Option<i64>,
     #[serde(alias = "end_hour")]
-    pub end_hour: i64,
+    pub end_hour: Option<i64>,
+    #[serde(alias = "l1_ttl")]
+    pub l1_ttl: Option<i64>,
     #[serde(alias = "max_cached_values")]
     pub max_cached_values: i64,
+    #[serde(alias = "max_entry_size")]
+    pub max_entry_size: Option<i64>,
+    #[serde(alias = "max_ttl")]
+    pub max_ttl: Option<i64>,
+    #[serde(alias = "stale_if_error")]
+    pub stale_if_error: Option<i64>,
+    #[serde(alias = "stale_while_revalidate")]
+    pub stale_while_revalidate: Option<i64>,
     #[serde(alias = "start_hour")]
-    pub start_hour: i64,
+    pub start_hour: Option<i64>,
+    #[serde(alias = "storage_type")]
+    pub storage_type: Option<String>,
+    #[serde(alias = "upstream", default, deserialize_with = "pdk::serde::deserialize_service_opt")]
+    pub upstream: Option<pdk::hl::Service>,
+    #[serde(alias = "vary_headers")]
+    pub vary_headers: Option<Vec<String>>,
 }
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
+    let config: Config = serde_json::from_slice(abi.get_configuration())
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to parse configuration '{}'. Cause: {}",
+                String::from_utf8_lossy(abi.get_configuration()), err
+            )
+        })?;
+    if let Some(service) = config.upstream {
+        abi.service_create(service)?;
+    }
     abi.setup()?;
     Ok(())
 }
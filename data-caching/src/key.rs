@@ -0,0 +1,106 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Cache keys built from the parts of the request that select the response.
+
+/// Builds the key of a request from its method, its path, its normalized query and the values of
+/// the `Vary` headers.
+pub fn cache_key(method: &str, path: &str, vary: &[(&str, Option<String>)]) -> String {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+
+    let mut key = format!("{} {path}", method.to_ascii_uppercase());
+
+    let query = normalize_query(query);
+    if !query.is_empty() {
+        key.push('?');
+        key.push_str(&query);
+    }
+
+    for (name, value) in vary {
+        let value = value.as_deref().unwrap_or_default().trim();
+        key.push_str(&format!("\n{}: {value}", name.to_ascii_lowercase()));
+    }
+
+    key
+}
+
+/// Path of the request that produced a key, without its query.
+pub fn key_path(key: &str) -> &str {
+    let request_line = key.lines().next().unwrap_or_default();
+    let target = request_line
+        .split_once(' ')
+        .map_or("", |(_, target)| target);
+    target.split('?').next().unwrap_or_default()
+}
+
+/// Sorts the query parameters by name, keeping the order of repeated parameters, and drops the
+/// empty ones.
+fn normalize_query(query: &str) -> String {
+    let mut params: Vec<&str> = query.split('&').filter(|param| !param.is_empty()).collect();
+    params.sort_by_key(|param| param.split('=').next().unwrap_or_default());
+    params.join("&")
+}
+
+/// Whether the upstream `Vary` header selects the response with headers that are not part of the
+/// key, in which case the response can't be cached.
+pub fn varies_on_unkeyed_headers(vary: &str, keyed: &[String]) -> bool {
+    vary.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .any(|name| name == "*" || !keyed.iter().any(|keyed| keyed.eq_ignore_ascii_case(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_key, key_path, varies_on_unkeyed_headers};
+
+    #[test]
+    fn keys_normalize_the_query() {
+        assert_eq!(cache_key("get", "/items", &[]), "GET /items");
+        assert_eq!(
+            cache_key("GET", "/items?size=10&&page=2&page=1", &[]),
+            cache_key("GET", "/items?page=2&size=10&page=1", &[])
+        );
+        assert_eq!(
+            cache_key("GET", "/items?size=10&page=2&page=1", &[]),
+            "GET /items?page=2&page=1&size=10"
+        );
+        assert_ne!(
+            cache_key("GET", "/items", &[]),
+            cache_key("HEAD", "/items", &[])
+        );
+    }
+
+    #[test]
+    fn keys_keep_the_path() {
+        let key = cache_key(
+            "GET",
+            "/items?page=1",
+            &[("Accept-Language", Some("en".to_string()))],
+        );
+        assert_eq!(key_path(&key), "/items");
+        assert_eq!(key_path("GET /"), "/");
+    }
+
+    #[test]
+    fn keys_include_the_vary_headers() {
+        let english = cache_key(
+            "GET",
+            "/items",
+            &[("Accept-Language", Some("en".to_string()))],
+        );
+        let spanish = cache_key(
+            "GET",
+            "/items",
+            &[("Accept-Language", Some("es".to_string()))],
+        );
+        let missing = cache_key("GET", "/items", &[("Accept-Language", None)]);
+
+        assert_ne!(english, spanish);
+        assert_ne!(english, missing);
+        assert_eq!(english, "GET /items\naccept-language: en");
+
+        let keyed = vec!["accept-language".to_string()];
+        assert!(!varies_on_unkeyed_headers("Accept-Language", &keyed));
+        assert!(varies_on_unkeyed_headers("Accept-Language, Cookie", &keyed));
+        assert!(varies_on_unkeyed_headers("*", &keyed));
+    }
+}
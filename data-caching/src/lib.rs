@@ -1,46 +1,84 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
+mod cache_control;
+mod entry;
+mod freshness;
 mod generated;
+mod key;
+mod purge;
+mod refresh;
+mod store;
+
+use std::convert::TryFrom;
+use std::time;
 
 use anyhow::{anyhow, Result};
+use futures::join;
 
-use pdk::cache::{Cache, CacheBuilder, CacheError};
+use pdk::cache::CacheBuilder;
+use pdk::data_storage::{DataStorage, DataStorageBuilder};
+use pdk::hl::timer::{Clock, Timer};
 use pdk::hl::*;
+use pdk::lock::LockBuilder;
 use pdk::logger;
 
+use crate::cache_control::CacheControl;
+use crate::entry::{header, CachedResponse};
+use crate::freshness::{initial_age, Ttl, Validity};
 use crate::generated::config::Config;
+use crate::key::{cache_key, key_path, varies_on_unkeyed_headers};
+use crate::purge::{is_authorized, Index, PurgeRequest};
+use crate::refresh::{Refresh, RefreshQueue};
+use crate::store::{Limits, LocalStore, RemoteStore, ResponseStore, StoreError};
+
+use chrono::{DateTime, Days, Duration, Local, Timelike};
+
+/// Identifier for the cache, its index and the refresh locks.
+const ID: &str = "awesome-caching";
+
+/// Storage type that keeps the responses in each worker.
+const LOCAL_STORAGE: &str = "local";
+
+/// Storage type that shares the responses across the cluster.
+const REMOTE_STORAGE: &str = "remote";
+
+/// Seconds the workers keep a copy of the shared responses when no L1 TTL is configured.
+const DEFAULT_L1_TTL: i64 = 5;
+
+/// Maximum bytes of a shared response when no maximum size is configured.
+const DEFAULT_MAX_ENTRY_SIZE: i64 = 1024 * 1024;
 
-use chrono::{DateTime, Days, Local, Timelike};
-use serde::{Deserialize, Serialize};
+/// Bytes from which the shared responses are compressed when no threshold is configured.
+const DEFAULT_COMPRESSION_THRESHOLD: i64 = 1024;
+
+/// Seconds a response without freshness headers stays cached when no TTL is configured.
+const DEFAULT_TTL: i64 = 300;
+
+/// Maximum seconds a response stays cached when no maximum TTL is configured.
+const DEFAULT_MAX_TTL: i64 = 86400;
+
+/// Methods whose responses can be cached.
+const CACHEABLE_METHODS: &[&str] = &["GET", "HEAD"];
+
+/// Unsafe methods that invalidate the responses stored for their path, as defined by RFC 9111.
+const INVALIDATING_METHODS: &[&str] = &["POST", "PUT", "PATCH", "DELETE"];
+
+/// Statuses whose responses can be cached, as defined by RFC 9111.
+const CACHEABLE_STATUSES: &[u32] = &[200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
 
 /// This enum sends data from the request scope to the response scope.
 enum CachingData {
-    SaveResponse(String),
+    /// Saves the response, keeping the stale one to replace upstream errors. The flag tells if
+    /// the request carried `Authorization`.
+    SaveResponse(String, Option<CachedResponse>, bool),
+    Revalidate(String, CachedResponse, bool),
+    /// Invalidates the responses stored for a path once the unsafe request succeeds.
+    Invalidate(String),
     IgnoreCache,
 }
 
-/// This struct serializes the response in the cache.
-#[derive(Serialize, Deserialize)]
-pub struct CachedResponse {
-    valid_until: DateTime<Local>,
-    status_code: u32,
-    headers: Vec<(String, String)>,
-    body: Vec<u8>,
-}
-
-impl CachedResponse {
-    /// Given the current time check, if the value was cached in the current cache window.
-    fn has_expired(&self, now: &DateTime<Local>) -> bool {
-        self.valid_until.lt(now)
-    }
-}
-
-/// Transforms the CachedResponse into a Response
-impl From<CachedResponse> for Response {
-    fn from(response: CachedResponse) -> Self {
-        Response::new(response.status_code)
-            .with_headers(response.headers)
-            .with_body(response.body)
-    }
+/// Hours of the day when responses are cached, if the window is configured.
+fn caching_window(config: &Config) -> Option<(u32, u32)> {
+    Some((config.start_hour? as u32, config.end_hour? as u32))
 }
 
 /// Checks if the time is between the given range
@@ -81,126 +119,474 @@ fn calculate_validity(
     }
 }
 
+/// Builds the caching key from the method, path, query and configured `Vary` headers.
+fn request_key(headers_state: &RequestHeadersState, config: &Config) -> String {
+    let handler = headers_state.handler();
+    let vary: Vec<(&str, Option<String>)> = config
+        .vary_headers
+        .iter()
+        .flatten()
+        .map(|name| (name.as_str(), handler.header(name)))
+        .collect();
+
+    cache_key(&headers_state.method(), &headers_state.path(), &vary)
+}
+
 /// Defines custom request errors to handle them in an unified way
 enum CachingRequestError {
     OutsideRange,
-    CacheMiss(String),
+    NotCacheable,
+    Invalidate(String),
+    CacheMiss(String, Option<CachedResponse>),
+    Revalidate(String, CachedResponse),
     Deserialize(String, serde_json::Error),
 }
 
 /// Trys to read existing requests from the cache.
 async fn try_from_cache(
-    request_state: RequestState,
+    headers_state: &RequestHeadersState,
     config: &Config,
-    cache: &impl Cache,
-) -> Result<CachedResponse, CachingRequestError> {
-    // Await for the headers
-    let headers_state = request_state.into_headers_state().await;
+    cache: &impl ResponseStore,
+    refreshes: Option<&RefreshQueue>,
+) -> Result<Response, CachingRequestError> {
+    let method = headers_state.method();
+
+    // Unsafe methods invalidate the stored responses, even outside caching hours
+    if INVALIDATING_METHODS.contains(&method.as_str()) {
+        let path = headers_state.path();
+        let path = path.split('?').next().unwrap_or_default();
+        return Err(CachingRequestError::Invalidate(path.to_string()));
+    }
 
     // Get the time in the current timezone.
     let now = Local::now();
 
     // Check if cache should be used
-    if !check_time_in_range(now.hour(), config.start_hour as u32, config.end_hour as u32) {
-        return Err(CachingRequestError::OutsideRange);
+    if let Some((start, end)) = caching_window(config) {
+        if !check_time_in_range(now.hour(), start, end) {
+            return Err(CachingRequestError::OutsideRange);
+        }
+    }
+
+    // Only safe methods can be answered from the cache
+    if !CACHEABLE_METHODS.contains(&method.as_str()) {
+        return Err(CachingRequestError::NotCacheable);
+    }
+
+    // Respect the client directives
+    let cache_control = headers_state
+        .handler()
+        .header("cache-control")
+        .map(|value| CacheControl::parse(&value))
+        .unwrap_or_default();
+
+    if cache_control.forbids_storing() {
+        return Err(CachingRequestError::NotCacheable);
     }
 
-    // Get the request path to use as caching key
-    let path = headers_state.path();
+    // Get the caching key of the request
+    let key = request_key(headers_state, config);
+
+    // The client requires a fresh response, which can still be cached
+    if cache_control.no_cache {
+        return Err(CachingRequestError::CacheMiss(key, None));
+    }
 
     // Read the value from the cache
     let cached = cache
-        .get(path.as_str())
-        .ok_or_else(|| CachingRequestError::CacheMiss(path.clone()))?;
+        .get(key.as_str())
+        .await
+        .ok_or_else(|| CachingRequestError::CacheMiss(key.clone(), None))?;
 
     // Deserialize the retrieved data
     let deserialized: CachedResponse = serde_json::from_slice(cached.as_slice())
-        .map_err(|e| CachingRequestError::Deserialize(path.clone(), e))?;
+        .map_err(|e| CachingRequestError::Deserialize(key.clone(), e))?;
+
+    let handler = headers_state.handler();
+    let if_none_match = handler.header("if-none-match");
+    let if_modified_since = handler.header("if-modified-since");
 
     // Check the logical expiration of the cached value
     if deserialized.has_expired(&now) {
-        return Err(CachingRequestError::CacheMiss(path));
+        match refreshes {
+            // Serve the stale value while it's refreshed in the background
+            Some(refreshes) if deserialized.can_serve_while_revalidating(&now) => {
+                logger::debug!("Serving a stale response while it's refreshed.");
+                let mut headers = vary_headers(handler, config);
+                headers.extend(
+                    deserialized
+                        .conditional_headers()
+                        .into_iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string())),
+                );
+                refreshes.push(Refresh {
+                    key,
+                    method,
+                    path: headers_state.path(),
+                    headers,
+                });
+            }
+            // Revalidate the expired value, unless the client sent its own validators
+            _ if deserialized.has_validators()
+                && if_none_match.is_none()
+                && if_modified_since.is_none() =>
+            {
+                for (name, value) in deserialized.conditional_headers() {
+                    handler.set_header(name, value);
+                }
+                return Err(CachingRequestError::Revalidate(key, deserialized));
+            }
+            _ => return Err(CachingRequestError::CacheMiss(key, Some(deserialized))),
+        }
+    }
+
+    // Answer the conditional requests of the clients that already have the response
+    if deserialized.is_not_modified(if_none_match.as_deref(), if_modified_since.as_deref()) {
+        return Ok(deserialized.not_modified(&now));
+    }
+
+    Ok(deserialized.into_response(&now))
+}
+
+/// JSON response of the admin endpoint.
+fn admin_response(status_code: u32, body: serde_json::Value) -> Response {
+    Response::new(status_code)
+        .with_headers(vec![(
+            "Content-Type".to_string(),
+            "application/json".to_string(),
+        )])
+        .with_body(body.to_string())
+}
+
+/// Deletes the given keys from the cache, returning how many responses were stored.
+async fn purge(cache: &impl ResponseStore, mut keys: Vec<String>) -> usize {
+    keys.sort();
+    keys.dedup();
+
+    let mut purged = 0;
+    for key in keys {
+        if cache.delete(&key).await {
+            purged += 1;
+        }
+    }
+    purged
+}
+
+/// Admin endpoint that purges the stored responses.
+///
+/// - POST with a JSON body defining a `key`, a path `prefix` or a `surrogate_key`.
+async fn admin(
+    headers_state: RequestHeadersState,
+    token: &str,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+) -> Response {
+    if headers_state.method() != "POST" {
+        return Response::new(405).with_headers(vec![("Allow".to_string(), "POST".to_string())]);
+    }
+
+    let authorization = headers_state.handler().header("authorization");
+    if !is_authorized(authorization.as_deref(), token) {
+        logger::warn!("Rejected unauthorized purge request.");
+        return Response::new(401)
+            .with_headers(vec![("WWW-Authenticate".to_string(), "Bearer".to_string())]);
     }
 
-    Ok(deserialized)
+    // Await for the body
+    let body = headers_state.into_body_state().await.handler().body();
+    let request = match serde_json::from_slice::<PurgeRequest>(&body) {
+        Ok(request) if !request.is_empty() => request,
+        _ => {
+            return admin_response(
+                400,
+                serde_json::json!({
+                    "message": "The body must define a key, a prefix or a surrogate_key."
+                }),
+            )
+        }
+    };
+
+    let mut keys: Vec<String> = request.key.into_iter().collect();
+    if let Some(prefix) = &request.prefix {
+        keys.extend(index.take_prefix(prefix).await);
+    }
+    if let Some(surrogate_key) = &request.surrogate_key {
+        keys.extend(index.take_tag(surrogate_key).await);
+    }
+
+    let purged = purge(cache, keys).await;
+    logger::info!("Purged {purged} cached responses.");
+
+    admin_response(200, serde_json::json!({ "purged": purged }))
 }
 
 /// Wraps the policy logic to unify the error handling
 async fn request_filter(
     request_state: RequestState,
     config: &Config,
-    cache: &impl Cache,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    refreshes: Option<&RefreshQueue>,
 ) -> Flow<CachingData> {
-    match try_from_cache(request_state, config, cache).await {
-        Ok(data) => {
+    // Await for the headers
+    let headers_state = request_state.into_headers_state().await;
+
+    // Route the admin requests
+    if let (Some(admin_path), Some(token)) = (&config.admin_path, &config.admin_token) {
+        if headers_state.path().split('?').next() == Some(admin_path.as_str()) {
+            return Flow::Break(admin(headers_state, token, cache, index).await);
+        }
+    }
+
+    // Responses to authorized requests are only stored if the upstream allows it
+    let authorized = headers_state.handler().header("authorization").is_some();
+
+    match try_from_cache(&headers_state, config, cache, refreshes).await {
+        Ok(response) => {
             logger::debug!("Data retrieved from the cache.");
-            Flow::Break(data.into())
+            Flow::Break(response)
         }
         Err(CachingRequestError::OutsideRange) => {
             logger::debug!("Outside caching hours. Request will proceed to the backend.");
             Flow::Continue(CachingData::IgnoreCache)
         }
-        Err(CachingRequestError::CacheMiss(path)) => {
+        Err(CachingRequestError::NotCacheable) => {
+            logger::debug!("Request is not cacheable. Request will proceed to the backend.");
+            Flow::Continue(CachingData::IgnoreCache)
+        }
+        Err(CachingRequestError::Invalidate(path)) => {
+            logger::debug!("Unsafe request. Responses stored for {path} will be invalidated.");
+            Flow::Continue(CachingData::Invalidate(path))
+        }
+        Err(CachingRequestError::CacheMiss(key, stale)) => {
             logger::debug!("Cache Miss. Request will proceed to the backend.");
-            Flow::Continue(CachingData::SaveResponse(path))
+            Flow::Continue(CachingData::SaveResponse(key, stale, authorized))
         }
-        Err(CachingRequestError::Deserialize(path, error)) => {
+        Err(CachingRequestError::Revalidate(key, cached)) => {
+            logger::debug!("Cached response expired. Request will revalidate it with the backend.");
+            Flow::Continue(CachingData::Revalidate(key, cached, authorized))
+        }
+        Err(CachingRequestError::Deserialize(key, error)) => {
             logger::warn!("Unexpected error deserializing the cached value. Request will proceed to the backend: {error}");
-            cache.delete(path.as_str());
-            Flow::Continue(CachingData::SaveResponse(path))
+            cache.delete(key.as_str()).await;
+            Flow::Continue(CachingData::SaveResponse(key, None, authorized))
         }
     }
 }
 
 /// Define the custom response errors to handle them in an unified way
 enum CachingResponseError {
+    NotCacheable,
     Serialization(serde_json::Error),
-    Cache(CacheError),
+    Store(StoreError),
+    Client(HttpClientError),
     Time,
 }
 
+/// Logs the outcome of storing a response.
+fn log_result(result: Result<(), CachingResponseError>) {
+    match result {
+        Ok(()) => {
+            logger::debug!("Response successfully cached.")
+        }
+        Err(CachingResponseError::NotCacheable) => {
+            logger::debug!("Response is not cacheable.")
+        }
+        Err(CachingResponseError::Serialization(error)) => {
+            logger::warn!("Unexpected error serializing the response: {error}.")
+        }
+        Err(CachingResponseError::Store(error)) => {
+            logger::warn!("Unexpected saving the response to the cache: {error}.")
+        }
+        Err(CachingResponseError::Client(error)) => {
+            logger::warn!("Unexpected error refreshing the response: {error}.")
+        }
+        Err(CachingResponseError::Time) => {
+            logger::warn!("Unexpected error calculating cache expiration time.")
+        }
+    }
+}
+
+/// Calculates until when a response can be served from the cache. Fails for the responses that
+/// can't be stored.
+fn validity(
+    headers: &[(String, String)],
+    now: DateTime<Local>,
+    config: &Config,
+    ttl: &Ttl,
+    authorized: bool,
+) -> Result<Validity, CachingResponseError> {
+    // Respect the upstream directives
+    let cache_control = header(headers, "cache-control")
+        .map(CacheControl::parse)
+        .unwrap_or_default();
+
+    if cache_control.forbids_storing() || cache_control.no_cache {
+        return Err(CachingResponseError::NotCacheable);
+    }
+
+    // Responses to authorized requests are private unless the upstream marks them as shared
+    if authorized && !cache_control.allows_authorized() {
+        return Err(CachingResponseError::NotCacheable);
+    }
+
+    // The response can't be selected by headers that are not part of the key
+    if let Some(vary) = header(headers, "vary") {
+        let keyed = config.vary_headers.clone().unwrap_or_default();
+        if varies_on_unkeyed_headers(vary, &keyed) {
+            return Err(CachingResponseError::NotCacheable);
+        }
+    }
+
+    // Calculates the time of logical expiration of the cached response.
+    let lifetime = ttl.lifetime(
+        &cache_control,
+        header(headers, "expires"),
+        header(headers, "date"),
+        now,
+    );
+    let initial_age = initial_age(header(headers, "age"));
+
+    // Responses that are already stale are not stored
+    if lifetime <= initial_age {
+        return Err(CachingResponseError::NotCacheable);
+    }
+
+    let mut valid_until = now + (lifetime - initial_age);
+
+    // The caching window further limits the validity
+    if let Some((start, end)) = caching_window(config) {
+        valid_until = valid_until.min(calculate_validity(now, start, end)?);
+    }
+
+    logger::debug!("Stored response is valid until {}", valid_until);
+
+    let (stale_while_revalidate, stale_if_error) = ttl.stale_windows(&cache_control);
+
+    Ok(Validity {
+        valid_until,
+        initial_age,
+        stale_while_revalidate,
+        stale_if_error,
+    })
+}
+
+/// Serializes a response and saves it in the cache, indexing it by path and surrogate keys.
+async fn store(
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    key: &str,
+    response: &CachedResponse,
+) -> Result<(), CachingResponseError> {
+    // Serializes the object.
+    let serialized = serde_json::to_vec(response).map_err(CachingResponseError::Serialization)?;
+
+    // Saves the serialized object
+    cache
+        .save(key, serialized)
+        .await
+        .map_err(CachingResponseError::Store)?;
+
+    let surrogate_keys = header(&response.headers, "surrogate-key");
+    index.add(key, key_path(key), surrogate_keys).await;
+
+    Ok(())
+}
+
+/// Updates a stored response with the headers of the 304 response that confirmed it.
+#[allow(clippy::too_many_arguments)]
+async fn refresh_not_modified(
+    cached: &mut CachedResponse,
+    headers: Vec<(String, String)>,
+    key: &str,
+    config: &Config,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    authorized: bool,
+) -> Result<(), CachingResponseError> {
+    let now = Local::now();
+    cached.update_headers(headers);
+
+    match validity(&cached.headers, now, config, ttl, authorized) {
+        Ok(validity) => {
+            cached.refresh(now, &validity);
+            store(cache, index, key, cached).await
+        }
+        Err(error) => {
+            cache.delete(key).await;
+            Err(error)
+        }
+    }
+}
+
 /// Try to save the response to the cache.
 async fn save_to_cache(
-    response_state: ResponseState,
-    path: &str,
+    headers_state: ResponseHeadersState,
+    key: &str,
     config: &Config,
-    cache: &impl Cache,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    authorized: bool,
 ) -> Result<(), CachingResponseError> {
-    // Awaits for the headers
-    let headers_state = response_state.into_headers_state().await;
     let status_code = headers_state.status_code(); // Get the status code.
     let headers = headers_state.handler().headers(); // Get the headers.
 
+    // Only store the cacheable statuses
+    if !CACHEABLE_STATUSES.contains(&status_code) {
+        return Err(CachingResponseError::NotCacheable);
+    }
+
+    let now = Local::now();
+    let validity = validity(&headers, now, config, ttl, authorized)?;
+
     // Awaits for the body
     let body_state = headers_state.into_body_state().await;
     let body = body_state.handler().body(); // Get the body.
 
-    // Calculates the time of logical expiration of the cached response.
-    let valid_until = calculate_validity(
-        Local::now(),
-        config.start_hour as u32,
-        config.end_hour as u32,
-    )?;
+    // Creates the object that we'll store in the cache.
+    let response = CachedResponse::new(now, &validity, status_code, headers, body);
 
-    logger::debug!("Stored response is valid until {}", valid_until);
+    store(cache, index, key, &response).await
+}
 
-    // Creates the object that we'll store in the cache.
-    let response = CachedResponse {
-        valid_until,
-        status_code,
-        headers,
-        body,
-    };
+/// Serves the expired response when the upstream confirms it was not modified, refreshing it in
+/// the cache. Any other upstream response replaces the stored one.
+#[allow(clippy::too_many_arguments)]
+async fn revalidate(
+    headers_state: ResponseHeadersState,
+    key: &str,
+    mut cached: CachedResponse,
+    config: &Config,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    authorized: bool,
+) -> Result<(), CachingResponseError> {
+    if headers_state.status_code() != 304 {
+        return save_to_cache(headers_state, key, config, ttl, cache, index, authorized).await;
+    }
 
-    // Serializes the object.
-    let serialized = serde_json::to_vec(&response).map_err(CachingResponseError::Serialization)?;
+    logger::debug!("Cached response revalidated by the backend.");
 
-    // Saves the serialized object
-    cache
-        .save(path, serialized)
-        .map_err(CachingResponseError::Cache)?;
+    let headers = headers_state.handler().headers();
+    let result = refresh_not_modified(
+        &mut cached,
+        headers,
+        key,
+        config,
+        ttl,
+        cache,
+        index,
+        authorized,
+    )
+    .await;
 
-    Ok(())
+    // The client didn't send validators, so it receives the complete response
+    headers_state.send_response(cached.into_response(&Local::now()));
+
+    result
 }
 
 /// Wraps the actual policy logic to unify the error handling.
@@ -208,23 +594,195 @@ async fn response_filter(
     response_state: ResponseState,
     caching_data: RequestData<CachingData>,
     config: &Config,
-    cache: &impl Cache,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
 ) {
     // Check if we should save the response to the cache
-    if let RequestData::Continue(CachingData::SaveResponse(path)) = caching_data {
-        match save_to_cache(response_state, path.as_str(), config, cache).await {
-            Ok(()) => {
-                logger::debug!("Response successfully cached.")
-            }
-            Err(CachingResponseError::Serialization(error)) => {
-                logger::warn!("Unexpected error serializing the response: {error}.")
-            }
-            Err(CachingResponseError::Cache(error)) => {
-                logger::warn!("Unexpected saving the response to the cache: {error}.")
-            }
-            Err(CachingResponseError::Time) => {
-                logger::warn!("Unexpected error calculating cache expiration time.")
+    let (key, stale, revalidating, authorized) = match caching_data {
+        RequestData::Continue(CachingData::SaveResponse(key, stale, authorized)) => {
+            (key, stale, false, authorized)
+        }
+        RequestData::Continue(CachingData::Revalidate(key, cached, authorized)) => {
+            (key, Some(cached), true, authorized)
+        }
+        RequestData::Continue(CachingData::Invalidate(path)) => {
+            let headers_state = response_state.into_headers_state().await;
+
+            // Failed requests didn't modify the resource
+            if headers_state.status_code() < 400 {
+                let purged = purge(cache, index.take_path(&path).await).await;
+                logger::debug!("Invalidated {purged} responses stored for {path}.");
             }
+            return;
+        }
+        _ => return,
+    };
+
+    let headers_state = response_state.into_headers_state().await;
+
+    // Serve the stale response instead of the upstream error
+    let now = Local::now();
+    if headers_state.status_code() >= 500 {
+        if let Some(stale) = stale.filter(|stale| stale.can_serve_on_error(&now)) {
+            logger::debug!("Backend failed. Serving the stale response.");
+            headers_state.send_response(stale.into_response(&now));
+            return;
+        }
+        return log_result(Err(CachingResponseError::NotCacheable));
+    }
+
+    let result = match stale {
+        Some(cached) if revalidating => {
+            revalidate(
+                headers_state,
+                key.as_str(),
+                cached,
+                config,
+                ttl,
+                cache,
+                index,
+                authorized,
+            )
+            .await
+        }
+        _ => {
+            save_to_cache(
+                headers_state,
+                key.as_str(),
+                config,
+                ttl,
+                cache,
+                index,
+                authorized,
+            )
+            .await
+        }
+    };
+
+    log_result(result);
+}
+
+/// Values of the configured `Vary` headers in a request.
+fn vary_headers(handler: &dyn HeadersHandler, config: &Config) -> Vec<(String, String)> {
+    config
+        .vary_headers
+        .iter()
+        .flatten()
+        .filter_map(|name| Some((name.clone(), handler.header(name)?)))
+        .collect()
+}
+
+/// Refreshes a stale response with the upstream. The lock ensures a single worker refreshes each
+/// response at a time.
+#[allow(clippy::too_many_arguments)]
+async fn refresh_response(
+    refresh: &Refresh,
+    upstream: &Service,
+    client: &HttpClient,
+    lock: &LockBuilder,
+    config: &Config,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+) -> Result<(), CachingResponseError> {
+    // The lock expires after the request timeout so other workers can recover it.
+    let lock = lock
+        .new(format!("{ID}-{}", refresh.key))
+        .expiration(time::Duration::from_secs(20))
+        .build();
+
+    let Some(_acquired) = lock.try_lock() else {
+        logger::debug!("Response is being refreshed by another worker.");
+        return Ok(());
+    };
+
+    // Refreshes only send the authorization when it's one of the keyed headers
+    let authorized = refresh
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("authorization"));
+
+    let headers = refresh
+        .headers
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    let response = client
+        .request(upstream)
+        .path(&refresh.path)
+        .headers(headers)
+        .timeout(time::Duration::from_secs(10))
+        .send(&refresh.method)
+        .await
+        .map_err(CachingResponseError::Client)?;
+
+    let status_code = response.status_code();
+    let headers: Vec<(String, String)> = response
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    if status_code == 304 {
+        // The stored response may have been evicted in the meantime
+        let Some(mut cached) = cache
+            .get(&refresh.key)
+            .await
+            .and_then(|cached| serde_json::from_slice::<CachedResponse>(&cached).ok())
+        else {
+            return Err(CachingResponseError::NotCacheable);
+        };
+        return refresh_not_modified(
+            &mut cached,
+            headers,
+            &refresh.key,
+            config,
+            ttl,
+            cache,
+            index,
+            authorized,
+        )
+        .await;
+    }
+
+    // Errors keep the stale response until its windows elapse
+    if !CACHEABLE_STATUSES.contains(&status_code) {
+        return Err(CachingResponseError::NotCacheable);
+    }
+
+    let now = Local::now();
+    let validity = validity(&headers, now, config, ttl, authorized)?;
+    let response = CachedResponse::new(
+        now,
+        &validity,
+        status_code,
+        headers,
+        response.body().to_vec(),
+    );
+
+    store(cache, index, &refresh.key, &response).await
+}
+
+/// Periodically performs the refreshes scheduled by the requests.
+#[allow(clippy::too_many_arguments)]
+async fn refresh_loop(
+    timer: &Timer,
+    refreshes: &RefreshQueue,
+    upstream: &Service,
+    client: &HttpClient,
+    lock: &LockBuilder,
+    config: &Config,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+) {
+    while timer.next_tick().await {
+        for refresh in refreshes.take() {
+            let result =
+                refresh_response(&refresh, upstream, client, lock, config, ttl, cache, index).await;
+            log_result(result);
         }
     }
 }
@@ -235,6 +793,10 @@ async fn configure(
     launcher: Launcher,
     Configuration(bytes): Configuration,
     cache_builder: CacheBuilder,
+    store_builder: DataStorageBuilder, // Inject the storage to index the responses to purge.
+    client: HttpClient,
+    clock: Clock, // Inject the clock to refresh the stale responses in the background.
+    lock: LockBuilder, // Inject the lock to refresh each response from a single worker.
 ) -> Result<()> {
     // Deserialize the configuration
     let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
@@ -245,30 +807,154 @@ async fn configure(
         )
     })?;
 
+    // The caching window is an optional constraint defined by both hours
+    match caching_window(&config) {
+        Some((start, end)) if start > 23 || end > 23 => {
+            return Err(anyhow!("Invalid caching window {start}-{end}."))
+        }
+        None if config.start_hour.is_some() || config.end_hour.is_some() => {
+            return Err(anyhow!("Both start_hour and end_hour must be configured."))
+        }
+        _ => {}
+    }
+
+    // The admin endpoint is only exposed when it's protected
+    let admin_token = config.admin_token.as_deref().unwrap_or_default();
+    if config.admin_path.is_some() && admin_token.is_empty() {
+        return Err(anyhow!(
+            "The admin_token is required to expose the admin_path."
+        ));
+    }
+
+    let ttl = Ttl {
+        default: Duration::seconds(config.default_ttl.unwrap_or(DEFAULT_TTL)),
+        max: Duration::seconds(config.max_ttl.unwrap_or(DEFAULT_MAX_TTL)),
+        stale_while_revalidate: Duration::seconds(config.stale_while_revalidate.unwrap_or(0)),
+        stale_if_error: Duration::seconds(config.stale_if_error.unwrap_or(0)),
+    };
+
+    if [
+        ttl.default,
+        ttl.max,
+        ttl.stale_while_revalidate,
+        ttl.stale_if_error,
+    ]
+    .iter()
+    .any(|duration| *duration < Duration::zero())
+    {
+        return Err(anyhow!("The TTLs can't be negative."));
+    }
+
     // Create the cache
     let cache = cache_builder
-        .new("awesome-caching".to_string())
+        .new(ID.to_string())
         .max_entries(config.max_cached_values as usize)
         .build();
 
-    let filter = on_request(|request_state| request_filter(request_state, &config, &cache))
-        .on_response(|response_state, request_data| {
-            response_filter(response_state, request_data, &config, &cache)
-        });
+    // Surrogate keys are only indexed when the admin endpoint can purge them
+    let tags = config.admin_path.is_some();
+
+    // The index holds a path and a surrogate key entry for each cached response
+    let max_index_entries = config.max_cached_values as usize * if tags { 2 } else { 1 };
+
+    let storage_type = config.storage_type.as_deref().unwrap_or(LOCAL_STORAGE);
+    match storage_type {
+        LOCAL_STORAGE => {
+            let index = Index::new(
+                store_builder.local(format!("{ID}-index")),
+                tags,
+                max_index_entries,
+            );
+            let cache = LocalStore::new(cache);
+            launch(
+                launcher, &config, &ttl, &cache, &index, &client, clock, &lock,
+            )
+            .await
+        }
+        REMOTE_STORAGE => {
+            let l1_ttl = config.l1_ttl.unwrap_or(DEFAULT_L1_TTL);
+            let max_entry_size = config.max_entry_size.unwrap_or(DEFAULT_MAX_ENTRY_SIZE);
+            let compression_threshold = config
+                .compression_threshold
+                .unwrap_or(DEFAULT_COMPRESSION_THRESHOLD);
+            if l1_ttl < 0 || max_entry_size <= 0 || compression_threshold < 0 {
+                return Err(anyhow!("Invalid remote storage limits."));
+            }
+
+            // Shared entries outlive the responses while they can be served stale
+            let retention = ttl.max + ttl.stale_while_revalidate + ttl.stale_if_error;
+            let retention = u32::try_from(retention.num_milliseconds())
+                .map_err(|_| anyhow!("The TTLs exceed the remote storage retention."))?;
+
+            let limits = Limits {
+                max_entry_size: max_entry_size as usize,
+                compression_threshold: compression_threshold as usize,
+            };
+            let index = Index::new(
+                store_builder.remote(format!("{ID}-index"), retention),
+                tags,
+                max_index_entries,
+            );
+            let remote = store_builder.remote(ID, retention);
+            let cache = RemoteStore::new(cache, Duration::seconds(l1_ttl), remote, limits);
+            launch(
+                launcher, &config, &ttl, &cache, &index, &client, clock, &lock,
+            )
+            .await
+        }
+        _ => Err(anyhow!("Invalid storage type: {storage_type}")),
+    }
+}
+
+/// Launches the filter, along with the background refreshes when the upstream is configured.
+#[allow(clippy::too_many_arguments)]
+async fn launch(
+    launcher: Launcher,
+    config: &Config,
+    ttl: &Ttl,
+    cache: &impl ResponseStore,
+    index: &Index<impl DataStorage>,
+    client: &HttpClient,
+    clock: Clock,
+    lock: &LockBuilder,
+) -> Result<()> {
+    // Stale responses are only refreshed in the background when the upstream is configured
+    let refreshes = RefreshQueue::default();
+    let queue = config.upstream.as_ref().map(|_| &refreshes);
+
+    let filter =
+        on_request(|request_state| request_filter(request_state, config, cache, index, queue))
+            .on_response(|response_state, request_data| {
+                response_filter(response_state, request_data, config, ttl, cache, index)
+            });
+
+    let Some(upstream) = &config.upstream else {
+        launcher.launch(filter).await?;
+        return Ok(());
+    };
+
+    // The timer granularity defines how fast the stale responses are refreshed.
+    let timer = clock.period(time::Duration::from_secs(1));
+    let refresh = refresh_loop(
+        &timer, &refreshes, upstream, client, lock, config, ttl, cache, index,
+    );
+
+    // Await for both futures to progress, propagating the error of the launcher.
+    let joined = join!(launcher.launch(filter), refresh);
+    joined.0?;
 
-    launcher.launch(filter).await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use pdk_unit::{UnitHttpRequest, UnitTestBuilder};
+    use pdk_unit::{UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder};
     use serde_json::json;
+    use std::cell::Cell;
+    use std::rc::Rc;
 
     fn config() -> String {
         json!({
-            "start_hour": 0,
-            "end_hour": 23,
             "max_cached_values": 100
         })
         .to_string()
@@ -296,4 +982,450 @@ mod tests {
 
         assert_eq!(response.status_code(), 200);
     }
+
+    /// Backend that counts its calls and answers with the response built by `response`.
+    fn counting_backend(
+        response: impl Fn(&UnitHttpRequest) -> UnitHttpResponse + 'static,
+    ) -> (
+        Rc<Cell<usize>>,
+        impl Fn(UnitHttpRequest) -> UnitHttpResponse,
+    ) {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let backend = move |request: UnitHttpRequest| {
+            counter.set(counter.get() + 1);
+            response(&request)
+        };
+        (calls, backend)
+    }
+
+    #[test]
+    fn only_cacheable_methods_and_statuses_are_stored() {
+        let (calls, backend) = counting_backend(|request| match request.header(":path") {
+            Some("/error") => UnitHttpResponse::new(500),
+            _ => UnitHttpResponse::new(200),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        for _ in 0..2 {
+            tester.request(UnitHttpRequest::post().with_path("/api/resource"));
+            tester.request(UnitHttpRequest::get().with_path("/error"));
+        }
+        assert_eq!(calls.get(), 4);
+
+        // The POST responses didn't populate the GET entry.
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn no_store_and_private_are_respected() {
+        let (calls, backend) = counting_backend(|request| match request.header(":path") {
+            Some("/private") => UnitHttpResponse::new(200).with_header("Cache-Control", "private"),
+            _ => UnitHttpResponse::new(200),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/private"));
+        tester.request(UnitHttpRequest::get().with_path("/private"));
+        assert_eq!(calls.get(), 2);
+
+        let no_store = || {
+            UnitHttpRequest::get()
+                .with_path("/public")
+                .with_header("Cache-Control", "no-store")
+        };
+        tester.request(no_store());
+        tester.request(no_store());
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn authorized_responses_are_only_stored_when_shared() {
+        let (calls, backend) = counting_backend(|request| match request.header(":path") {
+            Some("/public") => UnitHttpResponse::new(200).with_header("Cache-Control", "public"),
+            Some("/shared") => {
+                UnitHttpResponse::new(200).with_header("Cache-Control", "s-maxage=60")
+            }
+            _ => UnitHttpResponse::new(200).with_header("Cache-Control", "max-age=60"),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        let authorized = |path: &str| {
+            UnitHttpRequest::get()
+                .with_path(path)
+                .with_header("Authorization", "Bearer token")
+        };
+
+        tester.request(authorized("/private"));
+        tester.request(authorized("/private"));
+        assert_eq!(calls.get(), 2);
+
+        tester.request(authorized("/public"));
+        tester.request(authorized("/public"));
+        tester.request(authorized("/shared"));
+        tester.request(authorized("/shared"));
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn keys_include_the_query_and_vary_headers() {
+        let (calls, backend) =
+            counting_backend(|_| UnitHttpResponse::new(200).with_header("Vary", "Accept-Language"));
+        let config = json!({
+            "max_cached_values": 100,
+            "vary_headers": ["Accept-Language"],
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config.to_string())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+        let request = |path: &str, language: &str| {
+            UnitHttpRequest::get()
+                .with_path(path)
+                .with_header("Accept-Language", language)
+        };
+
+        tester.request(request("/items?size=10&page=2", "en"));
+        tester.request(request("/items?page=2&size=10", "en"));
+        assert_eq!(calls.get(), 1);
+
+        tester.request(request("/items?page=2&size=10", "es"));
+        tester.request(request("/items?page=3&size=10", "en"));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn hits_report_the_age_of_the_response() {
+        let (calls, backend) =
+            counting_backend(|_| UnitHttpResponse::new(200).with_header("Age", "30"));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+
+        assert_eq!(calls.get(), 1);
+        assert_eq!(response.header("age"), Some("30"));
+    }
+
+    #[test]
+    fn stale_responses_are_not_stored() {
+        let (calls, backend) = counting_backend(|request| match request.header(":path") {
+            Some("/max-age") => {
+                UnitHttpResponse::new(200).with_header("Cache-Control", "max-age=0")
+            }
+            Some("/expires") => {
+                UnitHttpResponse::new(200).with_header("Expires", "Sun, 06 Nov 1994 08:49:37 GMT")
+            }
+            _ => UnitHttpResponse::new(200)
+                .with_header("Cache-Control", "max-age=60")
+                .with_header("Age", "120"),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        for path in ["/max-age", "/expires", "/aged"] {
+            tester.request(UnitHttpRequest::get().with_path(path));
+            tester.request(UnitHttpRequest::get().with_path(path));
+        }
+
+        assert_eq!(calls.get(), 6);
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(json!({ "max_cached_values": 100, "start_hour": 18 }).to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get());
+
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn conditional_requests_are_answered_from_the_cache() {
+        let (calls, backend) = counting_backend(|_| {
+            UnitHttpResponse::new(200)
+                .with_header("ETag", "\"v1\"")
+                .with_body("Hello")
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+        let request = |etag: &str| {
+            UnitHttpRequest::get()
+                .with_path("/api/resource")
+                .with_header("If-None-Match", etag)
+        };
+
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+
+        let response = tester.request(request("\"v1\""));
+        assert_eq!(response.status_code(), 304);
+        assert_eq!(response.header("etag"), Some("\"v1\""));
+        assert!(response.body().is_empty());
+
+        let response = tester.request(request("\"v0\""));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), b"Hello");
+
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn expired_responses_are_revalidated() {
+        let revalidations = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&revalidations);
+        let (calls, backend) = counting_backend(move |request| {
+            if request.header("if-none-match") == Some("\"v1\"") {
+                counter.set(counter.get() + 1);
+                return UnitHttpResponse::new(304).with_header("Cache-Control", "max-age=60");
+            }
+            UnitHttpResponse::new(200)
+                .with_header("ETag", "\"v1\"")
+                .with_header("Cache-Control", "max-age=1")
+                .with_body("Hello")
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        // The expired response is revalidated instead of being downloaded again.
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), b"Hello");
+        assert_eq!(response.header("cache-control"), Some("max-age=60"));
+        assert_eq!(revalidations.get(), 1);
+
+        // The revalidated response is fresh again.
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn stale_responses_are_served_while_refreshed() {
+        let refreshes = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&refreshes);
+        let upstream = move |request: UnitHttpRequest| {
+            assert_eq!(request.header(":path"), Some("/api/resource"));
+            assert_eq!(request.header("if-none-match"), Some("\"v1\""));
+            counter.set(counter.get() + 1);
+            UnitHttpResponse::new(304).with_header("Cache-Control", "max-age=60")
+        };
+        let (calls, backend) = counting_backend(|_| {
+            UnitHttpResponse::new(200)
+                .with_header("ETag", "\"v1\"")
+                .with_header("Cache-Control", "max-age=1, stale-while-revalidate=60")
+                .with_body("Hello")
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "max_cached_values": 100,
+                    "upstream": "http://backend"
+                })
+                .to_string(),
+            )
+            .with_backend(backend)
+            .with_http_upstream_from_authority("backend", upstream)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+
+        // The stale response is served without waiting for the backend.
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), b"Hello");
+        assert_eq!(calls.get(), 1);
+        assert_eq!(refreshes.get(), 0);
+
+        // The refresh happens in the background.
+        tester.tick();
+        assert_eq!(refreshes.get(), 1);
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(response.header("cache-control"), Some("max-age=60"));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn stale_responses_replace_upstream_errors() {
+        let failing = Rc::new(Cell::new(false));
+        let fail = Rc::clone(&failing);
+        let (calls, backend) = counting_backend(move |_| {
+            if fail.get() {
+                return UnitHttpResponse::new(500);
+            }
+            UnitHttpResponse::new(200)
+                .with_header("Cache-Control", "max-age=1, stale-if-error=60")
+                .with_body("Hello")
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        failing.set(true);
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), b"Hello");
+        assert_eq!(calls.get(), 2);
+
+        // Without a stale response the error reaches the client.
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/other"));
+        assert_eq!(response.status_code(), 500);
+    }
+
+    fn admin_config() -> String {
+        json!({
+            "max_cached_values": 100,
+            "admin_path": "/cache",
+            "admin_token": "secret"
+        })
+        .to_string()
+    }
+
+    fn purge_request(body: serde_json::Value) -> UnitHttpRequest {
+        UnitHttpRequest::post()
+            .with_path("/cache")
+            .with_header("Authorization", "Bearer secret")
+            .with_body(body.to_string())
+    }
+
+    #[test]
+    fn admin_endpoint_requires_the_token() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(admin_config())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::post()
+                .with_path("/cache")
+                .with_header("Authorization", "Bearer other")
+                .with_body(json!({"prefix": "/"}).to_string()),
+        );
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(response.header("www-authenticate"), Some("Bearer"));
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/cache"));
+        assert_eq!(response.status_code(), 405);
+
+        let response = tester.request(purge_request(json!({})));
+        assert_eq!(response.status_code(), 400);
+    }
+
+    #[test]
+    fn invalid_storage_types_are_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(json!({"max_cached_values": 100, "storage_type": "disk"}).to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/api/resource"));
+
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn admin_path_requires_a_token() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(json!({"max_cached_values": 100, "admin_path": "/cache"}).to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/cache"));
+
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn responses_are_purged_by_key_prefix_and_surrogate_key() {
+        let (calls, backend) = counting_backend(|request| match request.header(":path") {
+            Some("/products/1") => {
+                UnitHttpResponse::new(200).with_header("Surrogate-Key", "products p1")
+            }
+            _ => UnitHttpResponse::new(200),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(admin_config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        let paths = ["/items?page=1", "/items/1", "/products/1"];
+
+        for path in paths.iter() {
+            tester.request(UnitHttpRequest::get().with_path(*path));
+        }
+        assert_eq!(calls.get(), 3);
+
+        let response = tester.request(purge_request(json!({"key": "GET /items?page=1"})));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(response.body(), json!({"purged": 1}).to_string().as_bytes());
+        for path in paths.iter() {
+            tester.request(UnitHttpRequest::get().with_path(*path));
+        }
+        assert_eq!(calls.get(), 4);
+
+        let response = tester.request(purge_request(json!({"prefix": "/items"})));
+        assert_eq!(response.body(), json!({"purged": 2}).to_string().as_bytes());
+        for path in paths.iter() {
+            tester.request(UnitHttpRequest::get().with_path(*path));
+        }
+        assert_eq!(calls.get(), 6);
+
+        let response = tester.request(purge_request(json!({"surrogate_key": "p1"})));
+        assert_eq!(response.body(), json!({"purged": 1}).to_string().as_bytes());
+        for path in paths.iter() {
+            tester.request(UnitHttpRequest::get().with_path(*path));
+        }
+        assert_eq!(calls.get(), 7);
+    }
+
+    #[test]
+    fn unsafe_methods_invalidate_the_path() {
+        let (calls, backend) = counting_backend(|request| match request.header(":method") {
+            Some("PUT") => UnitHttpResponse::new(500),
+            _ => UnitHttpResponse::new(200),
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(backend)
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/items?page=1"));
+        tester.request(UnitHttpRequest::get().with_path("/other"));
+
+        // Failed requests don't invalidate the stored responses.
+        tester.request(UnitHttpRequest::put().with_path("/items"));
+        tester.request(UnitHttpRequest::get().with_path("/items?page=1"));
+        assert_eq!(calls.get(), 3);
+
+        tester.request(UnitHttpRequest::post().with_path("/items?page=2"));
+        tester.request(UnitHttpRequest::get().with_path("/items?page=1"));
+        tester.request(UnitHttpRequest::get().with_path("/other"));
+        assert_eq!(calls.get(), 5);
+    }
 }
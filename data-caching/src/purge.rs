@@ -0,0 +1,288 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Index of the stored responses that allows purging them by path prefix or surrogate key.
+
+use pdk::data_storage::{DataStorage, StoreMode};
+use pdk::logger;
+use serde::Deserialize;
+
+/// Maximum attempts to update an index entry modified concurrently by other workers.
+const MAX_RETRIES: u32 = 5;
+
+/// Prefix of the index entries that list the keys stored for a path.
+const PATH_PREFIX: &str = "path:";
+
+/// Prefix of the index entries that list the keys tagged with a surrogate key.
+const TAG_PREFIX: &str = "tag:";
+
+/// Maximum keys of an index entry. Paths with many query variations drop their oldest keys, which
+/// are usually expired already.
+const MAX_ENTRY_KEYS: usize = 256;
+
+/// Body of the purge requests received by the admin endpoint. Every present criterion is applied.
+#[derive(Deserialize, Debug, Default)]
+pub struct PurgeRequest {
+    /// Exact cache key, such as `GET /items?page=1`.
+    pub key: Option<String>,
+    /// Prefix of the paths whose responses are purged, such as `/items`.
+    pub prefix: Option<String>,
+    /// Surrogate key sent by the upstream in the `Surrogate-Key` header.
+    pub surrogate_key: Option<String>,
+}
+
+impl PurgeRequest {
+    /// Whether the request defines at least one criterion.
+    pub fn is_empty(&self) -> bool {
+        self.key.is_none() && self.prefix.is_none() && self.surrogate_key.is_none()
+    }
+}
+
+/// Keys of the stored responses, grouped by path and by surrogate key.
+pub struct Index<S> {
+    storage: S,
+    /// Whether the surrogate keys are indexed, which are only purged by the admin endpoint.
+    tags: bool,
+    /// Maximum entries of the index. New paths and surrogate keys drop other entries once it's
+    /// full, and the responses they listed can only be purged by key until they expire.
+    max_entries: usize,
+}
+
+impl<S: DataStorage> Index<S> {
+    pub fn new(storage: S, tags: bool, max_entries: usize) -> Self {
+        Self {
+            storage,
+            tags,
+            max_entries,
+        }
+    }
+
+    /// Registers a stored response under its path and its surrogate keys.
+    pub async fn add(&self, key: &str, path: &str, surrogate_keys: Option<&str>) {
+        self.append(&format!("{PATH_PREFIX}{path}"), key).await;
+
+        if !self.tags {
+            return;
+        }
+        for tag in surrogate_keys.unwrap_or_default().split_whitespace() {
+            self.append(&format!("{TAG_PREFIX}{tag}"), key).await;
+        }
+    }
+
+    /// Removes and returns the keys stored for a path.
+    pub async fn take_path(&self, path: &str) -> Vec<String> {
+        self.take(&format!("{PATH_PREFIX}{path}")).await
+    }
+
+    /// Removes and returns the keys stored for the paths starting with a prefix.
+    pub async fn take_prefix(&self, prefix: &str) -> Vec<String> {
+        let entries = match self.storage.get_keys().await {
+            Ok(entries) => entries,
+            Err(error) => {
+                logger::warn!("Unexpected error listing the cache index: {error}");
+                return Vec::new();
+            }
+        };
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let matches = entry
+                .strip_prefix(PATH_PREFIX)
+                .is_some_and(|path| path.starts_with(prefix));
+            if matches {
+                keys.extend(self.take(&entry).await);
+            }
+        }
+        keys
+    }
+
+    /// Removes and returns the keys tagged with a surrogate key.
+    pub async fn take_tag(&self, tag: &str) -> Vec<String> {
+        self.take(&format!("{TAG_PREFIX}{tag}")).await
+    }
+
+    /// Adds a key to an index entry, retrying when other workers modify it concurrently.
+    async fn append(&self, entry: &str, key: &str) {
+        for _ in 0..MAX_RETRIES {
+            let (mut keys, mode) = match self.storage.get::<Vec<String>>(entry).await {
+                Ok(Some((keys, version))) => (keys, StoreMode::Cas(version)),
+                Ok(None) => (Vec::new(), StoreMode::Absent),
+                Err(error) => {
+                    logger::warn!("Unexpected error reading the cache index {entry}: {error}");
+                    continue;
+                }
+            };
+
+            // Most responses are refreshed with the same surrogate keys
+            if keys.iter().any(|indexed| indexed == key) {
+                return;
+            }
+            if matches!(mode, StoreMode::Absent) {
+                self.make_room(entry).await;
+            }
+            keys.push(key.to_string());
+            if keys.len() > MAX_ENTRY_KEYS {
+                keys.drain(..keys.len() - MAX_ENTRY_KEYS);
+            }
+
+            if self.storage.store(entry, &mode, &keys).await.is_ok() {
+                return;
+            }
+        }
+
+        logger::warn!("Unable to update the cache index {entry} after {MAX_RETRIES} retries.");
+    }
+
+    /// Drops entries until a new one fits, so distinct paths and surrogate keys can't grow the
+    /// index without limit.
+    async fn make_room(&self, entry: &str) {
+        let entries = match self.storage.get_keys().await {
+            Ok(entries) => entries,
+            Err(error) => {
+                logger::warn!("Unexpected error listing the cache index: {error}");
+                return;
+            }
+        };
+
+        let excess = (entries.len() + 1).saturating_sub(self.max_entries);
+        for dropped in entries
+            .iter()
+            .filter(|dropped| *dropped != entry)
+            .take(excess)
+        {
+            logger::debug!("Cache index is full. Dropping the entry {dropped}.");
+            if let Err(error) = self.storage.delete(dropped).await {
+                logger::warn!("Unexpected error deleting the cache index {dropped}: {error}");
+            }
+        }
+    }
+
+    /// Removes an index entry, returning its keys.
+    async fn take(&self, entry: &str) -> Vec<String> {
+        let keys = match self.storage.get::<Vec<String>>(entry).await {
+            Ok(keys) => keys.map(|(keys, _)| keys).unwrap_or_default(),
+            Err(error) => {
+                logger::warn!("Unexpected error reading the cache index {entry}: {error}");
+                return Vec::new();
+            }
+        };
+
+        if let Err(error) = self.storage.delete(entry).await {
+            logger::warn!("Unexpected error deleting the cache index {entry}: {error}");
+        }
+        keys
+    }
+}
+
+/// Compares the admin credentials in constant time, so the comparison doesn't leak the token.
+pub fn is_authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(provided) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+
+    let (provided, expected) = (provided.trim().as_bytes(), token.as_bytes());
+    provided.len() == expected.len()
+        && provided
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_authorized, Index};
+    use futures::executor::block_on;
+    use pdk::data_storage::{DataStorage, DataStorageError, StoreMode};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    /// In memory storage, versioned by the number of writes.
+    #[derive(Default)]
+    struct MemoryStorage {
+        entries: RefCell<BTreeMap<String, (Value, u64)>>,
+    }
+
+    impl DataStorage for MemoryStorage {
+        async fn get_keys(&self) -> Result<Vec<String>, DataStorageError> {
+            Ok(self.entries.borrow().keys().cloned().collect())
+        }
+
+        async fn store<T: Serialize>(
+            &self,
+            key: &str,
+            mode: &StoreMode,
+            item: &T,
+        ) -> Result<(), DataStorageError> {
+            let mut entries = self.entries.borrow_mut();
+            let version = entries.get(key).map(|(_, version)| *version);
+            let allowed = match mode {
+                StoreMode::Absent => version.is_none(),
+                StoreMode::Cas(expected) => {
+                    version.map(|v| v.to_string()).as_ref() == Some(expected)
+                }
+                _ => true,
+            };
+            if !allowed {
+                return Err(DataStorageError::CasMismatch);
+            }
+            let value = serde_json::to_value(item).unwrap();
+            entries.insert(key.to_string(), (value, version.unwrap_or_default() + 1));
+            Ok(())
+        }
+
+        async fn get<T: DeserializeOwned>(
+            &self,
+            key: &str,
+        ) -> Result<Option<(T, String)>, DataStorageError> {
+            Ok(self.entries.borrow().get(key).map(|(value, version)| {
+                (
+                    serde_json::from_value(value.clone()).unwrap(),
+                    version.to_string(),
+                )
+            }))
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), DataStorageError> {
+            self.entries.borrow_mut().remove(key);
+            Ok(())
+        }
+
+        async fn delete_all(&self) -> Result<(), DataStorageError> {
+            self.entries.borrow_mut().clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn index_entries_are_bounded() {
+        let index = Index::new(MemoryStorage::default(), true, 4);
+
+        block_on(async {
+            for i in 0..10 {
+                let path = format!("/items/{i}");
+                let key = format!("GET {path}");
+                index.add(&key, &path, Some("items featured")).await;
+            }
+        });
+
+        let entries = block_on(index.storage.get_keys()).unwrap();
+        assert_eq!(entries.len(), 4);
+        // The latest response is still indexed.
+        assert_eq!(block_on(index.take_path("/items/9")), vec!["GET /items/9"]);
+        assert_eq!(
+            block_on(index.take_tag("featured")).last().unwrap(),
+            "GET /items/9"
+        );
+    }
+
+    #[test]
+    fn admin_requests_require_the_token() {
+        assert!(is_authorized(Some("Bearer secret"), "secret"));
+        assert!(!is_authorized(Some("Bearer secreT"), "secret"));
+        assert!(!is_authorized(Some("Bearer secret2"), "secret"));
+        assert!(!is_authorized(Some("Basic secret"), "secret"));
+        assert!(!is_authorized(None, "secret"));
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Background refreshes of the stale responses served with `stale-while-revalidate`.
+
+use std::cell::RefCell;
+
+/// Request that refreshes a stored response with the upstream.
+pub struct Refresh {
+    pub key: String,
+    pub method: String,
+    pub path: String,
+    /// Headers selecting the response and validators of the stored one.
+    pub headers: Vec<(String, String)>,
+}
+
+/// Refreshes pending in this worker, deduplicated by key.
+#[derive(Default)]
+pub struct RefreshQueue {
+    // Each worker is single threaded so no need for locking mechanism, as long as the mutable
+    // reference is released before the next 'await' directive.
+    pending: RefCell<Vec<Refresh>>,
+}
+
+impl RefreshQueue {
+    /// Schedules a refresh, unless the same response is already scheduled.
+    pub fn push(&self, refresh: Refresh) {
+        let mut pending = self.pending.borrow_mut();
+        if !pending.iter().any(|scheduled| scheduled.key == refresh.key) {
+            pending.push(refresh);
+        }
+    }
+
+    /// Takes all the pending refreshes.
+    pub fn take(&self) -> Vec<Refresh> {
+        self.pending.take()
+    }
+}
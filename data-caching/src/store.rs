@@ -0,0 +1,280 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Storage of the cached responses. The local store keeps them in the cache of each worker, while
+//! the remote store shares them across the cluster, with the worker cache in front as an L1.
+
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+use chrono::{Duration, Local};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use pdk::cache::{Cache, CacheError};
+use pdk::data_storage::{DataStorage, DataStorageError, StoreMode};
+use pdk::logger;
+use serde::{Deserialize, Serialize};
+
+/// Size of the expiration that prefixes the L1 entries.
+const L1_HEADER_SIZE: usize = 8;
+
+/// Errors storing a response.
+#[derive(Debug)]
+pub enum StoreError {
+    Cache(CacheError),
+    Storage(DataStorageError),
+    Compression(std::io::Error),
+    TooLarge(usize),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Cache(error) => write!(f, "{error}"),
+            StoreError::Storage(error) => write!(f, "{error}"),
+            StoreError::Compression(error) => write!(f, "Compression error: {error}"),
+            StoreError::TooLarge(size) => write!(f, "Entry of {size} bytes exceeds the limit"),
+        }
+    }
+}
+
+/// Storage of the serialized responses.
+#[allow(async_fn_in_trait)]
+pub trait ResponseStore {
+    /// Returns the stored value for the key, if present.
+    async fn get(&self, key: &str) -> Option<Vec<u8>>;
+
+    /// Stores the value, replacing the previous one.
+    async fn save(&self, key: &str, value: Vec<u8>) -> Result<(), StoreError>;
+
+    /// Removes the value, returning whether it was stored or, for the shared storage, whether
+    /// it was deleted.
+    async fn delete(&self, key: &str) -> bool;
+}
+
+/// Store that keeps the responses in the cache of each worker.
+pub struct LocalStore<C> {
+    cache: C,
+}
+
+impl<C: Cache> LocalStore<C> {
+    pub fn new(cache: C) -> Self {
+        Self { cache }
+    }
+}
+
+impl<C: Cache> ResponseStore for LocalStore<C> {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.cache.get(key)
+    }
+
+    async fn save(&self, key: &str, value: Vec<u8>) -> Result<(), StoreError> {
+        self.cache.save(key, value).map_err(StoreError::Cache)
+    }
+
+    async fn delete(&self, key: &str) -> bool {
+        self.cache.delete(key).is_some()
+    }
+}
+
+/// Bounds of the entries in the shared storage.
+pub struct Limits {
+    /// Maximum size in bytes of an entry, after compression.
+    pub max_entry_size: usize,
+    /// Size in bytes from which the entries are compressed.
+    pub compression_threshold: usize,
+}
+
+/// Format of the entries in the shared storage.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope {
+    compressed: bool,
+    data: Vec<u8>,
+}
+
+impl Envelope {
+    /// Wraps a value, compressing it when it reaches the threshold. Fails when the result
+    /// exceeds the maximum size.
+    pub fn seal(value: Vec<u8>, limits: &Limits) -> Result<Self, StoreError> {
+        let envelope = if value.len() >= limits.compression_threshold {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&value).map_err(StoreError::Compression)?;
+            let compressed = encoder.finish().map_err(StoreError::Compression)?;
+
+            // Keep the original when the compression doesn't pay off
+            if compressed.len() < value.len() {
+                Self {
+                    compressed: true,
+                    data: compressed,
+                }
+            } else {
+                Self {
+                    compressed: false,
+                    data: value,
+                }
+            }
+        } else {
+            Self {
+                compressed: false,
+                data: value,
+            }
+        };
+
+        if envelope.data.len() > limits.max_entry_size {
+            return Err(StoreError::TooLarge(envelope.data.len()));
+        }
+        Ok(envelope)
+    }
+
+    /// Unwraps the value.
+    pub fn open(self) -> Result<Vec<u8>, StoreError> {
+        if !self.compressed {
+            return Ok(self.data);
+        }
+
+        let mut value = Vec::new();
+        ZlibDecoder::new(self.data.as_slice())
+            .read_to_end(&mut value)
+            .map_err(StoreError::Compression)?;
+        Ok(value)
+    }
+}
+
+/// Store that shares the responses across the cluster, keeping recently used ones in the cache of
+/// the worker for a short time.
+pub struct RemoteStore<C, S> {
+    l1: C,
+    l1_ttl: Duration,
+    remote: S,
+    limits: Limits,
+}
+
+impl<C: Cache, S: DataStorage> RemoteStore<C, S> {
+    pub fn new(l1: C, l1_ttl: Duration, remote: S, limits: Limits) -> Self {
+        Self {
+            l1,
+            l1_ttl,
+            remote,
+            limits,
+        }
+    }
+
+    /// Copies a value to the L1 until its TTL elapses. A zero TTL disables the L1.
+    fn save_l1(&self, key: &str, value: &[u8]) {
+        if self.l1_ttl <= Duration::zero() {
+            return;
+        }
+
+        let expires_at = (Local::now() + self.l1_ttl).timestamp_millis();
+        if let Err(error) = self.l1.save(key, l1_entry(value, expires_at)) {
+            logger::debug!("Unable to copy the response to the L1 cache: {error}");
+        }
+    }
+}
+
+impl<C: Cache, S: DataStorage> ResponseStore for RemoteStore<C, S> {
+    async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let now = Local::now().timestamp_millis();
+        if let Some(value) = self.l1.get(key).and_then(|entry| l1_value(entry, now)) {
+            return Some(value);
+        }
+
+        let envelope = match self.remote.get::<Envelope>(key).await {
+            Ok(envelope) => envelope?.0,
+            Err(error) => {
+                logger::warn!("Unexpected error reading the shared cache: {error}");
+                return None;
+            }
+        };
+
+        let value = envelope.open().ok()?;
+        self.save_l1(key, &value);
+        Some(value)
+    }
+
+    async fn save(&self, key: &str, value: Vec<u8>) -> Result<(), StoreError> {
+        // Oversized responses are not stored in any layer
+        let envelope = Envelope::seal(value.clone(), &self.limits)?;
+        self.save_l1(key, &value);
+
+        self.remote
+            .store(key, &StoreMode::Always, &envelope)
+            .await
+            .map_err(StoreError::Storage)
+    }
+
+    async fn delete(&self, key: &str) -> bool {
+        let cached = self.l1.delete(key).is_some();
+
+        // The shared storage doesn't tell whether the key was stored
+        match self.remote.delete(key).await {
+            Ok(()) => true,
+            Err(error) => {
+                logger::warn!("Unexpected error deleting from the shared cache: {error}");
+                cached
+            }
+        }
+    }
+}
+
+/// L1 entry that expires at the given timestamp in milliseconds.
+fn l1_entry(value: &[u8], expires_at: i64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(L1_HEADER_SIZE + value.len());
+    entry.extend_from_slice(&expires_at.to_be_bytes());
+    entry.extend_from_slice(value);
+    entry
+}
+
+/// Value of an L1 entry, unless it expired.
+fn l1_value(mut entry: Vec<u8>, now: i64) -> Option<Vec<u8>> {
+    let expires_at = i64::from_be_bytes(entry.get(..L1_HEADER_SIZE)?.try_into().ok()?);
+    if expires_at < now {
+        return None;
+    }
+    Some(entry.split_off(L1_HEADER_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{l1_entry, l1_value, Envelope, Limits, StoreError};
+
+    fn limits() -> Limits {
+        Limits {
+            max_entry_size: 64,
+            compression_threshold: 32,
+        }
+    }
+
+    #[test]
+    fn large_entries_are_compressed() {
+        let small = b"Hello".to_vec();
+        let envelope = Envelope::seal(small.clone(), &limits()).unwrap();
+        assert!(!envelope.compressed);
+        assert_eq!(envelope.open().unwrap(), small);
+
+        let large = vec![b'a'; 1024];
+        let envelope = Envelope::seal(large.clone(), &limits()).unwrap();
+        assert!(envelope.compressed);
+        assert!(envelope.data.len() < 64);
+        assert_eq!(envelope.open().unwrap(), large);
+    }
+
+    #[test]
+    fn entries_are_bounded() {
+        let random: Vec<u8> = (0..128u32).map(|i| (i * 7919 % 251) as u8).collect();
+
+        assert!(matches!(
+            Envelope::seal(random, &limits()),
+            Err(StoreError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn l1_entries_expire() {
+        let entry = l1_entry(b"Hello", 1000);
+
+        assert_eq!(l1_value(entry.clone(), 1000), Some(b"Hello".to_vec()));
+        assert_eq!(l1_value(entry, 1001), None);
+        assert_eq!(l1_value(vec![1, 2], 0), None);
+    }
+}
@@ -131,6 +131,70 @@ async fn caching() -> anyhow::Result<()> {
     Ok(())
 }
 
+// Responses stored in the shared storage are served by every replica
+#[pdk_test]
+async fn remote_caching() -> anyhow::Result<()> {
+    let backend_config = HttpMockConfig::builder()
+        .port(80)
+        .hostname("backend")
+        .build();
+
+    let policy_config = PolicyConfig::builder()
+        .name(POLICY_NAME)
+        .configuration(serde_json::json!({
+            "max_cached_values": 10,
+            "storage_type": "remote",
+            "l1_ttl": 0,
+            "compression_threshold": 16
+        }))
+        .build();
+
+    let api_config = ApiConfig::builder()
+        .name("ingress-http")
+        .upstream(&backend_config)
+        .path("/anything/echo/")
+        .port(FLEX_PORT)
+        .policies([policy_config])
+        .build();
+
+    let flex_config = FlexConfig::builder()
+        .version("1.10.0")
+        .hostname("local-flex-remote")
+        .with_api(api_config)
+        .config_mounts([(POLICY_DIR, "policy"), (COMMON_CONFIG_DIR, "common")])
+        .build();
+
+    let composite = TestComposite::builder()
+        .with_service(flex_config)
+        .with_service(backend_config)
+        .build()
+        .await?;
+
+    let flex: Flex = composite.service()?;
+    let flex_url = flex.external_url(FLEX_PORT).unwrap();
+    let upstream: HttpMock = composite.service()?;
+    let backend_server = MockServer::connect_async(upstream.socket()).await;
+
+    // The body exceeds the compression threshold
+    let body = "Compressed value ".repeat(10);
+    let mock = backend_server
+        .mock_async(|when, then| {
+            when.path_contains("/route_1");
+            then.status(200).body(&body);
+        })
+        .await;
+
+    let response = reqwest::get(format!("{flex_url}/route_1")).await?;
+    assert_response(response, StatusCode::OK, &body).await;
+
+    // Without the L1, the response is read back from the shared storage
+    let response = reqwest::get(format!("{flex_url}/route_1")).await?;
+    assert_response(response, StatusCode::OK, &body).await;
+    mock.assert_hits(1);
+
+    Ok(())
+}
+
 async fn assert_response(
     response: reqwest::Response,
     expected_status: StatusCode,
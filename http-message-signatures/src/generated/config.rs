@@ -0,0 +1,38 @@
+use serde::Deserialize;
+#[derive(Deserialize, Clone, Debug)]
+pub struct VerificationKeys0Config {
+    #[serde(alias = "algorithm")]
+    pub algorithm: String,
+    #[serde(alias = "keyId")]
+    pub key_id: String,
+    #[serde(alias = "publicKey")]
+    pub public_key: String,
+}
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    #[serde(alias = "algorithm")]
+    pub algorithm: String,
+    #[serde(alias = "components")]
+    pub components: Option<Vec<String>>,
+    #[serde(alias = "contentDigest")]
+    pub content_digest: Option<bool>,
+    #[serde(alias = "keyId")]
+    pub key_id: String,
+    #[serde(alias = "label")]
+    pub label: Option<String>,
+    #[serde(alias = "maxAge")]
+    pub max_age: Option<i64>,
+    #[serde(alias = "privateKey")]
+    pub private_key: String,
+    #[serde(alias = "requestComponents")]
+    pub request_components: Option<Vec<String>>,
+    #[serde(alias = "verificationKeys")]
+    pub verification_keys: Option<Vec<VerificationKeys0Config>>,
+    #[serde(alias = "verifyRequests")]
+    pub verify_requests: Option<bool>,
+}
+#[pdk::hl::entrypoint_flex]
+fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
+    abi.setup()?;
+    Ok(())
+}
@@ -0,0 +1,205 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Signing and verifying keys for the algorithms of the HTTP Signature Algorithms registry
+//! supported by the policy.
+
+use anyhow::{anyhow, Result};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rand_core::OsRng;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Sha256, Sha512};
+use std::convert::TryFrom;
+
+/// Signature algorithm, identified by its name in the `alg` signature parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    RsaPssSha512,
+    RsaV15Sha256,
+    EcdsaP256Sha256,
+    Ed25519,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "rsa-pss-sha512" => Ok(Self::RsaPssSha512),
+            "rsa-v1_5-sha256" => Ok(Self::RsaV15Sha256),
+            "ecdsa-p256-sha256" => Ok(Self::EcdsaP256Sha256),
+            "ed25519" => Ok(Self::Ed25519),
+            other => Err(anyhow!("Unknown signature algorithm '{other}'.")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RsaPssSha512 => "rsa-pss-sha512",
+            Self::RsaV15Sha256 => "rsa-v1_5-sha256",
+            Self::EcdsaP256Sha256 => "ecdsa-p256-sha256",
+            Self::Ed25519 => "ed25519",
+        }
+    }
+}
+
+/// Reads an RSA private key in PKCS#8 or PKCS#1 PEM format.
+fn rsa_private_key(pem: &str) -> Result<RsaPrivateKey> {
+    RsaPrivateKey::from_pkcs8_pem(pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+        .map_err(|err| anyhow!("Failed to parse rsa private key. Cause: {err}"))
+}
+
+/// Private key used to sign messages.
+pub enum SigningKey {
+    RsaPssSha512(rsa::pss::BlindedSigningKey<Sha512>),
+    RsaV15Sha256(rsa::pkcs1v15::SigningKey<Sha256>),
+    EcdsaP256Sha256(p256::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SigningKey {
+    /// Reads the private key for the algorithm from its PEM format.
+    pub fn from_pem(algorithm: Algorithm, pem: &str) -> Result<Self> {
+        let parse_err = |err| {
+            anyhow!(
+                "Failed to parse {} private key. Cause: {err}",
+                algorithm.name()
+            )
+        };
+
+        Ok(match algorithm {
+            Algorithm::RsaPssSha512 => Self::RsaPssSha512(rsa_private_key(pem)?.into()),
+            Algorithm::RsaV15Sha256 => Self::RsaV15Sha256(rsa_private_key(pem)?.into()),
+            Algorithm::EcdsaP256Sha256 => Self::EcdsaP256Sha256(
+                p256::ecdsa::SigningKey::from_pkcs8_pem(pem).map_err(parse_err)?,
+            ),
+            Algorithm::Ed25519 => {
+                Self::Ed25519(ed25519_dalek::SigningKey::from_pkcs8_pem(pem).map_err(parse_err)?)
+            }
+        })
+    }
+
+    /// Signs the message. ECDSA signatures are encoded as the concatenation of `r` and `s`.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::RsaPssSha512(key) => key.sign_with_rng(&mut OsRng, message).to_vec(),
+            Self::RsaV15Sha256(key) => key.sign(message).to_vec(),
+            Self::EcdsaP256Sha256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+            Self::Ed25519(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+}
+
+/// Public key used to verify signed messages.
+pub enum VerifyingKey {
+    RsaPssSha512(rsa::pss::VerifyingKey<Sha512>),
+    RsaV15Sha256(rsa::pkcs1v15::VerifyingKey<Sha256>),
+    EcdsaP256Sha256(p256::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl VerifyingKey {
+    /// Reads the public key for the algorithm from its PEM format.
+    pub fn from_pem(algorithm: Algorithm, pem: &str) -> Result<Self> {
+        let parse_err = |err| {
+            anyhow!(
+                "Failed to parse {} public key. Cause: {err}",
+                algorithm.name()
+            )
+        };
+
+        Ok(match algorithm {
+            Algorithm::RsaPssSha512 => Self::RsaPssSha512(
+                RsaPublicKey::from_public_key_pem(pem)
+                    .map_err(parse_err)?
+                    .into(),
+            ),
+            Algorithm::RsaV15Sha256 => Self::RsaV15Sha256(
+                RsaPublicKey::from_public_key_pem(pem)
+                    .map_err(parse_err)?
+                    .into(),
+            ),
+            Algorithm::EcdsaP256Sha256 => Self::EcdsaP256Sha256(
+                p256::ecdsa::VerifyingKey::from_public_key_pem(pem).map_err(parse_err)?,
+            ),
+            Algorithm::Ed25519 => Self::Ed25519(
+                ed25519_dalek::VerifyingKey::from_public_key_pem(pem).map_err(parse_err)?,
+            ),
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::RsaPssSha512(_) => Algorithm::RsaPssSha512,
+            Self::RsaV15Sha256(_) => Algorithm::RsaV15Sha256,
+            Self::EcdsaP256Sha256(_) => Algorithm::EcdsaP256Sha256,
+            Self::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+
+    /// Returns true if the signature is valid for the message.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            Self::RsaPssSha512(key) => rsa::pss::Signature::try_from(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            Self::RsaV15Sha256(key) => rsa::pkcs1v15::Signature::try_from(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            Self::EcdsaP256Sha256(key) => p256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            Self::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, SigningKey, VerifyingKey};
+
+    #[test]
+    fn signatures_are_verified_for_all_algorithms() {
+        let keys = [
+            (
+                Algorithm::RsaPssSha512,
+                include_str!("../tests/resources/rsa.pem"),
+                include_str!("../tests/resources/rsa.pub.pem"),
+            ),
+            (
+                Algorithm::RsaV15Sha256,
+                include_str!("../tests/resources/rsa.pem"),
+                include_str!("../tests/resources/rsa.pub.pem"),
+            ),
+            (
+                Algorithm::EcdsaP256Sha256,
+                include_str!("../tests/resources/ecdsa-p256.pem"),
+                include_str!("../tests/resources/ecdsa-p256.pub.pem"),
+            ),
+            (
+                Algorithm::Ed25519,
+                include_str!("../tests/resources/ed25519.pem"),
+                include_str!("../tests/resources/ed25519.pub.pem"),
+            ),
+        ];
+
+        for (algorithm, private, public) in keys {
+            let signing = SigningKey::from_pem(algorithm, private).unwrap();
+            let verifying = VerifyingKey::from_pem(algorithm, public).unwrap();
+
+            let signature = signing.sign(b"signature base");
+
+            assert!(verifying.verify(b"signature base", &signature));
+            assert!(!verifying.verify(b"other base", &signature));
+        }
+    }
+
+    #[test]
+    fn keys_must_match_the_algorithm() {
+        let ed25519 = include_str!("../tests/resources/ed25519.pem");
+
+        assert!(SigningKey::from_pem(Algorithm::EcdsaP256Sha256, ed25519).is_err());
+        assert!(SigningKey::from_pem(Algorithm::RsaPssSha512, ed25519).is_err());
+        assert!(Algorithm::parse("hmac-sha256").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+mod generated;
+mod keys;
+mod message;
+mod signer;
+mod verifier;
+
+use std::time::SystemTime;
+
+use anyhow::{anyhow, Result};
+
+use pdk::hl::*;
+use pdk::logger;
+
+use crate::generated::config::Config;
+use crate::message::{Message, CONTENT_DIGEST_HEADER, SIGNATURE_HEADER, SIGNATURE_INPUT_HEADER};
+use crate::signer::Signer;
+use crate::verifier::Verifier;
+
+/// Snapshot of the components of a request or response.
+struct HttpMessage {
+    derived: Vec<(&'static str, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpMessage {
+    fn request(state: &RequestHeadersState) -> Self {
+        let scheme = state.scheme().to_lowercase();
+        let authority = state.authority().to_lowercase();
+        let target = state.path();
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), format!("?{query}")),
+            None => (target.clone(), "?".to_string()),
+        };
+
+        Self {
+            derived: vec![
+                ("@method", state.method()),
+                ("@target-uri", format!("{scheme}://{authority}{target}")),
+                ("@authority", authority),
+                ("@scheme", scheme),
+                ("@request-target", target),
+                ("@path", path),
+                ("@query", query),
+            ],
+            headers: state.handler().headers(),
+        }
+    }
+
+    fn response(status_code: u32, headers: Vec<(String, String)>) -> Self {
+        Self {
+            derived: vec![("@status", status_code.to_string())],
+            headers,
+        }
+    }
+}
+
+impl Message for HttpMessage {
+    fn derived(&self, name: &str) -> Option<String> {
+        self.derived
+            .iter()
+            .find(|(derived, _)| *derived == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn header_values(&self, name: &str) -> Vec<String> {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Verifies the signature of the request, and its body digest if it is signed.
+async fn verify_request(state: RequestHeadersState, verifier: &Verifier) -> Result<()> {
+    let header = |name: &str| {
+        state
+            .handler()
+            .header(name)
+            .ok_or_else(|| anyhow!("Missing {name} header."))
+    };
+    let signature_input = header(SIGNATURE_INPUT_HEADER)?;
+    let signature = header(SIGNATURE_HEADER)?;
+
+    let message = HttpMessage::request(&state);
+    let params = verifier.verify(&message, &signature_input, &signature, now())?;
+
+    // The signature only protects the body through its digest.
+    if params
+        .components
+        .iter()
+        .any(|component| component == CONTENT_DIGEST_HEADER)
+    {
+        let digest = header(CONTENT_DIGEST_HEADER)?;
+        let body = state.into_body_state().await.handler().body();
+
+        if !message::verify_content_digest(&digest, &body) {
+            return Err(anyhow!("Content digest does not match the body."));
+        }
+    }
+
+    Ok(())
+}
+
+async fn request_filter(state: RequestHeadersState, verifier: &Verifier) -> Flow<()> {
+    match verify_request(state, verifier).await {
+        Ok(()) => Flow::Continue(()),
+        Err(err) => {
+            logger::info!("Request signature rejected: {err}");
+            Flow::Break(Response::new(401).with_body("Invalid request signature."))
+        }
+    }
+}
+
+async fn response_filter(state: ResponseHeadersState, signer: &Signer) {
+    let status_code = state.status_code();
+
+    // Headers can only be added after reading the body in the headers-body state.
+    let state = state.into_headers_body_state().await;
+    let handler = state.handler();
+
+    if signer.content_digest() {
+        let body = if state.contains_body() {
+            handler.body()
+        } else {
+            Vec::new()
+        };
+        handler.set_header(CONTENT_DIGEST_HEADER, &message::content_digest(&body));
+    }
+
+    let message = HttpMessage::response(status_code, handler.headers());
+    match signer.sign(&message, now()) {
+        Ok((signature_input, signature)) => {
+            handler.set_header(SIGNATURE_INPUT_HEADER, &signature_input);
+            handler.set_header(SIGNATURE_HEADER, &signature);
+        }
+        Err(err) => logger::warn!("Unable to sign the response: {err}"),
+    }
+}
+
+#[entrypoint]
+async fn configure(launcher: Launcher, Configuration(bytes): Configuration) -> Result<()> {
+    let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
+        anyhow!(
+            "Failed to parse configuration '{}'. Cause: {err}",
+            String::from_utf8_lossy(&bytes),
+        )
+    })?;
+
+    let signer = Signer::from_config(&config)?;
+
+    if config.verify_requests.unwrap_or_default() {
+        let verifier = Verifier::from_config(&config)?;
+
+        let filter = on_request(|rs| request_filter(rs, &verifier))
+            .on_response(|rs| response_filter(rs, &signer));
+        launcher.launch(filter).await?;
+    } else {
+        let filter = on_response(|rs| response_filter(rs, &signer));
+        launcher.launch(filter).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pdk_unit::{
+        TraceBackend, UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder,
+    };
+    use serde_json::json;
+    use std::rc::Rc;
+
+    use crate::keys::{Algorithm, SigningKey, VerifyingKey};
+    use crate::message::{self, SignatureParams};
+    use crate::HttpMessage;
+
+    const PRIVATE_KEY: &str = include_str!("../tests/resources/ed25519.pem");
+    const PUBLIC_KEY: &str = include_str!("../tests/resources/ed25519.pub.pem");
+
+    fn config(extra: serde_json::Value) -> String {
+        let mut config = json!({
+            "algorithm": "ed25519",
+            "privateKey": PRIVATE_KEY,
+            "keyId": "gateway",
+            "verificationKeys": [
+                { "keyId": "client", "algorithm": "ed25519", "publicKey": PUBLIC_KEY }
+            ]
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        config.to_string()
+    }
+
+    fn now() -> u64 {
+        crate::now()
+    }
+
+    #[test]
+    fn response_is_signed_with_content_digest() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config(json!({})))
+            .with_backend(
+                UnitHttpResponse::new(200)
+                    .with_header("content-type", "application/json")
+                    .with_body(r#"{"hello": "world"}"#),
+            )
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get());
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            response.header("content-digest"),
+            Some("sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:")
+        );
+
+        let (label, params) =
+            message::signature_inputs(response.header("signature-input").unwrap())
+                .pop()
+                .unwrap();
+        let params = params.unwrap();
+        assert_eq!(label, "sig1");
+        assert_eq!(
+            params.components,
+            vec!["@status", "content-digest", "content-type"]
+        );
+
+        let headers = ["content-digest", "content-type"]
+            .iter()
+            .map(|name| (name.to_string(), response.header(name).unwrap().to_string()))
+            .collect();
+        let base = message::signature_base(&HttpMessage::response(200, headers), &params).unwrap();
+        let signature =
+            message::find_signature(response.header("signature").unwrap(), &label).unwrap();
+        let key = VerifyingKey::from_pem(Algorithm::Ed25519, PUBLIC_KEY).unwrap();
+        assert!(key.verify(base.as_bytes(), &signature));
+    }
+
+    /// Signs a request as a client would.
+    fn signed_request(components: &[&str], created: u64) -> UnitHttpRequest {
+        signed_request_after(components, created, &[])
+    }
+
+    /// Signs a request as a client would, after the given signatures of intermediaries.
+    fn signed_request_after(
+        components: &[&str],
+        created: u64,
+        previous: &[(&str, &str)],
+    ) -> UnitHttpRequest {
+        let request = UnitHttpRequest::post()
+            .with_path("/orders?id=1")
+            .with_header(":authority", "api.example.com")
+            .with_header(":scheme", "https")
+            .with_header("content-digest", message::content_digest(b"order"))
+            .with_body("order");
+
+        let message = HttpMessage {
+            derived: vec![
+                ("@method", "POST".to_string()),
+                ("@authority", "api.example.com".to_string()),
+                ("@path", "/orders".to_string()),
+            ],
+            headers: vec![(
+                "content-digest".to_string(),
+                message::content_digest(b"order"),
+            )],
+        };
+        let params = SignatureParams::new(
+            components.iter().map(|c| c.to_string()).collect(),
+            created,
+            "client",
+            "ed25519",
+        );
+        let base = message::signature_base(&message, &params).unwrap();
+        let signature = SigningKey::from_pem(Algorithm::Ed25519, PRIVATE_KEY)
+            .unwrap()
+            .sign(base.as_bytes());
+
+        let (mut inputs, mut signatures): (Vec<String>, Vec<String>) = previous
+            .iter()
+            .map(|(input, signature)| (input.to_string(), signature.to_string()))
+            .unzip();
+        inputs.push(format!("sig1={}", params.serialized));
+        signatures.push(message::signature_member("sig1", &signature));
+
+        request
+            .with_header("signature-input", inputs.join(", "))
+            .with_header("signature", signatures.join(", "))
+    }
+
+    #[test]
+    fn signed_request_is_verified() {
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config(json!({ "verifyRequests": true })))
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(signed_request(
+            &["@method", "@authority", "@path", "content-digest"],
+            now(),
+        ));
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(backend.next().unwrap().body(), b"order");
+    }
+
+    #[test]
+    fn any_verified_request_signature_is_accepted() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config(json!({ "verifyRequests": true })))
+            .with_entrypoint(crate::configure);
+
+        // The signature of a proxy with an unknown key precedes the one of the client.
+        let proxy = (
+            r#"proxy=("@method");created=1618884473;keyid="proxy""#,
+            "proxy=:AQID:",
+        );
+        let response = tester.request(signed_request_after(
+            &["@method", "@authority", "@path"],
+            now(),
+            &[proxy],
+        ));
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn invalid_request_signatures_are_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config(json!({ "verifyRequests": true })))
+            .with_entrypoint(crate::configure);
+
+        // Unsigned request.
+        let response = tester.request(UnitHttpRequest::get());
+        assert_eq!(response.status_code(), 401);
+
+        // The path is required but not signed.
+        let response = tester.request(signed_request(&["@method", "@authority"], now()));
+        assert_eq!(response.status_code(), 401);
+
+        // The signature is too old.
+        let response = tester.request(signed_request(
+            &["@method", "@authority", "@path"],
+            now() - 3600,
+        ));
+        assert_eq!(response.status_code(), 401);
+
+        // The body doesn't match the signed digest.
+        let response = tester.request(
+            signed_request(&["@method", "@authority", "@path", "content-digest"], now())
+                .with_body("tampered"),
+        );
+        assert_eq!(response.status_code(), 401);
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Signature base, signature parameters and `Content-Digest` as defined by RFC 9421 and RFC 9530.
+
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use sha2::{Digest, Sha256, Sha512};
+
+/// Header that carries the signature parameters.
+pub const SIGNATURE_INPUT_HEADER: &str = "signature-input";
+
+/// Header that carries the signature.
+pub const SIGNATURE_HEADER: &str = "signature";
+
+/// Header that carries the digest of the body.
+pub const CONTENT_DIGEST_HEADER: &str = "content-digest";
+
+/// Derived components that can be signed in a response.
+const RESPONSE_COMPONENTS: &[&str] = &["@status"];
+
+/// Derived components that can be signed in a request.
+const REQUEST_COMPONENTS: &[&str] = &[
+    "@method",
+    "@target-uri",
+    "@authority",
+    "@scheme",
+    "@request-target",
+    "@path",
+    "@query",
+];
+
+/// HTTP message from which the values of the covered components are read.
+pub trait Message {
+    /// Value of a derived component, such as `@status` or `@method`.
+    fn derived(&self, name: &str) -> Option<String>;
+
+    /// All the values of a header.
+    fn header_values(&self, name: &str) -> Vec<String>;
+
+    /// Value of a component. Header values are trimmed and combined, as they would be by a
+    /// proxy.
+    fn component(&self, name: &str) -> Option<String> {
+        if name.starts_with('@') {
+            return self.derived(name);
+        }
+
+        let values = self.header_values(name);
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(
+            values
+                .iter()
+                .map(|value| value.trim())
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
+/// Checks that the components can be signed in a response.
+pub fn validate_response_components(components: &[String]) -> Result<()> {
+    for component in components {
+        if component.starts_with('@') && !RESPONSE_COMPONENTS.contains(&component.as_str()) {
+            return Err(anyhow!("Unsupported response component '{component}'."));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the components can be signed in a request.
+pub fn validate_request_components(components: &[String]) -> Result<()> {
+    for component in components {
+        if component.starts_with('@') && !REQUEST_COMPONENTS.contains(&component.as_str()) {
+            return Err(anyhow!("Unsupported request component '{component}'."));
+        }
+    }
+    Ok(())
+}
+
+/// Parameters of a signature, as found in the `Signature-Input` header.
+#[derive(Debug, Default, PartialEq)]
+pub struct SignatureParams {
+    pub components: Vec<String>,
+    pub created: Option<u64>,
+    pub expires: Option<u64>,
+    pub key_id: Option<String>,
+    pub alg: Option<String>,
+    /// Serialized parameters, exactly as they take part in the signature base.
+    pub serialized: String,
+}
+
+impl SignatureParams {
+    /// Creates the parameters of a new signature.
+    pub fn new(components: Vec<String>, created: u64, key_id: &str, alg: &str) -> Self {
+        let covered = components
+            .iter()
+            .map(|component| format!("\"{component}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let serialized = format!("({covered});created={created};keyid=\"{key_id}\";alg=\"{alg}\"");
+
+        Self {
+            components,
+            created: Some(created),
+            expires: None,
+            key_id: Some(key_id.to_string()),
+            alg: Some(alg.to_string()),
+            serialized,
+        }
+    }
+
+    /// Parses the serialized inner list of components and its parameters.
+    fn parse(serialized: &str) -> Result<Self> {
+        let serialized = serialized.trim();
+        let invalid = || anyhow!("Invalid signature parameters '{serialized}'.");
+
+        let inner = serialized.strip_prefix('(').ok_or_else(invalid)?;
+        let (covered, params) = inner.split_once(')').ok_or_else(invalid)?;
+
+        let components = covered
+            .split_whitespace()
+            .map(|item| {
+                item.strip_prefix('"')
+                    .and_then(|item| item.strip_suffix('"'))
+                    .map(str::to_string)
+                    .ok_or_else(invalid)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut result = Self {
+            components,
+            serialized: serialized.to_string(),
+            ..Self::default()
+        };
+
+        for param in params.split(';').filter(|param| !param.is_empty()) {
+            let (name, value) = param.split_once('=').ok_or_else(invalid)?;
+            let string = || {
+                value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .map(str::to_string)
+                    .ok_or_else(invalid)
+            };
+            let integer = || value.parse::<u64>().map_err(|_| invalid());
+
+            match name {
+                "created" => result.created = Some(integer()?),
+                "expires" => result.expires = Some(integer()?),
+                "keyid" => result.key_id = Some(string()?),
+                "alg" => result.alg = Some(string()?),
+                // Unknown parameters are only part of the signature base.
+                _ => {}
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Builds the signature base for the covered components of the message.
+pub fn signature_base(message: &dyn Message, params: &SignatureParams) -> Result<String> {
+    let mut base = String::new();
+
+    for component in &params.components {
+        let value = message
+            .component(component)
+            .ok_or_else(|| anyhow!("Missing covered component '{component}'."))?;
+        base.push_str(&format!("\"{component}\": {value}\n"));
+    }
+
+    base.push_str(&format!("\"@signature-params\": {}", params.serialized));
+    Ok(base)
+}
+
+/// Splits a structured field dictionary into its members, ignoring the commas inside
+/// inner lists and strings.
+fn dictionary_members(header: &str) -> Vec<(&str, &str)> {
+    let mut members = Vec::new();
+    let (mut start, mut depth, mut quoted) = (0, 0, false);
+
+    for (i, c) in header.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                members.push(&header[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    members.push(&header[start..]);
+
+    members
+        .into_iter()
+        .filter_map(|member| member.split_once('='))
+        .map(|(label, value)| (label.trim(), value.trim()))
+        .collect()
+}
+
+/// Parses all the signature parameters of the `Signature-Input` header, with their labels.
+pub fn signature_inputs(header: &str) -> Vec<(String, Result<SignatureParams>)> {
+    dictionary_members(header)
+        .into_iter()
+        .map(|(label, value)| (label.to_string(), SignatureParams::parse(value)))
+        .collect()
+}
+
+/// Finds the signature with the given label in the `Signature` header.
+pub fn find_signature(header: &str, label: &str) -> Result<Vec<u8>> {
+    let (_, value) = dictionary_members(header)
+        .into_iter()
+        .find(|(name, _)| *name == label)
+        .ok_or_else(|| anyhow!("Missing signature '{label}'."))?;
+
+    let encoded = value
+        .strip_prefix(':')
+        .and_then(|value| value.strip_suffix(':'))
+        .ok_or_else(|| anyhow!("Invalid signature '{label}'."))?;
+
+    BASE64_STANDARD
+        .decode(encoded)
+        .map_err(|err| anyhow!("Invalid signature '{label}'. Cause: {err}"))
+}
+
+/// Serializes a signature as a `Signature` header member.
+pub fn signature_member(label: &str, signature: &[u8]) -> String {
+    format!("{label}=:{}:", BASE64_STANDARD.encode(signature))
+}
+
+/// Computes the `Content-Digest` header value for a body with SHA-256.
+pub fn content_digest(body: &[u8]) -> String {
+    format!("sha-256=:{}:", BASE64_STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Checks a `Content-Digest` header value against the body. At least one known digest must be
+/// present and all known digests must match.
+pub fn verify_content_digest(header: &str, body: &[u8]) -> bool {
+    let mut verified = false;
+
+    for (algorithm, value) in dictionary_members(header) {
+        let expected = match algorithm {
+            "sha-256" => Sha256::digest(body).to_vec(),
+            "sha-512" => Sha512::digest(body).to_vec(),
+            _ => continue,
+        };
+
+        let digest = value
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .and_then(|value| BASE64_STANDARD.decode(value).ok());
+        if digest.as_deref() != Some(expected.as_slice()) {
+            return false;
+        }
+        verified = true;
+    }
+
+    verified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestResponse;
+
+    impl Message for TestResponse {
+        fn derived(&self, name: &str) -> Option<String> {
+            (name == "@status").then(|| "200".to_string())
+        }
+
+        fn header_values(&self, name: &str) -> Vec<String> {
+            match name {
+                "content-type" => vec!["application/json".to_string()],
+                "cache-control" => vec![" max-age=60 ".to_string(), "must-revalidate".to_string()],
+                _ => vec![],
+            }
+        }
+    }
+
+    #[test]
+    fn signature_base_follows_rfc_9421() {
+        let params = SignatureParams::new(
+            vec![
+                "@status".to_string(),
+                "content-type".to_string(),
+                "cache-control".to_string(),
+            ],
+            1618884473,
+            "test-key",
+            "ed25519",
+        );
+
+        assert_eq!(
+            signature_base(&TestResponse, &params).unwrap(),
+            "\"@status\": 200\n\
+             \"content-type\": application/json\n\
+             \"cache-control\": max-age=60, must-revalidate\n\
+             \"@signature-params\": (\"@status\" \"content-type\" \"cache-control\");created=1618884473;keyid=\"test-key\";alg=\"ed25519\""
+        );
+
+        let missing = SignatureParams::new(vec!["x-missing".to_string()], 0, "k", "ed25519");
+        assert!(signature_base(&TestResponse, &missing).is_err());
+    }
+
+    #[test]
+    fn signature_headers_are_parsed() {
+        let header = r#"sig-a=("@method" "@path");created=1618884473;keyid="a", sig-b=("@authority" "content-digest");created=1618884475;keyid="test-key-rsa";nonce="b3k2pp5k7z""#;
+
+        let mut inputs = signature_inputs(header).into_iter();
+        let (label, _) = inputs.next().unwrap();
+        assert_eq!(label, "sig-a");
+
+        let (label, params) = inputs.next().unwrap();
+        let params = params.unwrap();
+        assert_eq!(label, "sig-b");
+        assert_eq!(params.components, vec!["@authority", "content-digest"]);
+        assert_eq!(params.created, Some(1618884475));
+        assert_eq!(params.key_id.as_deref(), Some("test-key-rsa"));
+        assert_eq!(
+            params.serialized,
+            r#"("@authority" "content-digest");created=1618884475;keyid="test-key-rsa";nonce="b3k2pp5k7z""#
+        );
+
+        assert_eq!(
+            find_signature("sig-a=:AQID:, sig-b=:BAUG:", "sig-b").unwrap(),
+            vec![4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn content_digest_follows_rfc_9530() {
+        let body = br#"{"hello": "world"}"#;
+
+        assert_eq!(
+            content_digest(body),
+            "sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:"
+        );
+        assert!(verify_content_digest(&content_digest(body), body));
+        assert!(!verify_content_digest(&content_digest(b"other"), body));
+        assert!(!verify_content_digest("md5=:AAAA:", body));
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use anyhow::Result;
+
+use pdk::logger;
+
+use crate::generated::config::Config;
+use crate::keys::{Algorithm, SigningKey};
+use crate::message::{self, Message, SignatureParams};
+
+/// Components signed by default: the status code, the body digest and its content type.
+const DEFAULT_COMPONENTS: &[&str] = &["@status", "content-digest", "content-type"];
+
+/// Label of the signature unless configured otherwise.
+const DEFAULT_LABEL: &str = "sig1";
+
+/// Signs responses with the configured key.
+pub struct Signer {
+    key: SigningKey,
+    algorithm: Algorithm,
+    key_id: String,
+    label: String,
+    components: Vec<String>,
+    content_digest: bool,
+}
+
+impl Signer {
+    /// Creates the [Signer] from a [Config].
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let algorithm = Algorithm::parse(&config.algorithm)?;
+
+        let components: Vec<String> = match &config.components {
+            Some(components) => components.iter().map(|c| c.to_lowercase()).collect(),
+            None => DEFAULT_COMPONENTS.iter().map(|c| c.to_string()).collect(),
+        };
+        message::validate_response_components(&components)?;
+
+        Ok(Self {
+            key: SigningKey::from_pem(algorithm, &config.private_key)?,
+            algorithm,
+            key_id: config.key_id.clone(),
+            label: config
+                .label
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LABEL.to_string()),
+            components,
+            content_digest: config.content_digest.unwrap_or(true),
+        })
+    }
+
+    /// Returns true if the `Content-Digest` header must be added to the responses.
+    pub fn content_digest(&self) -> bool {
+        self.content_digest
+    }
+
+    /// Signs the message. Returns the values of the `Signature-Input` and `Signature` headers.
+    pub fn sign(&self, message: &dyn Message, created: u64) -> Result<(String, String)> {
+        // Headers missing in this message can't be covered by its signature.
+        let components = self
+            .components
+            .iter()
+            .filter(|component| {
+                let present = message.component(component).is_some();
+                if !present {
+                    logger::debug!("Component {component} is not present, it won't be signed.");
+                }
+                present
+            })
+            .cloned()
+            .collect();
+
+        let params = SignatureParams::new(components, created, &self.key_id, self.algorithm.name());
+        let base = message::signature_base(message, &params)?;
+        let signature = self.key.sign(base.as_bytes());
+
+        Ok((
+            format!("{}={}", self.label, params.serialized),
+            message::signature_member(&self.label, &signature),
+        ))
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::generated::config::Config;
+use crate::keys::{Algorithm, VerifyingKey};
+use crate::message::{self, Message, SignatureParams};
+
+/// Components that request signatures must cover by default.
+const DEFAULT_COMPONENTS: &[&str] = &["@method", "@authority", "@path"];
+
+/// Maximum age in seconds of request signatures by default.
+const DEFAULT_MAX_AGE: u64 = 300;
+
+/// Tolerated clock difference in seconds with the clients.
+const CLOCK_SKEW: u64 = 60;
+
+/// Verifies the signatures of requests with the configured public keys.
+pub struct Verifier {
+    keys: HashMap<String, VerifyingKey>,
+    required_components: Vec<String>,
+    max_age: u64,
+}
+
+impl Verifier {
+    /// Creates the [Verifier] from a [Config].
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let keys = config
+            .verification_keys
+            .iter()
+            .flatten()
+            .map(|key| {
+                let algorithm = Algorithm::parse(&key.algorithm)?;
+                let verifying = VerifyingKey::from_pem(algorithm, &key.public_key)
+                    .map_err(|err| anyhow!("Verification key '{}': {err}", key.key_id))?;
+                Ok((key.key_id.clone(), verifying))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        if keys.is_empty() {
+            return Err(anyhow!("Request verification requires verification keys."));
+        }
+
+        let required_components: Vec<String> = match &config.request_components {
+            Some(components) => components.iter().map(|c| c.to_lowercase()).collect(),
+            None => DEFAULT_COMPONENTS.iter().map(|c| c.to_string()).collect(),
+        };
+        message::validate_request_components(&required_components)?;
+
+        let max_age = match config.max_age {
+            Some(max_age) if max_age < 0 => return Err(anyhow!("Invalid max age {max_age}.")),
+            Some(max_age) => max_age as u64,
+            None => DEFAULT_MAX_AGE,
+        };
+
+        Ok(Self {
+            keys,
+            required_components,
+            max_age,
+        })
+    }
+
+    /// Verifies the signatures of the message at the given time. Messages may carry several
+    /// signatures, such as the ones added by intermediaries, so the first one verified is used.
+    /// Returns its parameters.
+    pub fn verify(
+        &self,
+        message: &dyn Message,
+        signature_input: &str,
+        signature: &str,
+        now: u64,
+    ) -> Result<SignatureParams> {
+        let mut error = anyhow!("Missing signature input.");
+
+        for (label, params) in message::signature_inputs(signature_input) {
+            let verified = params
+                .and_then(|params| self.verify_signature(message, &label, params, signature, now));
+            match verified {
+                Ok(params) => return Ok(params),
+                Err(err) => error = anyhow!("Signature '{label}': {err}"),
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Verifies the signature with the given label and parameters.
+    fn verify_signature(
+        &self,
+        message: &dyn Message,
+        label: &str,
+        params: SignatureParams,
+        signature: &str,
+        now: u64,
+    ) -> Result<SignatureParams> {
+        let key_id = params
+            .key_id
+            .as_deref()
+            .ok_or_else(|| anyhow!("Missing keyid."))?;
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| anyhow!("Unknown keyid '{key_id}'."))?;
+
+        if let Some(alg) = params.alg.as_deref() {
+            if alg != key.algorithm().name() {
+                return Err(anyhow!("Algorithm '{alg}' does not match the key."));
+            }
+        }
+
+        if let Some(missing) = self
+            .required_components
+            .iter()
+            .find(|component| !params.components.contains(component))
+        {
+            return Err(anyhow!("Required component '{missing}' is not signed."));
+        }
+
+        // Signatures must be recent, to limit replays.
+        let created = params.created.ok_or_else(|| anyhow!("Missing created."))?;
+        if created > now + CLOCK_SKEW || now.saturating_sub(created) > self.max_age {
+            return Err(anyhow!("Signature created at {created} is not valid."));
+        }
+        if params.expires.is_some_and(|expires| expires < now) {
+            return Err(anyhow!("Signature expired."));
+        }
+
+        let base = message::signature_base(message, &params)?;
+        let signature = message::find_signature(signature, label)?;
+        if !key.verify(base.as_bytes(), &signature) {
+            return Err(anyhow!("Invalid signature."));
+        }
+
+        Ok(params)
+    }
+}
@@ -0,0 +1,26 @@
+#!/bin/bash
+
+# test-playground.sh - Test script for the http-message-signatures policy playground
+
+set -e
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+PROJECT_ROOT="$(dirname "$SCRIPT_DIR")"
+source "$PROJECT_ROOT/test-playground-common.sh"
+
+setup_cleanup_trap
+print_header "HTTP Message Signatures Policy Playground Test"
+check_directory
+
+start_playground
+if ! wait_for_initialization; then
+    exit 1
+fi
+
+# Test 1: Response should be signed
+print_section "Test 1: Signed response"
+execute_curl "Test 1" "$GATEWAY_URL"
+validate_status "Test 1: Signed response" "200"
+
+print_summary "HTTP Message Signatures Policy"
+exit $?
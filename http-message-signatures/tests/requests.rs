@@ -0,0 +1,123 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+
+mod common;
+
+use base64::prelude::*;
+use common::*;
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use httpmock::MockServer;
+use pdk_test::port::Port;
+use pdk_test::services::flex::{ApiConfig, Flex, FlexConfig, PolicyConfig};
+use pdk_test::services::httpmock::{HttpMock, HttpMockConfig};
+use pdk_test::{pdk_test, TestComposite};
+use sha2::{Digest, Sha256};
+
+// Flex port for the internal test network
+const FLEX_PORT: Port = 8081;
+
+// Keys used to sign the responses.
+const PRIVATE_KEY: &str = include_str!("resources/ed25519.pem");
+const PUBLIC_KEY: &str = include_str!("resources/ed25519.pub.pem");
+
+#[pdk_test]
+async fn response_is_signed() -> anyhow::Result<()> {
+    // Configure an HttpMock service
+    let upstream_config = HttpMockConfig::builder()
+        .port(80)
+        .hostname("backend")
+        .build();
+
+    // Configure a Flex service
+    let policy_config = PolicyConfig::builder()
+        .name(POLICY_NAME)
+        .configuration(serde_json::json!({
+            "algorithm": "ed25519",
+            "privateKey": PRIVATE_KEY,
+            "keyId": "gateway",
+            "components": ["@status", "content-digest"]
+        }))
+        .build();
+
+    let api_config = ApiConfig::builder()
+        .name("ingress-http")
+        .upstream(&upstream_config)
+        .path("/anything/echo/")
+        .port(FLEX_PORT)
+        .policies([policy_config])
+        .build();
+
+    let flex_config = FlexConfig::builder()
+        .version("1.10.0")
+        .hostname("local-flex")
+        .with_api(api_config)
+        .config_mounts([(POLICY_DIR, "policy"), (COMMON_CONFIG_DIR, "common")])
+        .build();
+
+    // Compose the services
+    let composite = TestComposite::builder()
+        .with_service(flex_config)
+        .with_service(upstream_config)
+        .build()
+        .await?;
+
+    // Get a handle to the Flex service
+    let flex: Flex = composite.service()?;
+
+    // Get an external URL to point the Flex service
+    let flex_url = flex.external_url(FLEX_PORT).unwrap();
+
+    // Get a handle to the HttpMock service
+    let httpmock: HttpMock = composite.service()?;
+
+    // Create a MockServer
+    let mock_server = MockServer::connect_async(httpmock.socket()).await;
+
+    // Mock a /hello request
+    mock_server
+        .mock_async(|when, then| {
+            when.path_contains("/hello");
+            then.status(202).body("World!");
+        })
+        .await;
+
+    // Perform an actual request
+    let response = reqwest::get(format!("{flex_url}/hello")).await?;
+    assert_eq!(response.status(), 202);
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_default()
+    };
+    let content_digest = header("content-digest");
+    let signature_input = header("signature-input");
+    let signature = header("signature");
+
+    // The digest covers the response body.
+    let body = response.bytes().await?;
+    assert_eq!(
+        content_digest,
+        format!("sha-256=:{}:", BASE64_STANDARD.encode(Sha256::digest(&body)))
+    );
+
+    // The signature covers the status code and the digest.
+    let params = signature_input.strip_prefix("sig1=").unwrap();
+    let base = format!(
+        "\"@status\": 202\n\"content-digest\": {content_digest}\n\"@signature-params\": {params}"
+    );
+    let signature = BASE64_STANDARD.decode(
+        signature
+            .strip_prefix("sig1=:")
+            .and_then(|signature| signature.strip_suffix(':'))
+            .unwrap(),
+    )?;
+
+    let key = VerifyingKey::from_public_key_pem(PUBLIC_KEY)?;
+    key.verify(base.as_bytes(), &Signature::from_slice(&signature)?)?;
+
+    Ok(())
+}
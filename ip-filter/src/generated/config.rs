@@ -1,15 +1,84 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
+pub struct Headers0Config {
+    #[serde(alias = "name")]
+    pub name: String,
+    #[serde(alias = "value")]
+    pub value: String,
+}
+#[derive(Deserialize, Clone, Debug)]
+pub struct RejectionConfig {
+    #[serde(alias = "bodyFormat")]
+    pub body_format: Option<String>,
+    #[serde(alias = "headers")]
+    pub headers: Option<Vec<Headers0Config>>,
+    #[serde(alias = "statusCode")]
+    pub status_code: Option<i64>,
+}
+#[derive(Deserialize, Clone, Debug)]
+pub struct Rules0Config {
+    #[serde(alias = "asnsAllowed")]
+    pub asns_allowed: Option<Vec<i64>>,
+    #[serde(alias = "asnsBlocked")]
+    pub asns_blocked: Option<Vec<i64>>,
+    #[serde(alias = "countriesAllowed")]
+    pub countries_allowed: Option<Vec<String>>,
+    #[serde(alias = "countriesBlocked")]
+    pub countries_blocked: Option<Vec<String>>,
+    #[serde(alias = "ipsAllowed")]
+    pub ips_allowed: Option<Vec<String>>,
+    #[serde(alias = "ipsBlocked")]
+    pub ips_blocked: Option<Vec<String>>,
+    #[serde(alias = "methods")]
+    pub methods: Option<Vec<String>>,
+    #[serde(alias = "path")]
+    pub path: String,
+}
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "asnHeader")]
+    pub asn_header: Option<String>,
+    #[serde(alias = "asnsAllowed")]
+    pub asns_allowed: Option<Vec<i64>>,
+    #[serde(alias = "asnsBlocked")]
+    pub asns_blocked: Option<Vec<i64>>,
+    #[serde(alias = "countryHeader")]
+    pub country_header: Option<String>,
+    #[serde(alias = "countriesAllowed")]
+    pub countries_allowed: Option<Vec<String>>,
+    #[serde(alias = "countriesBlocked")]
+    pub countries_blocked: Option<Vec<String>>,
+    #[serde(alias = "geoDatabase")]
+    pub geo_database: Option<String>,
+    #[serde(alias = "geoDatabaseFrequency")]
+    pub geo_database_frequency: Option<i64>,
+    #[serde(alias = "geoDatabaseSource", default, deserialize_with = "pdk::serde::deserialize_service_opt")]
+    pub geo_database_source: Option<pdk::hl::Service>,
     #[serde(alias = "ipHeader")]
     pub ip_header: String,
     #[serde(alias = "ipsAllowed")]
     pub ips_allowed: Option<Vec<String>>,
     #[serde(alias = "ipsBlocked")]
     pub ips_blocked: Option<Vec<String>>,
+    #[serde(alias = "rejection")]
+    pub rejection: Option<RejectionConfig>,
+    #[serde(alias = "reportOnly")]
+    pub report_only: Option<bool>,
+    #[serde(alias = "rules")]
+    pub rules: Option<Vec<Rules0Config>>,
 }
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
+    let config: Config = serde_json::from_slice(abi.get_configuration())
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to parse configuration '{}'. Cause: {}",
+                String::from_utf8_lossy(abi.get_configuration()), err
+            )
+        })?;
+    if let Some(service) = config.geo_database_source {
+        abi.service_create(service)?;
+    }
     abi.setup()?;
     Ok(())
 }
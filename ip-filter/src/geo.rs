@@ -0,0 +1,337 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use maxminddb::Reader;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use pdk::cache::Cache;
+use pdk::hl::timer::Timer;
+use pdk::hl::{HttpClient, Service};
+use pdk::lock::TryLock;
+use pdk::logger;
+
+/// Identifier for the cache and the lock to share the database version between workers.
+pub const ID: &str = "ip-filter-geo";
+
+/// Key for cache entry that keeps the [Version] of the database. The database itself is too
+/// large for the shared cache, so each worker downloads and keeps its own copy.
+const VERSION: &str = "version";
+
+/// Delay before retrying the first failed download of a version.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+/// Maximum delay between the retries of a failed download.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Version of the database announced to the workers. The digest ensures every worker loads the
+/// same content, even if the source changes between their downloads.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Version {
+    update: SystemTime,
+    digest: String,
+}
+
+/// Failed downloads of a worker, retried with an exponential backoff.
+#[derive(Default, Debug)]
+struct Backoff {
+    /// Version whose download failed, or `None` for the checks of new versions.
+    version: Option<Version>,
+    attempts: u32,
+    retry_at: Option<SystemTime>,
+}
+
+impl Backoff {
+    /// Returns true if the download of the version is not backed off.
+    fn allows(&self, version: Option<&Version>, now: SystemTime) -> bool {
+        self.version.as_ref() != version || self.retry_at.is_none_or(|retry_at| now >= retry_at)
+    }
+
+    /// Records a failed download of the version, doubling the delay of its next retry.
+    fn failed(&mut self, version: Option<&Version>, now: SystemTime) {
+        if self.version.as_ref() != version {
+            *self = Self {
+                version: version.cloned(),
+                ..Self::default()
+            };
+        }
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(MAX_BACKOFF);
+        self.attempts += 1;
+        self.retry_at = Some(now + delay);
+    }
+}
+
+/// Subset of a MaxMind record. Country and ASN databases populate different fields, a merged
+/// database populates both.
+#[derive(Deserialize)]
+struct GeoRecord<'a> {
+    #[serde(borrow)]
+    country: Option<CountryRecord<'a>>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CountryRecord<'a> {
+    iso_code: Option<&'a str>,
+}
+
+/// Country and ASN resolved for a client IP.
+#[derive(Default, Debug, PartialEq)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+/// This struct keeps in memory the parsed MaxMind database of the worker.
+#[derive(Default)]
+pub struct GeoDatabase {
+    // Each worker is single threaded so no need for locking mechanism, as long as the mutable
+    // reference is released before the next 'await' directive.
+    version: RefCell<Option<Version>>,
+    reader: RefCell<Option<Reader<Vec<u8>>>>,
+    backoff: RefCell<Backoff>,
+}
+
+impl GeoDatabase {
+    /// Creates a [GeoDatabase] from the content of a `.mmdb` file.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let database = Self::default();
+        database.update(Version::new(SystemTime::now(), &bytes), bytes)?;
+        Ok(database)
+    }
+
+    /// Replaces the database with the content of a `.mmdb` file.
+    fn update(&self, version: Version, bytes: Vec<u8>) -> Result<()> {
+        let reader = Reader::from_source(bytes)
+            .map_err(|err| anyhow!("Invalid geo database. Cause: {err}"))?;
+
+        self.reader.replace(Some(reader));
+        self.version.replace(Some(version));
+        Ok(())
+    }
+
+    /// Resolves the country and ASN of the specified ip. Unknown values are left empty.
+    pub fn lookup(&self, ip: &str) -> GeoInfo {
+        let reader = self.reader.borrow();
+        let (Some(reader), Ok(ip)) = (reader.as_ref(), ip.parse::<IpAddr>()) else {
+            return GeoInfo::default();
+        };
+
+        match reader.lookup::<GeoRecord>(ip) {
+            Ok(record) => GeoInfo {
+                country: record
+                    .country
+                    .and_then(|c| c.iso_code)
+                    .map(str::to_uppercase),
+                asn: record.autonomous_system_number,
+            },
+            Err(_) => GeoInfo::default(),
+        }
+    }
+
+    /// Version of the database loaded in the worker memory.
+    fn version(&self) -> Option<Version> {
+        self.version.borrow().clone()
+    }
+}
+
+impl Version {
+    fn new(update: SystemTime, bytes: &[u8]) -> Self {
+        Self {
+            update,
+            digest: STANDARD.encode(Sha256::digest(bytes)),
+        }
+    }
+}
+
+/// Get the announced version from the cache.
+fn announced_version(cache: &impl Cache) -> Option<Version> {
+    cache
+        .get(VERSION)
+        .and_then(|data| serde_json::from_slice::<Version>(data.as_slice()).ok())
+}
+
+/// Downloads the database from the source service. Failed downloads of the version are backed off,
+/// so an unavailable source isn't requested on every tick. Returns `None` while backed off.
+async fn download(
+    source: &Service,
+    client: &HttpClient,
+    database: &GeoDatabase,
+    version: Option<&Version>,
+) -> Result<Option<Vec<u8>>> {
+    let now = SystemTime::now();
+    if !database.backoff.borrow().allows(version, now) {
+        return Ok(None);
+    }
+
+    let result = request(source, client).await;
+    if result.is_ok() {
+        database.backoff.take();
+    } else {
+        database.backoff.borrow_mut().failed(version, now);
+    }
+    result.map(Some)
+}
+
+async fn request(source: &Service, client: &HttpClient) -> Result<Vec<u8>> {
+    let response = client
+        .request(source)
+        .timeout(Duration::from_secs(10))
+        .get()
+        .await?;
+
+    if response.status_code() != 200 {
+        return Err(anyhow!(
+            "{} - {}",
+            response.status_code(),
+            String::from_utf8_lossy(response.body())
+        ));
+    }
+
+    Ok(response.body().to_vec())
+}
+
+/// Downloads a new version of the database if it has passed enough time since the last update,
+/// and announces it to the other workers.
+async fn fetch_database(
+    source: &Service,
+    frequency: Duration,
+    client: &HttpClient,
+    cache: &impl Cache,
+    lock: &TryLock,
+    database: &GeoDatabase,
+) -> Result<()> {
+    let now = SystemTime::now();
+
+    if announced_version(cache).is_some_and(|version| now <= version.update + frequency) {
+        return Ok(()); // No update necessary.
+    }
+
+    // Acquire the lock to ensure only one worker is checking the database at a time
+    if let Some(acquired) = lock.try_lock() {
+        let Some(bytes) = download(source, client, database, None).await? else {
+            return Ok(());
+        };
+
+        // The database is validated before announcing the new version to the other workers.
+        let version = Version::new(now, &bytes);
+        database.update(version.clone(), bytes)?;
+
+        // If we lost the lock another worker announces its own version.
+        if !acquired.refresh_lock() {
+            return Err(anyhow!("Lost the lock!"));
+        }
+
+        cache.save(VERSION, serde_json::to_vec(&version)?)?;
+    }
+    Ok(())
+}
+
+/// Downloads the database to the worker memory if another worker announced a new version.
+async fn load_database(
+    source: &Service,
+    client: &HttpClient,
+    cache: &impl Cache,
+    database: &GeoDatabase,
+) -> Result<()> {
+    let Some(version) = announced_version(cache) else {
+        return Ok(());
+    };
+
+    if database.version().as_ref() == Some(&version) {
+        return Ok(());
+    }
+
+    let Some(bytes) = download(source, client, database, Some(&version)).await? else {
+        return Ok(());
+    };
+
+    // The source changed since the version was announced. Withdrawing it makes a worker announce
+    // the current content, so all of them load the same database.
+    if Version::new(version.update, &bytes) != version {
+        database
+            .backoff
+            .borrow_mut()
+            .failed(Some(&version), SystemTime::now());
+        cache.delete(VERSION);
+        return Err(anyhow!(
+            "The geo database source changed since its last version."
+        ));
+    }
+
+    database.update(version, bytes)
+}
+
+/// Periodically refreshes the database from the source service. Each worker keeps the parsed
+/// database in its memory and only its version is shared between workers.
+pub async fn fetch_loop(
+    source: &Service,
+    frequency: Duration,
+    client: &HttpClient,
+    timer: &Timer,
+    cache: &impl Cache,
+    lock: &TryLock,
+    database: &GeoDatabase,
+) {
+    while timer.next_tick().await {
+        if let Err(err) = fetch_database(source, frequency, client, cache, lock, database).await {
+            logger::warn!("Unexpected error while fetching the geo database: {err}.");
+        }
+        if let Err(err) = load_database(source, client, cache, database).await {
+            logger::warn!("Unexpected error while loading the geo database: {err}.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backoff, GeoDatabase, GeoInfo, Version, MAX_BACKOFF};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn failed_downloads_are_backed_off_per_version() {
+        let now = SystemTime::UNIX_EPOCH;
+        let announced = Version::new(now, b"database");
+        let mut backoff = Backoff::default();
+
+        backoff.failed(Some(&announced), now);
+        assert!(!backoff.allows(Some(&announced), now + Duration::from_secs(4)));
+        assert!(backoff.allows(Some(&announced), now + Duration::from_secs(5)));
+
+        // The delay doubles with each failure, up to the maximum.
+        backoff.failed(Some(&announced), now);
+        assert!(!backoff.allows(Some(&announced), now + Duration::from_secs(9)));
+        assert!(backoff.allows(Some(&announced), now + Duration::from_secs(10)));
+        for _ in 0..20 {
+            backoff.failed(Some(&announced), now);
+        }
+        assert!(backoff.allows(Some(&announced), now + MAX_BACKOFF));
+
+        // Other versions are not backed off.
+        let newer = Version::new(now + Duration::from_secs(1), b"database");
+        assert!(backoff.allows(Some(&newer), now));
+        assert!(backoff.allows(None, now));
+    }
+
+    #[test]
+    fn lookup_resolves_country_and_asn() {
+        let database =
+            GeoDatabase::from_bytes(include_bytes!("../tests/resources/geo.mmdb").to_vec())
+                .unwrap();
+
+        assert_eq!(
+            database.lookup("198.51.100.7"),
+            GeoInfo {
+                country: Some("DE".to_string()),
+                asn: Some(64501)
+            }
+        );
+        assert_eq!(database.lookup("8.8.8.8"), GeoInfo::default());
+        assert_eq!(database.lookup("not an ip"), GeoInfo::default());
+    }
+}
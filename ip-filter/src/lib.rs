@@ -1,43 +1,94 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
 mod generated;
+mod geo;
+mod rejection;
+mod rules;
+
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::join;
 
+use pdk::cache::CacheBuilder;
+use pdk::hl::timer::Clock;
 use pdk::hl::*;
-use pdk::ip_filter::IpFilter;
+use pdk::lock::LockBuilder;
+use pdk::policy_violation::PolicyViolations;
 
 use crate::generated::config::Config;
+use crate::geo::{GeoDatabase, GeoInfo};
+use crate::rejection::{BodyFormat, Rejection};
+use crate::rules::IpRules;
+
+/// Default header used to forward the resolved country to the upstream.
+const DEFAULT_COUNTRY_HEADER: &str = "x-geo-country";
+/// Default header used to forward the resolved ASN to the upstream.
+const DEFAULT_ASN_HEADER: &str = "x-geo-asn";
+/// Default frequency in seconds with which the geo database source is queried.
+const DEFAULT_GEO_DATABASE_FREQUENCY: u64 = 86400;
 
-// Apply IP filters to specific IP header
+// Apply the IP filters of the rule matching the request to specific IP header
 async fn request_filter(
     request_state: RequestState,
-    allow_filter: &Option<IpFilter>,
-    block_filter: &Option<IpFilter>,
-    ip_header: &str,
+    config: &Config,
+    rules: &IpRules,
+    geo_database: Option<&GeoDatabase>,
+    rejection: &Rejection,
+    violations: &PolicyViolations,
 ) -> Flow<()> {
     let headers = request_state.into_headers_state().await;
+    let handler = headers.handler();
+
+    let country_header = config
+        .country_header
+        .as_deref()
+        .unwrap_or(DEFAULT_COUNTRY_HEADER);
+    let asn_header = config.asn_header.as_deref().unwrap_or(DEFAULT_ASN_HEADER);
+
+    // The geo headers can only be set by the policy.
+    if geo_database.is_some() {
+        handler.remove_header(country_header);
+        handler.remove_header(asn_header);
+    }
 
-    let Some(ip) = headers.handler().header(ip_header) else {
+    let Some(ip) = handler.header(&config.ip_header) else {
         return Flow::Continue(());
     };
 
-    if let Some(filter) = block_filter {
-        if !filter.is_allowed(&ip) {
-            return Flow::Break(Response::new(403).with_body("Blocked IP!"));
-        }
-    }
+    let geo = geo_database
+        .map(|database| database.lookup(&ip))
+        .unwrap_or_default();
 
-    if let Some(filter) = allow_filter {
-        if !filter.is_allowed(&ip) {
-            return Flow::Break(Response::new(403).with_body("IP not allowed!"));
-        }
+    let lists = rules.lists_for(&headers.method(), &headers.path());
+
+    let result = lists.check(&ip, &geo);
+
+    // Forward the resolved country and ASN to the upstream.
+    let GeoInfo { country, asn } = geo;
+    if let Some(country) = country {
+        handler.set_header(country_header, &country);
+    }
+    if let Some(asn) = asn {
+        handler.set_header(asn_header, &asn.to_string());
     }
 
-    Flow::Continue(())
+    match result {
+        Ok(()) => Flow::Continue(()),
+        Err(reason) => rejection.reject(violations, reason),
+    }
 }
 
 #[entrypoint]
-async fn configure(launcher: Launcher, Configuration(bytes): Configuration) -> Result<()> {
+async fn configure(
+    launcher: Launcher,
+    Configuration(bytes): Configuration,
+    client: HttpClient,
+    cache: CacheBuilder, // Inject the cache to share the geo database version between the workers.
+    clock: Clock,        // Inject the clock to periodically refresh the geo database.
+    lock: LockBuilder,   // Inject the lock to download the geo database from a single worker.
+    violations: PolicyViolations,
+) -> Result<()> {
     let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
         anyhow!(
             "Failed to parse configuration '{}'. Cause: {}",
@@ -46,37 +97,95 @@ async fn configure(launcher: Launcher, Configuration(bytes): Configuration) -> R
         )
     })?;
 
-    // Create allow filter if ipsAllowed is configured
-    let allow_filter = match &config.ips_allowed {
-        Some(ips) if !ips.is_empty() => {
-            let ip_values: Vec<&str> = ips.iter().map(|s| s.as_str()).collect();
-            Some(IpFilter::allow(&ip_values)?)
+    // Create the per path and method rules, falling back to the global lists
+    let rules = IpRules::from_config(&config)?;
+
+    // Response for rejected requests, the reason is sent as plain text unless configured otherwise
+    let rejection = Rejection::from_config(
+        config.rejection.as_ref(),
+        config.report_only.unwrap_or_default(),
+        BodyFormat::Text,
+    )?;
+
+    // Load the geo database shipped with the configuration, or prepare an empty one to be
+    // filled from the configured source.
+    let geo_database = match (&config.geo_database, &config.geo_database_source) {
+        (Some(encoded), _) => Some(GeoDatabase::from_bytes(STANDARD.decode(encoded.trim())?)?),
+        (None, Some(_)) => Some(GeoDatabase::default()),
+        (None, None) if rules.uses_geo() => {
+            return Err(anyhow!(
+                "Country and ASN lists require either geoDatabase or geoDatabaseSource."
+            ))
         }
-        _ => None,
+        (None, None) => None,
     };
 
-    // Create block filter if ipsBlocked is configured (IPs that match are blocked)
-    let block_filter = match &config.ips_blocked {
-        Some(ips) if !ips.is_empty() => {
-            let ip_values: Vec<&str> = ips.iter().map(|s| s.as_str()).collect();
-            Some(IpFilter::block(&ip_values)?)
-        }
-        _ => None,
+    // Create filter with the IP rules and header names
+    let filter = on_request(|rs| {
+        request_filter(
+            rs,
+            &config,
+            &rules,
+            geo_database.as_ref(),
+            &rejection,
+            &violations,
+        )
+    });
+
+    let (Some(database), None, Some(source)) = (
+        &geo_database,
+        &config.geo_database,
+        &config.geo_database_source,
+    ) else {
+        launcher.launch(filter).await?;
+        return Ok(());
     };
 
-    // Create filter with both IP filters and header name
-    let filter =
-        on_request(|rs| request_filter(rs, &allow_filter, &block_filter, &config.ip_header));
+    let frequency = config
+        .geo_database_frequency
+        .map(|seconds| seconds as u64)
+        .unwrap_or(DEFAULT_GEO_DATABASE_FREQUENCY);
+
+    // The timer granularity defines how fast a new database version is propagated between workers.
+    let timer = clock.period(Duration::from_secs(1));
+
+    // Cache to share the version of the geo database between workers.
+    let cache = cache.new(geo::ID.to_string()).build();
 
-    launcher.launch(filter).await?;
+    // The lock expires after the download timeout so other workers can recover it.
+    let lock = lock
+        .new(geo::ID.to_string())
+        .expiration(Duration::from_secs(20))
+        .build();
+
+    let fetch = geo::fetch_loop(
+        source,
+        Duration::from_secs(frequency),
+        &client,
+        &timer,
+        &cache,
+        &lock,
+        database,
+    );
+
+    // Await for both futures to progress, propagating the error of the launcher.
+    let joined = join!(launcher.launch(filter), fetch);
+    joined.0?;
 
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use pdk_unit::{UnitHttpRequest, UnitTestBuilder};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use pdk_unit::{
+        TraceBackend, UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder,
+    };
     use serde_json::json;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const GEO_DATABASE: &[u8] = include_bytes!("../tests/resources/geo.mmdb");
 
     #[test]
     fn blocked_ip_is_rejected() {
@@ -148,4 +257,239 @@ mod tests {
 
         assert_eq!(response.status_code(), 200);
     }
+
+    #[test]
+    fn path_rule_overrides_global_lists() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "rules": [
+                        {
+                            "path": "/admin/**",
+                            "ipsAllowed": ["10.0.0.0/8"]
+                        }
+                    ]
+                })
+                .to_string(),
+            )
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_path("/admin/users")
+                .with_header("x-forwarded-for", "192.168.1.1"),
+        );
+        assert_eq!(response.status_code(), 403);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_path("/admin/users")
+                .with_header("x-forwarded-for", "10.0.0.1"),
+        );
+        assert_eq!(response.status_code(), 200);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_path("/public")
+                .with_header("x-forwarded-for", "192.168.1.1"),
+        );
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn rule_only_applies_to_configured_methods() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "rules": [
+                        {
+                            "path": "/orders/*",
+                            "methods": ["DELETE"],
+                            "ipsBlocked": ["192.168.1.0/24"]
+                        }
+                    ]
+                })
+                .to_string(),
+            )
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::delete()
+                .with_path("/orders/42")
+                .with_header("x-forwarded-for", "192.168.1.1"),
+        );
+        assert_eq!(response.status_code(), 403);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_path("/orders/42")
+                .with_header("x-forwarded-for", "192.168.1.1"),
+        );
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn country_lists_use_the_shipped_database() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "geoDatabase": STANDARD.encode(GEO_DATABASE),
+                    "countriesBlocked": ["de"]
+                })
+                .to_string(),
+            )
+            .with_entrypoint(crate::configure);
+
+        // 198.51.100.0/24 resolves to DE in the test database.
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "198.51.100.7"));
+        assert_eq!(response.status_code(), 403);
+
+        // 192.0.2.0/24 resolves to GB in the test database.
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.0.2.7"));
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn resolved_country_and_asn_are_forwarded() {
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "geoDatabase": STANDARD.encode(GEO_DATABASE),
+                    "asnsAllowed": [64500],
+                    "countryHeader": "x-country"
+                })
+                .to_string(),
+            )
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_header("x-forwarded-for", "192.0.2.7")
+                .with_header("x-country", "US"),
+        );
+        assert_eq!(response.status_code(), 200);
+
+        let upstream_request = backend.next().unwrap();
+        assert_eq!(upstream_request.header("x-country"), Some("GB"));
+        assert_eq!(upstream_request.header("x-geo-asn"), Some("64500"));
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "203.0.113.7"));
+        assert_eq!(response.status_code(), 403);
+    }
+
+    #[test]
+    fn database_is_fetched_from_source() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "geoDatabaseSource": "http://geo",
+                    "countriesAllowed": ["GB"]
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("geo", |_| {
+                UnitHttpResponse::new(200).with_body(GEO_DATABASE)
+            })
+            .with_entrypoint(crate::configure);
+
+        // Countries are unknown until the database is fetched.
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.0.2.7"));
+        assert_eq!(response.status_code(), 403);
+
+        tester.tick();
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.0.2.7"));
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn unavailable_source_is_backed_off() {
+        let requests = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&requests);
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "geoDatabaseSource": "http://geo",
+                    "countriesAllowed": ["GB"]
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("geo", move |_| {
+                counter.set(counter.get() + 1);
+                UnitHttpResponse::new(503)
+            })
+            .with_entrypoint(crate::configure);
+
+        for _ in 0..3 {
+            tester.tick();
+        }
+
+        assert_eq!(requests.get(), 1);
+    }
+
+    #[test]
+    fn rejection_response_is_configurable() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "ipsBlocked": ["192.168.1.1"],
+                    "rejection": {
+                        "statusCode": 401,
+                        "bodyFormat": "problem+json",
+                        "headers": [{ "name": "x-rejected-by", "value": "ip-filter" }]
+                    }
+                })
+                .to_string(),
+            )
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.168.1.1"));
+
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(response.header("x-rejected-by"), Some("ip-filter"));
+        assert_eq!(
+            response.header("content-type"),
+            Some("application/problem+json")
+        );
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["status"], 401);
+        assert_eq!(body["detail"], "Blocked IP!");
+    }
+
+    #[test]
+    fn report_only_mode_does_not_block() {
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "ipHeader": "x-forwarded-for",
+                    "ipsBlocked": ["192.168.1.1"],
+                    "reportOnly": true
+                })
+                .to_string(),
+            )
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        let response =
+            tester.request(UnitHttpRequest::get().with_header("x-forwarded-for", "192.168.1.1"));
+
+        assert_eq!(response.status_code(), 200);
+        assert!(backend.next().unwrap().violation().is_some());
+    }
 }
@@ -0,0 +1,134 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use anyhow::{anyhow, Result};
+use serde_json::json;
+
+use pdk::hl::{Flow, Response};
+use pdk::logger;
+use pdk::policy_violation::PolicyViolations;
+
+use crate::generated::config::RejectionConfig;
+
+/// Format of the body of a rejection response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyFormat {
+    /// No body.
+    Empty,
+    /// The rejection reason as plain text.
+    Text,
+    /// `{"error": <reason>}` as `application/json`.
+    Json,
+    /// RFC 9457 problem details as `application/problem+json`.
+    ProblemJson,
+}
+
+impl BodyFormat {
+    fn parse(format: &str) -> Result<Self> {
+        match format {
+            "empty" => Ok(Self::Empty),
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "problem+json" => Ok(Self::ProblemJson),
+            other => Err(anyhow!("Unknown rejection body format '{other}'.")),
+        }
+    }
+}
+
+/// Builds the response for rejected requests, or only reports them in report-only mode.
+pub struct Rejection {
+    status_code: u32,
+    format: BodyFormat,
+    headers: Vec<(String, String)>,
+    report_only: bool,
+}
+
+impl Rejection {
+    /// Creates a [Rejection] from its optional configuration. Unset values keep the policy
+    /// defaults: a 403 status code and the given body format.
+    pub fn from_config(
+        config: Option<&RejectionConfig>,
+        report_only: bool,
+        default_format: BodyFormat,
+    ) -> Result<Self> {
+        let status_code = match config.and_then(|c| c.status_code) {
+            Some(code) if (400..=599).contains(&code) => code as u32,
+            Some(code) => return Err(anyhow!("Invalid rejection status code {code}.")),
+            None => 403,
+        };
+
+        let format = match config.and_then(|c| c.body_format.as_deref()) {
+            Some(format) => BodyFormat::parse(format)?,
+            None => default_format,
+        };
+
+        let headers = config
+            .and_then(|c| c.headers.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|h| (h.name.clone(), h.value.clone()))
+            .collect();
+
+        Ok(Self {
+            status_code,
+            format,
+            headers,
+            report_only,
+        })
+    }
+
+    /// Reports a policy violation for the given reason. Returns the flow that breaks the
+    /// request with the rejection response, or continues it in report-only mode.
+    pub fn reject(&self, violations: &PolicyViolations, reason: &str) -> Flow<()> {
+        violations.generate_policy_violation();
+
+        if self.report_only {
+            logger::warn!("Report-only mode, request would have been rejected: {reason}");
+            return Flow::Continue(());
+        }
+
+        logger::info!("Request rejected: {reason}");
+        Flow::Break(self.response(reason))
+    }
+
+    fn response(&self, reason: &str) -> Response {
+        let mut headers = self.headers.clone();
+        let content_type = |value: &str| ("content-type".to_string(), value.to_string());
+
+        let body = match self.format {
+            BodyFormat::Empty => Vec::new(),
+            BodyFormat::Text => reason.as_bytes().to_vec(),
+            BodyFormat::Json => {
+                headers.push(content_type("application/json"));
+                json!({ "error": reason }).to_string().into_bytes()
+            }
+            BodyFormat::ProblemJson => {
+                headers.push(content_type("application/problem+json"));
+                json!({
+                    "type": "about:blank",
+                    "title": title(self.status_code),
+                    "status": self.status_code,
+                    "detail": reason,
+                })
+                .to_string()
+                .into_bytes()
+            }
+        };
+
+        Response::new(self.status_code)
+            .with_headers(headers)
+            .with_body(body)
+    }
+}
+
+/// Reason phrase used as the problem details title.
+fn title(status_code: u32) -> &'static str {
+    match status_code {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        451 => "Unavailable For Legal Reasons",
+        503 => "Service Unavailable",
+        _ => "Request Rejected",
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Result};
+
+use pdk::ip_filter::IpFilter;
+
+use crate::generated::config::{Config, Rules0Config};
+use crate::geo::GeoInfo;
+
+/// Allow and block lists of country codes and ASNs.
+pub struct GeoLists {
+    countries_allowed: Vec<String>,
+    countries_blocked: Vec<String>,
+    asns_allowed: Vec<u32>,
+    asns_blocked: Vec<u32>,
+}
+
+impl GeoLists {
+    /// Creates the [GeoLists] from the configured country codes and ASNs.
+    pub fn new(
+        countries_allowed: &Option<Vec<String>>,
+        countries_blocked: &Option<Vec<String>>,
+        asns_allowed: &Option<Vec<i64>>,
+        asns_blocked: &Option<Vec<i64>>,
+    ) -> Result<Self> {
+        let countries = |codes: &Option<Vec<String>>| -> Vec<String> {
+            codes.iter().flatten().map(|c| c.to_uppercase()).collect()
+        };
+        let asns = |asns: &Option<Vec<i64>>| -> Result<Vec<u32>> {
+            asns.iter()
+                .flatten()
+                .map(|asn| u32::try_from(*asn).map_err(|_| anyhow!("Invalid ASN: {asn}")))
+                .collect()
+        };
+
+        Ok(Self {
+            countries_allowed: countries(countries_allowed),
+            countries_blocked: countries(countries_blocked),
+            asns_allowed: asns(asns_allowed)?,
+            asns_blocked: asns(asns_blocked)?,
+        })
+    }
+
+    /// Returns true if no country or ASN list is configured.
+    pub fn is_empty(&self) -> bool {
+        self.countries_allowed.is_empty()
+            && self.countries_blocked.is_empty()
+            && self.asns_allowed.is_empty()
+            && self.asns_blocked.is_empty()
+    }
+
+    /// Checks the resolved country and ASN against the lists. Unknown values are never blocked,
+    /// but they are not allowed when an allow list is configured.
+    fn check(&self, geo: &GeoInfo) -> Result<(), &'static str> {
+        if let Some(country) = &geo.country {
+            if self.countries_blocked.contains(country) {
+                return Err("Blocked country!");
+            }
+        }
+
+        if let Some(asn) = &geo.asn {
+            if self.asns_blocked.contains(asn) {
+                return Err("Blocked ASN!");
+            }
+        }
+
+        if !self.countries_allowed.is_empty()
+            && !geo
+                .country
+                .as_ref()
+                .is_some_and(|c| self.countries_allowed.contains(c))
+        {
+            return Err("Country not allowed!");
+        }
+
+        if !self.asns_allowed.is_empty()
+            && !geo.asn.is_some_and(|asn| self.asns_allowed.contains(&asn))
+        {
+            return Err("ASN not allowed!");
+        }
+
+        Ok(())
+    }
+}
+
+/// Allow and block lists applied to a client IP and to its resolved country and ASN.
+pub struct IpLists {
+    allow: Option<IpFilter>,
+    block: Option<IpFilter>,
+    geo: GeoLists,
+}
+
+impl IpLists {
+    /// Creates the [IpLists] from the configured allowed and blocked IPs or CIDR ranges.
+    pub fn new(
+        allowed: &Option<Vec<String>>,
+        blocked: &Option<Vec<String>>,
+        geo: GeoLists,
+    ) -> Result<Self> {
+        let allow = match allowed {
+            Some(ips) if !ips.is_empty() => Some(IpFilter::allow(ips)?),
+            _ => None,
+        };
+
+        let block = match blocked {
+            Some(ips) if !ips.is_empty() => Some(IpFilter::block(ips)?),
+            _ => None,
+        };
+
+        Ok(Self { allow, block, geo })
+    }
+
+    /// Checks the IP against the block list first and then against the allow list, followed by
+    /// the country and ASN lists. Returns the rejection message if the IP is not allowed.
+    pub fn check(&self, ip: &str, geo: &GeoInfo) -> Result<(), &'static str> {
+        if let Some(filter) = &self.block {
+            if !filter.is_allowed(ip) {
+                return Err("Blocked IP!");
+            }
+        }
+
+        if let Some(filter) = &self.allow {
+            if !filter.is_allowed(ip) {
+                return Err("IP not allowed!");
+            }
+        }
+
+        self.geo.check(geo)
+    }
+}
+
+/// IP lists restricted to the requests matching a path glob and a set of methods.
+struct Rule {
+    path: String,
+    methods: Vec<String>,
+    lists: IpLists,
+}
+
+impl Rule {
+    fn from_config(config: &Rules0Config) -> Result<Self> {
+        Ok(Self {
+            path: config.path.clone(),
+            methods: config
+                .methods
+                .iter()
+                .flatten()
+                .map(|m| m.to_uppercase())
+                .collect(),
+            lists: IpLists::new(
+                &config.ips_allowed,
+                &config.ips_blocked,
+                GeoLists::new(
+                    &config.countries_allowed,
+                    &config.countries_blocked,
+                    &config.asns_allowed,
+                    &config.asns_blocked,
+                )?,
+            )?,
+        })
+    }
+
+    fn matches(&self, method: &str, path: &str) -> bool {
+        let method_matches =
+            self.methods.is_empty() || self.methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+
+        method_matches && glob_matches(&self.path, path)
+    }
+}
+
+/// Ordered set of [Rule]s with the global [IpLists] as fallback.
+pub struct IpRules {
+    rules: Vec<Rule>,
+    default: IpLists,
+}
+
+impl IpRules {
+    /// Creates the [IpRules] from a [Config].
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            rules: config
+                .rules
+                .iter()
+                .flatten()
+                .map(Rule::from_config)
+                .collect::<Result<_>>()?,
+            default: IpLists::new(
+                &config.ips_allowed,
+                &config.ips_blocked,
+                GeoLists::new(
+                    &config.countries_allowed,
+                    &config.countries_blocked,
+                    &config.asns_allowed,
+                    &config.asns_blocked,
+                )?,
+            )?,
+        })
+    }
+
+    /// Returns true if any rule or the global lists filter by country or ASN.
+    pub fn uses_geo(&self) -> bool {
+        !self.default.geo.is_empty() || self.rules.iter().any(|rule| !rule.lists.geo.is_empty())
+    }
+
+    /// Returns the [IpLists] of the first rule matching the request, or the global ones
+    /// if no rule matches.
+    pub fn lists_for(&self, method: &str, path: &str) -> &IpLists {
+        // Query parameters do not take part in the matching.
+        let path = path.split('?').next().unwrap_or_default();
+
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(method, path))
+            .map(|rule| &rule.lists)
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Matches a path against a glob where `*` matches any character but `/` and `**` matches
+/// any sequence of characters. A `**/` also matches zero segments, and a trailing `/**` also
+/// matches the parent path itself.
+fn glob_matches(glob: &str, path: &str) -> bool {
+    let parent = glob
+        .strip_suffix("/**")
+        .is_some_and(|prefix| matches_from(prefix.as_bytes(), path.as_bytes()));
+
+    parent || matches_from(glob.as_bytes(), path.as_bytes())
+}
+
+/// Iterative matching that only resumes from the last `**` and the last `*` after it, so it runs
+/// in polynomial time whatever the number of wildcards.
+fn matches_from(glob: &[u8], path: &[u8]) -> bool {
+    let (mut g, mut p) = (0, 0);
+    // Glob and path positions after the last `**`, and whether it only spans whole segments.
+    let mut any: Option<(usize, usize, bool)> = None;
+    // Glob and path positions after the last `*` since the last `**`.
+    let mut star: Option<(usize, usize)> = None;
+
+    loop {
+        if glob[g..].starts_with(b"**") {
+            let segments = glob[g + 2..].starts_with(b"/");
+            g += if segments { 3 } else { 2 };
+            any = Some((g, p, segments));
+            star = None;
+            continue;
+        }
+        if glob.get(g) == Some(&b'*') {
+            g += 1;
+            star = Some((g, p));
+            continue;
+        }
+        if g < glob.len() && glob.get(g) == path.get(p) {
+            g += 1;
+            p += 1;
+            continue;
+        }
+        if g == glob.len() && p == path.len() {
+            return true;
+        }
+
+        // On a mismatch, the last `*` consumes one more character of the segment.
+        if let Some((star_g, star_p)) = star {
+            if path.get(star_p).is_some_and(|c| *c != b'/') {
+                star = Some((star_g, star_p + 1));
+                g = star_g;
+                p = star_p + 1;
+                continue;
+            }
+        }
+
+        // Otherwise, the last `**` consumes one more character, or one more segment.
+        let Some((any_g, any_p, segments)) = any else {
+            return false;
+        };
+        let next = if segments {
+            path[any_p..]
+                .iter()
+                .position(|c| *c == b'/')
+                .map(|i| any_p + i + 1)
+        } else {
+            Some(any_p + 1).filter(|next| *next <= path.len())
+        };
+        let Some(next) = next else {
+            return false;
+        };
+        any = Some((any_g, next, segments));
+        star = None;
+        g = any_g;
+        p = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_matches;
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_matches("/admin/**", "/admin"));
+        assert!(glob_matches("/admin/**", "/admin/users/1"));
+        assert!(!glob_matches("/admin/**", "/administrator"));
+        assert!(glob_matches("/users/*/orders", "/users/42/orders"));
+        assert!(!glob_matches("/users/*/orders", "/users/42/x/orders"));
+        assert!(glob_matches("/**/health", "/api/v1/health"));
+        assert!(glob_matches("/**/health", "/health"));
+        assert!(!glob_matches("/**/health", "/api/healthz"));
+        assert!(glob_matches("/static/**.css", "/static/css/site.css"));
+        assert!(glob_matches("/*/*.json", "/api/items.json"));
+        assert!(!glob_matches("/*/*.json", "/api/v1/items.json"));
+        assert!(glob_matches("/exact", "/exact"));
+        assert!(!glob_matches("/exact", "/exact/more"));
+    }
+
+    #[test]
+    fn many_wildcards_match_in_polynomial_time() {
+        let path = "/a".repeat(2000);
+
+        assert!(!glob_matches("/**/**/**/**/**/**/**/**/b", &path));
+        assert!(!glob_matches("/**a**a**a**a**a**a**b", &path));
+        assert!(glob_matches("/**/**/**/a", &path));
+    }
+}
@@ -1,15 +1,48 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
+pub struct Claims0Config {
+    #[serde(alias = "name")]
+    pub name: String,
+    #[serde(alias = "value", deserialize_with = "de_value_0")]
+    pub value: pdk::script::Script,
+}
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "algorithm")]
+    pub algorithm: Option<String>,
+    #[serde(alias = "claims")]
+    pub claims: Option<Vec<Claims0Config>>,
     #[serde(alias = "jku")]
     pub jku: String,
+    #[serde(alias = "jwksPath")]
+    pub jwks_path: Option<String>,
     #[serde(alias = "kid")]
     pub kid: String,
     #[serde(alias = "privateKey")]
     pub private_key: String,
+    #[serde(alias = "targetHeader")]
+    pub target_header: Option<String>,
+    #[serde(alias = "tokenPrefix")]
+    pub token_prefix: Option<String>,
+    #[serde(alias = "ttl")]
+    pub ttl: Option<i64>,
 }
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
     abi.setup()?;
     Ok(())
 }
+fn de_value_0<'de, D>(deserializer: D) -> Result<pdk::script::Script, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let exp: pdk::script::Expression = serde::de::Deserialize::deserialize(
+        deserializer,
+    )?;
+    pdk::script::ScriptingEngine::script(&exp)
+        .input(pdk::script::Input::Attributes)
+        .input(pdk::script::Input::Authentication)
+        .input(pdk::script::Input::Vars("properties"))
+        .compile()
+        .map_err(serde::de::Error::custom)
+}
@@ -0,0 +1,58 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+use std::collections::HashMap;
+
+use anyhow::Result;
+use base64::prelude::*;
+use pdk::jwt::model::JWTClaims;
+use pdk::jwt::JwtGenerator;
+use serde_json::Value;
+
+use crate::keys::{Algorithm, SigningKey};
+
+/// Generates the JWTs with the PDK [JwtGenerator] when it supports the configured algorithm, and
+/// signs them with the policy [SigningKey] otherwise.
+pub struct Generator {
+    key: SigningKey,
+    pdk: Option<JwtGenerator>,
+}
+
+impl Generator {
+    pub fn new(algorithm: Algorithm, private_key: &str) -> Result<Self> {
+        let key = SigningKey::from_pem(algorithm, private_key)?;
+        let pdk = algorithm
+            .pdk()
+            .map(|(algorithm, length)| JwtGenerator::new(algorithm, length, private_key))
+            .transpose()?;
+
+        Ok(Self { key, pdk })
+    }
+
+    /// JWKS document with the public key that verifies the generated JWTs.
+    pub fn jwks(&self, kid: &str) -> Value {
+        serde_json::json!({ "keys": [self.key.jwk(kid)] })
+    }
+
+    /// Creates a signed JWT with the given claims and headers.
+    pub fn jwt(
+        &self,
+        claims: HashMap<String, Value>,
+        mut headers: HashMap<String, Value>,
+    ) -> Result<String> {
+        if let Some(generator) = &self.pdk {
+            let claims = JWTClaims::new(None, None, None, claims, headers)?;
+            return Ok(generator.jwt(claims)?);
+        }
+
+        headers.insert("alg".to_string(), self.key.algorithm().name().into());
+        headers.insert("typ".to_string(), "JWT".into());
+
+        let message = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&headers)?),
+            BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+        );
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(self.key.sign(message.as_bytes()));
+
+        Ok(format!("{message}.{signature}"))
+    }
+}
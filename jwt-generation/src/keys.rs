@@ -0,0 +1,364 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Private keys for the JWS signing algorithms supported by the policy, and the JWK
+//! representation of their public keys.
+
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use pdk::jwt::model::{SigningAlgorithm, SigningKeyLength};
+use rand_core::OsRng;
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Signer};
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde_json::{json, Value};
+use sha2::{Sha256, Sha384, Sha512};
+
+/// JWS signing algorithm, identified by its name in the `alg` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Rs256,
+    Rs384,
+    Rs512,
+    Ps256,
+    Ps384,
+    Ps512,
+    Es256,
+    Es384,
+    Es512,
+    EdDsa,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "RS256" => Ok(Self::Rs256),
+            "RS384" => Ok(Self::Rs384),
+            "RS512" => Ok(Self::Rs512),
+            "PS256" => Ok(Self::Ps256),
+            "PS384" => Ok(Self::Ps384),
+            "PS512" => Ok(Self::Ps512),
+            "ES256" => Ok(Self::Es256),
+            "ES384" => Ok(Self::Es384),
+            "ES512" => Ok(Self::Es512),
+            "EdDSA" => Ok(Self::EdDsa),
+            other => Err(anyhow!("Unknown signing algorithm '{other}'.")),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rs256 => "RS256",
+            Self::Rs384 => "RS384",
+            Self::Rs512 => "RS512",
+            Self::Ps256 => "PS256",
+            Self::Ps384 => "PS384",
+            Self::Ps512 => "PS512",
+            Self::Es256 => "ES256",
+            Self::Es384 => "ES384",
+            Self::Es512 => "ES512",
+            Self::EdDsa => "EdDSA",
+        }
+    }
+
+    /// Algorithm and key length for the PDK [pdk::jwt::JwtGenerator], if it supports this
+    /// algorithm.
+    pub fn pdk(&self) -> Option<(SigningAlgorithm, SigningKeyLength)> {
+        match self {
+            Self::Rs256 => Some((SigningAlgorithm::Rsa, SigningKeyLength::Len256)),
+            Self::Rs384 => Some((SigningAlgorithm::Rsa, SigningKeyLength::Len384)),
+            Self::Rs512 => Some((SigningAlgorithm::Rsa, SigningKeyLength::Len512)),
+            Self::Es256 => Some((SigningAlgorithm::Es, SigningKeyLength::Len256)),
+            Self::Es384 => Some((SigningAlgorithm::Es, SigningKeyLength::Len384)),
+            _ => None,
+        }
+    }
+}
+
+/// Signing key for each algorithm, built once from the private key.
+enum Key {
+    Rs256(rsa::pkcs1v15::SigningKey<Sha256>),
+    Rs384(rsa::pkcs1v15::SigningKey<Sha384>),
+    Rs512(rsa::pkcs1v15::SigningKey<Sha512>),
+    Ps256(rsa::pss::BlindedSigningKey<Sha256>),
+    Ps384(rsa::pss::BlindedSigningKey<Sha384>),
+    Ps512(rsa::pss::BlindedSigningKey<Sha512>),
+    Es256(p256::ecdsa::SigningKey),
+    Es384(p384::ecdsa::SigningKey),
+    Es512(p521::ecdsa::SigningKey),
+    EdDsa(ed25519_dalek::SigningKey),
+}
+
+/// Private key used to sign the JWTs.
+pub struct SigningKey {
+    key: Key,
+}
+
+impl SigningKey {
+    /// Reads the private key for the algorithm from its PEM in pkcs8 format.
+    pub fn from_pem(algorithm: Algorithm, pem: &str) -> Result<Self> {
+        let parse_err = |err| {
+            anyhow!(
+                "Failed to parse {} private key. Cause: {err}",
+                algorithm.name()
+            )
+        };
+        let rsa = || RsaPrivateKey::from_pkcs8_pem(pem).map_err(parse_err);
+
+        let key = match algorithm {
+            Algorithm::Rs256 => Key::Rs256(rsa::pkcs1v15::SigningKey::new(rsa()?)),
+            Algorithm::Rs384 => Key::Rs384(rsa::pkcs1v15::SigningKey::new(rsa()?)),
+            Algorithm::Rs512 => Key::Rs512(rsa::pkcs1v15::SigningKey::new(rsa()?)),
+            Algorithm::Ps256 => Key::Ps256(rsa::pss::BlindedSigningKey::new(rsa()?)),
+            Algorithm::Ps384 => Key::Ps384(rsa::pss::BlindedSigningKey::new(rsa()?)),
+            Algorithm::Ps512 => Key::Ps512(rsa::pss::BlindedSigningKey::new(rsa()?)),
+            Algorithm::Es256 => {
+                Key::Es256(p256::ecdsa::SigningKey::from_pkcs8_pem(pem).map_err(parse_err)?)
+            }
+            Algorithm::Es384 => {
+                Key::Es384(p384::ecdsa::SigningKey::from_pkcs8_pem(pem).map_err(parse_err)?)
+            }
+            Algorithm::Es512 => {
+                let secret = p521::SecretKey::from_pkcs8_pem(pem).map_err(parse_err)?;
+                Key::Es512(
+                    p521::ecdsa::SigningKey::from_bytes(&secret.to_bytes()).map_err(|err| {
+                        anyhow!("Failed to parse ES512 private key. Cause: {err}")
+                    })?,
+                )
+            }
+            Algorithm::EdDsa => {
+                Key::EdDsa(ed25519_dalek::SigningKey::from_pkcs8_pem(pem).map_err(parse_err)?)
+            }
+        };
+
+        Ok(Self { key })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self.key {
+            Key::Rs256(_) => Algorithm::Rs256,
+            Key::Rs384(_) => Algorithm::Rs384,
+            Key::Rs512(_) => Algorithm::Rs512,
+            Key::Ps256(_) => Algorithm::Ps256,
+            Key::Ps384(_) => Algorithm::Ps384,
+            Key::Ps512(_) => Algorithm::Ps512,
+            Key::Es256(_) => Algorithm::Es256,
+            Key::Es384(_) => Algorithm::Es384,
+            Key::Es512(_) => Algorithm::Es512,
+            Key::EdDsa(_) => Algorithm::EdDsa,
+        }
+    }
+
+    /// Signs the message. ECDSA signatures are encoded as the concatenation of `r` and `s`, as
+    /// required by JWS.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match &self.key {
+            Key::Rs256(key) => key.sign(message).to_vec(),
+            Key::Rs384(key) => key.sign(message).to_vec(),
+            Key::Rs512(key) => key.sign(message).to_vec(),
+            Key::Ps256(key) => key.sign_with_rng(&mut OsRng, message).to_vec(),
+            Key::Ps384(key) => key.sign_with_rng(&mut OsRng, message).to_vec(),
+            Key::Ps512(key) => key.sign_with_rng(&mut OsRng, message).to_vec(),
+            Key::Es256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+            Key::Es384(key) => {
+                let signature: p384::ecdsa::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+            Key::Es512(key) => {
+                let signature: p521::ecdsa::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+            Key::EdDsa(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+
+    /// Public key as a JWK, as published in a JWKS document.
+    pub fn jwk(&self, kid: &str) -> Value {
+        let encode = |bytes: &[u8]| BASE64_URL_SAFE_NO_PAD.encode(bytes);
+        let ec = |crv: &str, x: Option<&[u8]>, y: Option<&[u8]>| {
+            json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": encode(x.unwrap_or_default()),
+                "y": encode(y.unwrap_or_default()),
+            })
+        };
+
+        let rsa = |key: &RsaPrivateKey| {
+            json!({
+                "kty": "RSA",
+                "n": encode(&key.n().to_bytes_be()),
+                "e": encode(&key.e().to_bytes_be()),
+            })
+        };
+
+        let mut jwk = match &self.key {
+            Key::Rs256(key) => rsa(key.as_ref()),
+            Key::Rs384(key) => rsa(key.as_ref()),
+            Key::Rs512(key) => rsa(key.as_ref()),
+            Key::Ps256(key) => rsa(key.as_ref()),
+            Key::Ps384(key) => rsa(key.as_ref()),
+            Key::Ps512(key) => rsa(key.as_ref()),
+            Key::Es256(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                ec(
+                    "P-256",
+                    point.x().map(|x| &x[..]),
+                    point.y().map(|y| &y[..]),
+                )
+            }
+            Key::Es384(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                ec(
+                    "P-384",
+                    point.x().map(|x| &x[..]),
+                    point.y().map(|y| &y[..]),
+                )
+            }
+            Key::Es512(key) => {
+                let point = p521::ecdsa::VerifyingKey::from(key).to_encoded_point(false);
+                ec(
+                    "P-521",
+                    point.x().map(|x| &x[..]),
+                    point.y().map(|y| &y[..]),
+                )
+            }
+            Key::EdDsa(key) => json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": encode(key.verifying_key().as_bytes()),
+            }),
+        };
+
+        if let Some(jwk) = jwk.as_object_mut() {
+            jwk.insert("kid".to_string(), kid.into());
+            jwk.insert("use".to_string(), "sig".into());
+            jwk.insert("alg".to_string(), self.algorithm().name().into());
+        }
+        jwk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Algorithm, SigningKey};
+    use ed25519_dalek::pkcs8::DecodePublicKey;
+    use rsa::signature::Verifier;
+    use rsa::RsaPublicKey;
+    use sha2::{Sha256, Sha384, Sha512};
+    use std::convert::TryFrom;
+
+    const RSA: &str = include_str!("../tests/resources/rsa.pem");
+    const RSA_PUBLIC: &str = include_str!("../tests/resources/rsa.pub.pem");
+
+    fn sign(algorithm: &str, pem: &str) -> Vec<u8> {
+        SigningKey::from_pem(Algorithm::parse(algorithm).unwrap(), pem)
+            .unwrap()
+            .sign(b"header.payload")
+    }
+
+    #[test]
+    fn signatures_are_verified_for_all_algorithms() {
+        let rsa = RsaPublicKey::from_public_key_pem(RSA_PUBLIC).unwrap();
+        let message = b"header.payload";
+
+        let signature = rsa::pkcs1v15::Signature::try_from(sign("RS384", RSA).as_slice()).unwrap();
+        assert!(rsa::pkcs1v15::VerifyingKey::<Sha384>::new(rsa.clone())
+            .verify(message, &signature)
+            .is_ok());
+
+        let signature = rsa::pss::Signature::try_from(sign("PS256", RSA).as_slice()).unwrap();
+        assert!(rsa::pss::VerifyingKey::<Sha256>::new(rsa.clone())
+            .verify(message, &signature)
+            .is_ok());
+
+        let signature = rsa::pss::Signature::try_from(sign("PS512", RSA).as_slice()).unwrap();
+        assert!(rsa::pss::VerifyingKey::<Sha512>::new(rsa)
+            .verify(message, &signature)
+            .is_ok());
+
+        let key = p256::ecdsa::VerifyingKey::from_public_key_pem(include_str!(
+            "../tests/resources/es256.pub.pem"
+        ))
+        .unwrap();
+        let signature = sign("ES256", include_str!("../tests/resources/es256.pem"));
+        assert_eq!(signature.len(), 64);
+        assert!(key
+            .verify(
+                message,
+                &p256::ecdsa::Signature::from_slice(&signature).unwrap()
+            )
+            .is_ok());
+
+        let key = p384::ecdsa::VerifyingKey::from_public_key_pem(include_str!(
+            "../tests/resources/es384.pub.pem"
+        ))
+        .unwrap();
+        let signature = sign("ES384", include_str!("../tests/resources/es384.pem"));
+        assert_eq!(signature.len(), 96);
+        assert!(key
+            .verify(
+                message,
+                &p384::ecdsa::Signature::from_slice(&signature).unwrap()
+            )
+            .is_ok());
+
+        let public =
+            p521::PublicKey::from_public_key_pem(include_str!("../tests/resources/es512.pub.pem"))
+                .unwrap();
+        let key = p521::ecdsa::VerifyingKey::from_affine(*public.as_affine()).unwrap();
+        let signature = sign("ES512", include_str!("../tests/resources/es512.pem"));
+        assert_eq!(signature.len(), 132);
+        assert!(key
+            .verify(
+                message,
+                &p521::ecdsa::Signature::from_slice(&signature).unwrap()
+            )
+            .is_ok());
+
+        let key = ed25519_dalek::VerifyingKey::from_public_key_pem(include_str!(
+            "../tests/resources/ed25519.pub.pem"
+        ))
+        .unwrap();
+        let signature = sign("EdDSA", include_str!("../tests/resources/ed25519.pem"));
+        assert!(key
+            .verify(
+                message,
+                &ed25519_dalek::Signature::from_slice(&signature).unwrap()
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn jwk_describes_the_public_key() {
+        let key = SigningKey::from_pem(
+            Algorithm::Es512,
+            include_str!("../tests/resources/es512.pem"),
+        )
+        .unwrap();
+        let jwk = key.jwk("1");
+
+        assert_eq!(jwk["kty"], "EC");
+        assert_eq!(jwk["crv"], "P-521");
+        assert_eq!(jwk["kid"], "1");
+        assert_eq!(jwk["alg"], "ES512");
+        assert_eq!(jwk["x"].as_str().unwrap().len(), 88);
+
+        let key = SigningKey::from_pem(Algorithm::Ps256, RSA).unwrap();
+        let jwk = key.jwk("2");
+        assert_eq!(jwk["kty"], "RSA");
+        assert_eq!(jwk["e"], "AQAB");
+    }
+
+    #[test]
+    fn keys_must_match_the_algorithm() {
+        let ed25519 = include_str!("../tests/resources/ed25519.pem");
+
+        assert!(SigningKey::from_pem(Algorithm::Es256, ed25519).is_err());
+        assert!(SigningKey::from_pem(Algorithm::Rs256, ed25519).is_err());
+        assert!(Algorithm::parse("HS256").is_err());
+        assert!(Algorithm::parse("none").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Propagation of the token claims to the request headers.
+
+use pdk::jwt::model::JWTClaims;
+use serde_json::Value;
+
+/// Resolves a dot separated claim path, such as `realm_access.roles` or `groups.0`. Numeric
+/// segments index arrays.
+pub fn claim_value(claims: &JWTClaims, path: &str) -> Option<Value> {
+    let mut segments = path.split('.');
+    let mut value: Value = claims.get_claim(segments.next()?)?;
+
+    for segment in segments {
+        value = match value {
+            Value::Object(mut object) => object.remove(segment)?,
+            Value::Array(mut array) => {
+                let index = segment.parse::<usize>().ok()?;
+                (index < array.len()).then(|| array.swap_remove(index))?
+            }
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+/// Formats a claim as a header value. Arrays are joined with commas and objects are serialized
+/// as JSON.
+pub fn header_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(string) => Some(string.clone()),
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .filter_map(header_value)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{claim_value, header_value};
+    use pdk::jwt::model::JWTClaims;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn nested_claims_and_arrays_are_resolved() {
+        let claims = HashMap::from([
+            ("username".to_string(), json!("LibraryFan1984")),
+            (
+                "realm_access".to_string(),
+                json!({ "roles": ["member", "reader"], "level": 3 }),
+            ),
+        ]);
+        let claims = JWTClaims::new(None, None, None, claims, HashMap::new()).unwrap();
+        let header = |path: &str| claim_value(&claims, path).as_ref().and_then(header_value);
+
+        assert_eq!(header("username").as_deref(), Some("LibraryFan1984"));
+        assert_eq!(
+            header("realm_access.roles").as_deref(),
+            Some("member,reader")
+        );
+        assert_eq!(header("realm_access.roles.1").as_deref(), Some("reader"));
+        assert_eq!(header("realm_access.level").as_deref(), Some("3"));
+        assert_eq!(
+            header("realm_access").as_deref(),
+            Some(r#"{"level":3,"roles":["member","reader"]}"#)
+        );
+        assert_eq!(header("realm_access.roles.2"), None);
+        assert_eq!(header("username.first"), None);
+        assert_eq!(header("missing"), None);
+    }
+}
@@ -1,13 +1,48 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
+pub struct ClaimsToHeaders0Config {
+    #[serde(alias = "claim")]
+    pub claim: String,
+    #[serde(alias = "header")]
+    pub header: String,
+}
+#[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "allowedAlgorithms")]
+    pub allowed_algorithms: Option<Vec<String>>,
+    #[serde(alias = "audiences")]
+    pub audiences: Option<Vec<String>>,
+    #[serde(alias = "claimsToHeaders")]
+    pub claims_to_headers: Option<Vec<ClaimsToHeaders0Config>>,
     #[serde(alias = "customRule", deserialize_with = "de_custom_rule_0")]
     pub custom_rule: pdk::script::Script,
+    #[serde(alias = "issuers")]
+    pub issuers: Option<Vec<String>>,
+    #[serde(alias = "jwksRefreshInterval")]
+    pub jwks_refresh_interval: Option<i64>,
+    #[serde(alias = "jwksUrl", default, deserialize_with = "pdk::serde::deserialize_service_opt")]
+    pub jwks_url: Option<pdk::hl::Service>,
+    #[serde(alias = "leeway")]
+    pub leeway: Option<i64>,
+    #[serde(alias = "requiredClaims")]
+    pub required_claims: Option<Vec<String>>,
+    #[serde(alias = "ruleFailureMessage")]
+    pub rule_failure_message: Option<String>,
     #[serde(alias = "secret")]
-    pub secret: String,
+    pub secret: Option<String>,
 }
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
+    let config: Config = serde_json::from_slice(abi.get_configuration())
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "Failed to parse configuration '{}'. Cause: {}",
+                String::from_utf8_lossy(abi.get_configuration()), err
+            )
+        })?;
+    if let Some(service) = config.jwks_url {
+        abi.service_create(service)?;
+    }
     abi.setup()?;
     Ok(())
 }
@@ -0,0 +1,367 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Signature validation with the keys published by an identity provider as a JWKS document.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use serde::Deserialize;
+
+use pdk::cache::Cache;
+use pdk::hl::timer::Timer;
+use pdk::hl::{HttpClient, Service};
+use pdk::jwt::model::{JWTClaims, SigningAlgorithm, SigningKeyLength};
+use pdk::jwt::SignatureValidator;
+use pdk::lock::TryLock;
+use pdk::logger;
+use rsa::pkcs8::{EncodePublicKey, LineEnding};
+use rsa::{BigUint, RsaPublicKey};
+
+use crate::validation::TokenHeader;
+
+/// Identifier for the cache and the lock to share the JWKS between workers.
+pub const ID: &str = "jwt-validation-jwks";
+
+/// Key for cache entry that keeps the time of the last update to the stored JWKS.
+const LAST_UPDATE: &str = "lastUpdate";
+/// Key for cache entry that keeps the JWKS to be shared between workers.
+const DATA_KEY: &str = "data";
+
+/// Minimum time between the refreshes forced by tokens without a matching key, so invalid tokens
+/// can't flood the identity provider.
+const UNKNOWN_KID_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Signing algorithms the PDK validator supports, by their name in the `alg` header.
+const ALGORITHMS: &[(&str, SigningAlgorithm, SigningKeyLength)] = &[
+    ("RS256", SigningAlgorithm::Rsa, SigningKeyLength::Len256),
+    ("RS384", SigningAlgorithm::Rsa, SigningKeyLength::Len384),
+    ("RS512", SigningAlgorithm::Rsa, SigningKeyLength::Len512),
+    ("ES256", SigningAlgorithm::Es, SigningKeyLength::Len256),
+    ("ES384", SigningAlgorithm::Es, SigningKeyLength::Len384),
+];
+
+/// Subset of a JWK with the public key parameters of RSA and EC keys.
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    alg: Option<String>,
+    #[serde(rename = "use")]
+    usage: Option<String>,
+    crv: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+/// Validator for the tokens signed with one of the keys of the JWKS.
+struct Key {
+    kid: Option<String>,
+    alg: &'static str,
+    validator: SignatureValidator,
+}
+
+fn decode(value: &Option<String>, name: &str) -> Result<Vec<u8>> {
+    let value = value
+        .as_deref()
+        .ok_or_else(|| anyhow!("Missing '{name}' parameter."))?;
+    Ok(BASE64_URL_SAFE_NO_PAD.decode(value)?)
+}
+
+impl Jwk {
+    /// Public key in PEM format and the algorithms it can verify.
+    fn pem(&self) -> Result<(String, Vec<&'static str>)> {
+        match (self.kty.as_str(), self.crv.as_deref()) {
+            ("RSA", _) => {
+                let key = RsaPublicKey::new(
+                    BigUint::from_bytes_be(&decode(&self.n, "n")?),
+                    BigUint::from_bytes_be(&decode(&self.e, "e")?),
+                )?;
+                Ok((
+                    key.to_public_key_pem(LineEnding::LF)?,
+                    vec!["RS256", "RS384", "RS512"],
+                ))
+            }
+            ("EC", Some(crv @ ("P-256" | "P-384"))) => {
+                let mut point = vec![0x04];
+                point.extend(decode(&self.x, "x")?);
+                point.extend(decode(&self.y, "y")?);
+
+                if crv == "P-256" {
+                    let key = p256::PublicKey::from_sec1_bytes(&point)?;
+                    Ok((key.to_public_key_pem(LineEnding::LF)?, vec!["ES256"]))
+                } else {
+                    let key = p384::PublicKey::from_sec1_bytes(&point)?;
+                    Ok((key.to_public_key_pem(LineEnding::LF)?, vec!["ES384"]))
+                }
+            }
+            (kty, crv) => Err(anyhow!("Unsupported key type {kty} {crv:?}.")),
+        }
+    }
+
+    /// Validators for the algorithms the key can verify, restricted to its `alg` when present.
+    fn keys(&self) -> Result<Vec<Key>> {
+        let (pem, algorithms) = self.pem()?;
+
+        ALGORITHMS
+            .iter()
+            .filter(|(name, _, _)| algorithms.contains(name))
+            .filter(|(name, _, _)| self.alg.as_deref().is_none_or(|alg| alg == *name))
+            .map(|(name, algorithm, length)| {
+                Ok(Key {
+                    kid: self.kid.clone(),
+                    alg: name,
+                    validator: SignatureValidator::new(*algorithm, *length, pem.clone())?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Keys of the JWKS loaded in the worker memory.
+#[derive(Default)]
+pub struct Jwks {
+    keys: RefCell<Vec<Rc<Key>>>,
+    last_update: Cell<Option<SystemTime>>,
+    last_forced_refresh: Cell<Option<SystemTime>>,
+}
+
+impl Jwks {
+    /// Parses a JWKS document. Keys that can't be used to verify signatures are skipped.
+    fn parse(data: &[u8]) -> Result<Vec<Rc<Key>>> {
+        let set: JwkSet =
+            serde_json::from_slice(data).map_err(|err| anyhow!("Invalid JWKS. Cause: {err}"))?;
+
+        let mut keys = Vec::new();
+        for jwk in set
+            .keys
+            .iter()
+            .filter(|jwk| jwk.usage.as_deref() != Some("enc"))
+        {
+            match jwk.keys() {
+                Ok(parsed) => keys.extend(parsed.into_iter().map(Rc::new)),
+                Err(err) => logger::debug!("Skipping JWK {:?}: {err}", jwk.kid),
+            }
+        }
+
+        if keys.is_empty() {
+            return Err(anyhow!("JWKS has no supported signing keys."));
+        }
+        Ok(keys)
+    }
+
+    fn update(&self, update: SystemTime, data: &[u8]) -> Result<()> {
+        self.keys.replace(Self::parse(data)?);
+        self.last_update.set(Some(update));
+        Ok(())
+    }
+
+    /// Keys that can validate a token with the given header. Tokens without `kid` are checked
+    /// against every key for their algorithm.
+    fn find(&self, header: &TokenHeader) -> Vec<Rc<Key>> {
+        self.keys
+            .borrow()
+            .iter()
+            .filter(|key| key.alg == header.alg)
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns true if a token without a matching key may force a refresh of the JWKS.
+    fn may_force_refresh(&self, now: SystemTime) -> bool {
+        let allowed = self
+            .last_forced_refresh
+            .get()
+            .is_none_or(|last| now >= last + UNKNOWN_KID_COOLDOWN);
+        if allowed {
+            self.last_forced_refresh.set(Some(now));
+        }
+        allowed
+    }
+}
+
+/// Validates tokens with the JWKS published by the identity provider, sharing it between workers.
+pub struct JwksValidator<'a, C: Cache> {
+    pub service: &'a Service,
+    pub client: &'a HttpClient,
+    pub cache: C,
+    pub lock: TryLock,
+    pub frequency: Duration,
+    pub jwks: Jwks,
+}
+
+impl<C: Cache> JwksValidator<'_, C> {
+    /// Get the last update value from the cache.
+    fn last_update(&self) -> Option<SystemTime> {
+        self.cache
+            .get(LAST_UPDATE)
+            .and_then(|data| serde_json::from_slice::<SystemTime>(data.as_slice()).ok())
+    }
+
+    /// Downloads the JWKS from the identity provider and shares it with the other workers.
+    async fn fetch(&self) -> Result<()> {
+        // Acquire the lock to ensure only one worker is downloading the JWKS at a time
+        let Some(acquired) = self.lock.try_lock() else {
+            return Ok(());
+        };
+
+        let response = self
+            .client
+            .request(self.service)
+            .timeout(Duration::from_secs(10))
+            .get()
+            .await?;
+
+        // If we lost the lock we discard the response.
+        if !acquired.refresh_lock() {
+            return Err(anyhow!("Lost the lock!"));
+        }
+
+        if response.status_code() != 200 {
+            return Err(anyhow!(
+                "{} - {}",
+                response.status_code(),
+                String::from_utf8_lossy(response.body())
+            ));
+        }
+
+        // Validate the JWKS before sharing it with the other workers.
+        let now = SystemTime::now();
+        self.jwks.update(now, response.body())?;
+
+        self.cache.save(LAST_UPDATE, serde_json::to_vec(&now)?)?;
+        self.cache.save(DATA_KEY, response.body().to_vec())?;
+        Ok(())
+    }
+
+    /// Downloads the JWKS if it has passed enough time since the last update.
+    async fn refresh(&self) -> Result<()> {
+        let now = SystemTime::now();
+
+        if self
+            .last_update()
+            .is_some_and(|update| now < update + self.frequency)
+        {
+            return Ok(()); // No update necessary.
+        }
+
+        self.fetch().await
+    }
+
+    /// Reads the JWKS from the cache to the worker memory if there is any update.
+    fn load(&self) -> Result<()> {
+        let Some(update) = self.last_update() else {
+            return Ok(());
+        };
+
+        if self.jwks.last_update.get() == Some(update) {
+            return Ok(());
+        }
+
+        match self.cache.get(DATA_KEY) {
+            Some(data) => self.jwks.update(update, &data),
+            None => Ok(()),
+        }
+    }
+
+    /// Keys for the token, from the latest JWKS shared by the workers. When no key matches, the
+    /// identity provider may have rotated its keys, so the JWKS is downloaded again.
+    async fn find(&self, header: &TokenHeader) -> Vec<Rc<Key>> {
+        if let Err(err) = self.load() {
+            logger::warn!("Unexpected error while loading the JWKS: {err}.");
+        }
+        let keys = self.jwks.find(header);
+        if !keys.is_empty() || !self.jwks.may_force_refresh(SystemTime::now()) {
+            return keys;
+        }
+
+        logger::debug!(
+            "No key for kid {:?} and alg {}, refreshing the JWKS.",
+            header.kid,
+            header.alg
+        );
+        if let Err(err) = self.fetch().await {
+            logger::warn!("Unexpected error while fetching the JWKS: {err}.");
+        }
+        self.jwks.find(header)
+    }
+
+    /// Validates the token signature with the key selected by its `kid` and `alg` headers.
+    pub async fn validate(&self, token: String, header: &TokenHeader) -> Result<JWTClaims> {
+        for key in self.find(header).await {
+            if let Ok(claims) = key.validator.validate(token.clone()) {
+                return Ok(claims);
+            }
+        }
+        Err(anyhow!(
+            "No key validates the token with kid {:?} and alg {}.",
+            header.kid,
+            header.alg
+        ))
+    }
+
+    /// Periodically refreshes the JWKS from the identity provider and shares it between workers.
+    pub async fn fetch_loop(&self, timer: &Timer) {
+        while timer.next_tick().await {
+            if let Err(err) = self.refresh().await {
+                logger::warn!("Unexpected error while fetching the JWKS: {err}.");
+            }
+            if let Err(err) = self.load() {
+                logger::warn!("Unexpected error while loading the JWKS: {err}.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Jwks;
+    use crate::validation::TokenHeader;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    #[test]
+    fn keys_are_selected_by_kid_and_alg() {
+        let jwks = Jwks::default();
+        jwks.update(
+            SystemTime::now(),
+            include_bytes!("../tests/resources/jwks.json"),
+        )
+        .unwrap();
+
+        let header = |alg: &str, kid: Option<&str>| TokenHeader {
+            alg: alg.to_string(),
+            kid: kid.map(str::to_string),
+        };
+
+        // The RSA key declares its algorithm, the EC key is bound to its curve.
+        assert_eq!(jwks.find(&header("RS256", Some("rsa"))).len(), 1);
+        assert!(jwks.find(&header("RS384", Some("rsa"))).is_empty());
+        assert_eq!(jwks.find(&header("ES256", Some("ec"))).len(), 1);
+        assert!(jwks.find(&header("ES256", Some("rsa"))).is_empty());
+        assert_eq!(jwks.find(&header("ES256", None)).len(), 1);
+        assert!(jwks.find(&header("HS256", None)).is_empty());
+    }
+
+    #[test]
+    fn unsupported_keys_are_skipped() {
+        let jwks = json!({
+            "keys": [
+                { "kty": "OKP", "crv": "Ed25519", "kid": "ed", "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo" },
+                { "kty": "oct", "kid": "secret", "k": "c2VjcmV0" }
+            ]
+        });
+
+        assert!(Jwks::parse(jwks.to_string().as_bytes()).is_err());
+        assert!(Jwks::parse(b"not a jwks").is_err());
+    }
+}
@@ -1,45 +1,83 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
 use chrono::Utc;
+use futures::join;
+use pdk::cache::{Cache, CacheBuilder};
+use pdk::hl::timer::Clock;
 use pdk::hl::*;
+use pdk::jwt::model::JWTClaims;
 use pdk::jwt::*;
+use pdk::lock::LockBuilder;
 use pdk::logger::debug;
+use serde_json::json;
 
-use crate::generated::config::Config;
+use crate::generated::config::{ClaimsToHeaders0Config, Config};
+use crate::jwks::{Jwks, JwksValidator};
+use crate::validation::{ClaimsValidator, TokenError, TokenHeader};
 
+mod claims;
 mod generated;
+mod jwks;
+mod validation;
 
-async fn filter(
-    state: RequestState,
-    config: &Config,
-    signature_validator: &SignatureValidator,
-) -> Flow<()> {
-    let headers_state = state.into_headers_state().await;
+/// Seconds between the refreshes of the JWKS when no interval is configured.
+const DEFAULT_JWKS_REFRESH_INTERVAL: u64 = 3600;
+
+/// Message of the rejections by the custom rule when no message is configured.
+const DEFAULT_RULE_FAILURE_MESSAGE: &str = "Invalid token: Only members are allowed.";
+
+/// Claims forwarded to the upstream when none are configured.
+fn default_claims_to_headers() -> Vec<ClaimsToHeaders0Config> {
+    vec![ClaimsToHeaders0Config {
+        claim: "username".to_string(),
+        header: "username".to_string(),
+    }]
+}
+
+/// Validates the signature of a token and extracts its claims.
+trait TokenValidator {
+    async fn validate(&self, token: String, header: &TokenHeader) -> Option<JWTClaims>;
+}
+
+impl TokenValidator for SignatureValidator {
+    async fn validate(&self, token: String, _header: &TokenHeader) -> Option<JWTClaims> {
+        SignatureValidator::validate(self, token).ok()
+    }
+}
+
+impl<C: Cache> TokenValidator for JwksValidator<'_, C> {
+    async fn validate(&self, token: String, header: &TokenHeader) -> Option<JWTClaims> {
+        JwksValidator::validate(self, token, header)
+            .await
+            .map_err(|err| debug!("Token rejected: {err}"))
+            .ok()
+    }
+}
 
+/// Extracts the token of the request and validates it.
+async fn validate(
+    headers_state: &RequestHeadersState,
+    config: &Config,
+    claims_validator: &ClaimsValidator,
+    signature_validator: &impl TokenValidator,
+) -> Result<JWTClaims, TokenError> {
     // Extract token
-    let token = match TokenProvider::bearer(headers_state.handler()) {
-        Ok(t) => t,
-        Err(_) => {
-            return Flow::Break(Response::new(401).with_body("Bearer not found"));
-        }
-    };
+    let token =
+        TokenProvider::bearer(headers_state.handler()).map_err(|_| TokenError::MissingToken)?;
+
+    // Validate the algorithm before the signature
+    let header = claims_validator.validate_header(&token)?;
 
     // Validate signature
-    let claims = match signature_validator.validate(token) {
-        Ok(claims) => claims,
-        Err(_) => {
-            return Flow::Break(Response::new(401).with_body("Invalid token"));
-        }
-    };
+    let claims = signature_validator
+        .validate(token, &header)
+        .await
+        .ok_or(TokenError::InvalidSignature)?;
 
-    // Validate expiration
-    if let Some(exp) = claims.expiration() {
-        if exp < Utc::now() {
-            return Flow::Break(Response::new(401).with_body("Expired token"));
-        }
-    } else {
-        return Flow::Break(Response::new(401).with_body("Token missing exp claim"));
-    }
+    // Validate expiration, issuer, audience and required claims
+    claims_validator.validate_claims(&claims, Utc::now())?;
 
     // Custom claim validation
     let mut evaluator = config.custom_rule.evaluator();
@@ -51,36 +89,72 @@ async fn filter(
         .and_then(|value| value.as_bool())
         .unwrap_or_default()
     {
-        return Flow::Break(
-            Response::new(400).with_body("Invalid token: Only members are allowed."),
-        );
+        let message = config
+            .rule_failure_message
+            .as_deref()
+            .unwrap_or(DEFAULT_RULE_FAILURE_MESSAGE);
+        return Err(TokenError::RuleFailed(message.to_string()));
     }
 
-    // Access JWT payload on demand
-    if let Some(not_before) = claims.not_before() {
-        debug!("not_before is {}'", not_before);
-    }
+    Ok(claims)
+}
 
-    // Access JWT headers on demand
-    if let Some(alg_header) = claims.get_header("alg") {
-        debug!("alg header is {}", alg_header);
-    }
+async fn filter(
+    state: RequestState,
+    config: &Config,
+    claims_validator: &ClaimsValidator,
+    signature_validator: &impl TokenValidator,
+) -> Flow<()> {
+    let headers_state = state.into_headers_state().await;
+
+    let claims = match validate(
+        &headers_state,
+        config,
+        claims_validator,
+        signature_validator,
+    )
+    .await
+    {
+        Ok(claims) => claims,
+        Err(err) => {
+            debug!("Request rejected: {err}");
+            return Flow::Break(
+                Response::new(err.status_code())
+                    .with_headers([
+                        ("Content-Type".to_string(), "application/json".to_string()),
+                        ("WWW-Authenticate".to_string(), err.www_authenticate()),
+                    ])
+                    .with_body(
+                        json!({ "error": err.code(), "message": err.to_string() }).to_string(),
+                    ),
+            );
+        }
+    };
 
     // Propagate claims to headers
-    let some_custom_claim: Option<String> = claims.get_claim("username");
+    for mapping in config.claims_to_headers.iter().flatten() {
+        let value = claims::claim_value(&claims, &mapping.claim);
 
-    if let Some(custom_claim) = some_custom_claim {
-        headers_state
-            .handler()
-            .set_header("username", custom_claim.as_str());
+        match value.as_ref().and_then(claims::header_value) {
+            Some(value) => headers_state.handler().set_header(&mapping.header, &value),
+            // Clients must not be able to spoof the headers of the missing claims.
+            None => headers_state.handler().remove_header(&mapping.header),
+        }
     }
 
     Flow::Continue(())
 }
 
 #[entrypoint]
-async fn configure(launcher: Launcher, Configuration(configuration): Configuration) -> Result<()> {
-    let config: Config = serde_json::from_slice(&configuration).map_err(|err| {
+async fn configure(
+    launcher: Launcher,
+    Configuration(configuration): Configuration,
+    client: HttpClient,
+    cache: CacheBuilder, // Inject the cache to share the JWKS between the workers.
+    clock: Clock,        // Inject the clock to periodically refresh the JWKS.
+    lock: LockBuilder,   // Inject the lock to download the JWKS from a single worker.
+) -> Result<()> {
+    let mut config: Config = serde_json::from_slice(&configuration).map_err(|err| {
         anyhow!(
             "Failed to parse configuration '{}'. Cause: {}",
             String::from_utf8_lossy(&configuration),
@@ -88,24 +162,75 @@ async fn configure(launcher: Launcher, Configuration(configuration): Configurati
         )
     })?;
 
-    let signature_validator = SignatureValidator::new(
-        model::SigningAlgorithm::Hmac,
-        model::SigningKeyLength::Len256,
-        config.secret.clone(),
-    )?;
-
-    launcher
-        .launch(on_request(|request| {
-            filter(request, &config, &signature_validator)
-        }))
-        .await?;
+    // An explicit empty list disables the propagation of the claims.
+    config
+        .claims_to_headers
+        .get_or_insert_with(default_claims_to_headers);
+
+    let claims_validator = ClaimsValidator::from_config(&config)?;
+
+    let Some(service) = &config.jwks_url else {
+        let secret = config
+            .secret
+            .clone()
+            .ok_or_else(|| anyhow!("Either secret or jwksUrl must be configured."))?;
+
+        let signature_validator = SignatureValidator::new(
+            model::SigningAlgorithm::Hmac,
+            model::SigningKeyLength::Len256,
+            secret,
+        )?;
+
+        launcher
+            .launch(on_request(|request| {
+                filter(request, &config, &claims_validator, &signature_validator)
+            }))
+            .await?;
+        return Ok(());
+    };
+
+    let frequency = config
+        .jwks_refresh_interval
+        .map(|seconds| seconds as u64)
+        .unwrap_or(DEFAULT_JWKS_REFRESH_INTERVAL);
+
+    // The timer granularity defines how fast a new JWKS is propagated between workers.
+    let timer = clock.period(Duration::from_secs(1));
+
+    let jwks_validator = JwksValidator {
+        service,
+        client: &client,
+        // Cache to share the JWKS between workers.
+        cache: cache.new(jwks::ID.to_string()).build(),
+        // The lock expires after the download timeout so other workers can recover it.
+        lock: lock
+            .new(jwks::ID.to_string())
+            .expiration(Duration::from_secs(20))
+            .build(),
+        frequency: Duration::from_secs(frequency),
+        jwks: Jwks::default(),
+    };
+
+    let filter = on_request(|request| filter(request, &config, &claims_validator, &jwks_validator));
+
+    // Await for both futures to progress, propagating the error of the launcher.
+    let joined = join!(launcher.launch(filter), jwks_validator.fetch_loop(&timer));
+    joined.0?;
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use pdk_unit::{dw2pel, UnitHttpRequest, UnitTestBuilder};
+    use pdk::jwt::model::{JWTClaims, SigningAlgorithm, SigningKeyLength};
+    use pdk::jwt::JwtGenerator;
+    use pdk_unit::{
+        dw2pel, TraceBackend, UnitHttpMessage, UnitHttpRequest, UnitHttpResponse, UnitTestBuilder,
+    };
     use serde_json::json;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
 
     const SECRET: &str = "Make-sure-this-is-a-secret-dont-share-it";
 
@@ -170,4 +295,242 @@ mod tests {
 
         assert_eq!(response.status_code(), 401);
     }
+
+    #[test]
+    fn rejections_report_an_error_code() {
+        let config = json!({
+            "secret": SECRET,
+            "customRule": dw2pel("vars.claimSet.role == 'Member'"),
+            "issuers": ["Bookstore"],
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config.to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {VALID_TOKEN}")),
+        );
+
+        assert_eq!(response.status_code(), 401);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap(),
+            json!({ "error": "invalid_issuer", "message": "Issuer is not allowed" })
+        );
+    }
+
+    #[test]
+    fn claims_are_propagated_to_headers() {
+        let config = json!({
+            "secret": SECRET,
+            "customRule": dw2pel("vars.claimSet.role == 'Member'"),
+            "claimsToHeaders": [
+                { "claim": "username", "header": "x-username" },
+                { "claim": "role", "header": "x-role" },
+                { "claim": "groups.0", "header": "x-group" },
+            ],
+        });
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config.to_string())
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get()
+                .with_header("Authorization", format!("Bearer {VALID_TOKEN}"))
+                .with_header("x-group", "spoofed"),
+        );
+
+        assert_eq!(response.status_code(), 200);
+        let request = backend.next().unwrap();
+        assert_eq!(request.header("x-username"), Some("LibraryFan1984"));
+        assert_eq!(request.header("x-role"), Some("Member"));
+        assert_eq!(request.header("x-group"), None);
+    }
+
+    #[test]
+    fn rule_failures_return_403() {
+        let config = json!({
+            "secret": SECRET,
+            "customRule": dw2pel("vars.claimSet.role == 'Admin'"),
+            "ruleFailureMessage": "Only administrators are allowed.",
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config.to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {VALID_TOKEN}")),
+        );
+
+        assert_eq!(response.status_code(), 403);
+        assert_eq!(
+            response.header("WWW-Authenticate"),
+            Some(
+                r#"Bearer error="insufficient_scope", error_description="Only administrators are allowed.""#
+            )
+        );
+    }
+
+    const JWKS: &str = include_str!("../tests/resources/jwks.json");
+
+    fn jwks_config() -> String {
+        json!({
+            "jwksUrl": "http://jwks",
+            "customRule": dw2pel("vars.claimSet.role == 'Member'"),
+        })
+        .to_string()
+    }
+
+    /// Signs a token with one of the keys published in the test JWKS.
+    fn signed_token(algorithm: SigningAlgorithm, key: &str, kid: Option<&str>) -> String {
+        let claims = HashMap::from([
+            ("role".to_string(), "Member".into()),
+            ("exp".to_string(), 2704460407u64.into()),
+        ]);
+        let headers = kid
+            .map(|kid| HashMap::from([("kid".to_string(), kid.into())]))
+            .unwrap_or_default();
+
+        JwtGenerator::new(algorithm, SigningKeyLength::Len256, key)
+            .unwrap()
+            .jwt(JWTClaims::new(None, None, None, claims, headers).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn token_is_validated_with_the_jwks_key_of_its_kid() {
+        let fetches = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&fetches);
+        let mut tester = UnitTestBuilder::default()
+            .with_config(jwks_config())
+            .with_http_upstream_from_authority("jwks", move |_| {
+                counter.set(counter.get() + 1);
+                UnitHttpResponse::new(200).with_body(JWKS)
+            })
+            .with_entrypoint(crate::configure);
+
+        // The unknown kid forces the download of the JWKS.
+        let token = signed_token(
+            SigningAlgorithm::Rsa,
+            include_str!("../tests/resources/rsa.pem"),
+            Some("rsa"),
+        );
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {token}")),
+        );
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(fetches.get(), 1);
+
+        // Unknown kids can't force another download until the cooldown elapsed.
+        let token = signed_token(
+            SigningAlgorithm::Rsa,
+            include_str!("../tests/resources/rsa.pem"),
+            Some("rotated"),
+        );
+        for _ in 0..2 {
+            let response = tester.request(
+                UnitHttpRequest::get().with_header("Authorization", format!("Bearer {token}")),
+            );
+            assert_eq!(response.status_code(), 401);
+        }
+        assert_eq!(fetches.get(), 1);
+    }
+
+    #[test]
+    fn jwks_is_fetched_periodically() {
+        let fetches = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&fetches);
+        let mut tester = UnitTestBuilder::default()
+            .with_config(jwks_config())
+            .with_http_upstream_from_authority("jwks", move |_| {
+                counter.set(counter.get() + 1);
+                UnitHttpResponse::new(200).with_body(JWKS)
+            })
+            .with_entrypoint(crate::configure);
+
+        tester.tick();
+        assert_eq!(fetches.get(), 1);
+
+        // Without kid, the key is selected by algorithm among the loaded keys.
+        let token = signed_token(
+            SigningAlgorithm::Es,
+            include_str!("../tests/resources/ec.pem"),
+            None,
+        );
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {token}")),
+        );
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(fetches.get(), 1);
+    }
+
+    #[test]
+    fn token_without_kid_forces_the_download_of_the_jwks() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(jwks_config())
+            .with_http_upstream_from_authority("jwks", |_| {
+                UnitHttpResponse::new(200).with_body(JWKS)
+            })
+            .with_entrypoint(crate::configure);
+
+        let token = signed_token(
+            SigningAlgorithm::Es,
+            include_str!("../tests/resources/ec.pem"),
+            None,
+        );
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {token}")),
+        );
+        assert_eq!(response.status_code(), 200);
+    }
+
+    #[test]
+    fn unsupported_jwks_algorithms_are_rejected() {
+        let config = json!({
+            "jwksUrl": "http://jwks",
+            "allowedAlgorithms": ["RS256", "PS256"],
+        });
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config.to_string())
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get());
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn secret_without_hs256_is_rejected() {
+        for algorithms in [json!(["RS256"]), json!([])] {
+            let config = json!({
+                "secret": SECRET,
+                "allowedAlgorithms": algorithms,
+            });
+            let mut tester = UnitTestBuilder::default()
+                .with_config(config.to_string())
+                .with_entrypoint(crate::configure);
+
+            let response = tester.request(UnitHttpRequest::get());
+            assert_eq!(response.status_code(), 503);
+        }
+    }
+
+    #[test]
+    fn username_is_propagated_by_default() {
+        let backend = Rc::new(TraceBackend::new(UnitHttpResponse::new(200)));
+        let mut tester = UnitTestBuilder::default()
+            .with_config(config())
+            .with_backend(Rc::clone(&backend))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(
+            UnitHttpRequest::get().with_header("Authorization", format!("Bearer {VALID_TOKEN}")),
+        );
+
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            backend.next().unwrap().header("username"),
+            Some("LibraryFan1984")
+        );
+    }
 }
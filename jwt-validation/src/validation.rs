@@ -0,0 +1,355 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Validation of the token algorithm and of its standard claims.
+
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use pdk::jwt::model::JWTClaims;
+use serde::Deserialize;
+
+use crate::generated::config::Config;
+
+/// Algorithms accepted with the HMAC secret.
+const SECRET_ALGORITHMS: &[&str] = &["HS256"];
+
+/// Algorithms accepted with the JWKS keys.
+const JWKS_ALGORITHMS: &[&str] = &["RS256", "RS384", "RS512", "ES256", "ES384"];
+
+/// Header of the token, read before validating its signature.
+#[derive(Deserialize)]
+pub struct TokenHeader {
+    pub alg: String,
+    pub kid: Option<String>,
+}
+
+impl TokenHeader {
+    pub fn parse(token: &str) -> Result<Self> {
+        let header = token
+            .split('.')
+            .next()
+            .ok_or_else(|| anyhow!("Malformed token."))?;
+        let header = BASE64_URL_SAFE_NO_PAD.decode(header)?;
+        Ok(serde_json::from_slice(&header)?)
+    }
+}
+
+/// Reasons to reject a request, each one with its own error code.
+#[derive(Debug, PartialEq)]
+pub enum TokenError {
+    MissingToken,
+    MalformedToken,
+    InvalidAlgorithm(String),
+    InvalidSignature,
+    MissingExpiration,
+    Expired,
+    NotYetValid,
+    IssuedInFuture,
+    InvalidIssuer,
+    InvalidAudience,
+    MissingClaim(String),
+    /// The custom rule rejected the token, with the configured message.
+    RuleFailed(String),
+}
+
+impl TokenError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MissingToken => "missing_token",
+            Self::MalformedToken => "malformed_token",
+            Self::InvalidAlgorithm(_) => "invalid_algorithm",
+            Self::InvalidSignature => "invalid_signature",
+            Self::MissingExpiration => "missing_expiration",
+            Self::Expired => "token_expired",
+            Self::NotYetValid => "token_not_yet_valid",
+            Self::IssuedInFuture => "invalid_issued_at",
+            Self::InvalidIssuer => "invalid_issuer",
+            Self::InvalidAudience => "invalid_audience",
+            Self::MissingClaim(_) => "missing_claim",
+            Self::RuleFailed(_) => "rule_failed",
+        }
+    }
+
+    pub fn status_code(&self) -> u32 {
+        match self {
+            Self::RuleFailed(_) => 403,
+            _ => 401,
+        }
+    }
+
+    /// Value of the `WWW-Authenticate` header, as defined by RFC 6750.
+    pub fn www_authenticate(&self) -> String {
+        let error = match self {
+            // Requests without credentials receive no error code.
+            Self::MissingToken => return "Bearer".to_string(),
+            Self::RuleFailed(_) => "insufficient_scope",
+            _ => "invalid_token",
+        };
+
+        // The description only admits printable ASCII characters other than quotes and backslashes.
+        let description: String = self
+            .to_string()
+            .chars()
+            .filter(|c| matches!(c, ' '..='~') && !matches!(c, '"' | '\\'))
+            .collect();
+
+        format!(r#"Bearer error="{error}", error_description="{description}""#)
+    }
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingToken => write!(f, "Bearer not found"),
+            Self::MalformedToken => write!(f, "Malformed token"),
+            Self::InvalidAlgorithm(alg) => write!(f, "Algorithm {alg} is not allowed"),
+            Self::InvalidSignature => write!(f, "Invalid token"),
+            Self::MissingExpiration => write!(f, "Token missing exp claim"),
+            Self::Expired => write!(f, "Expired token"),
+            Self::NotYetValid => write!(f, "Token not yet valid"),
+            Self::IssuedInFuture => write!(f, "Token issued in the future"),
+            Self::InvalidIssuer => write!(f, "Issuer is not allowed"),
+            Self::InvalidAudience => write!(f, "Audience is not allowed"),
+            Self::MissingClaim(claim) => write!(f, "Token missing {claim} claim"),
+            Self::RuleFailed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// Checks the algorithm and the standard claims of the tokens.
+pub struct ClaimsValidator {
+    algorithms: Vec<String>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    required_claims: Vec<String>,
+    leeway: Duration,
+}
+
+impl ClaimsValidator {
+    /// Creates the [ClaimsValidator] from a [Config].
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let algorithms = match (&config.allowed_algorithms, &config.jwks_url) {
+            (Some(algorithms), _) => algorithms.clone(),
+            (None, Some(_)) => JWKS_ALGORITHMS.iter().map(|alg| alg.to_string()).collect(),
+            (None, None) => SECRET_ALGORITHMS
+                .iter()
+                .map(|alg| alg.to_string())
+                .collect(),
+        };
+
+        // Unsigned tokens are never accepted.
+        if algorithms
+            .iter()
+            .any(|alg| alg.eq_ignore_ascii_case("none"))
+        {
+            return Err(anyhow!("Algorithm 'none' can't be allowed."));
+        }
+
+        // The keys of the JWKS can only validate the algorithms supported by the PDK.
+        if config.jwks_url.is_some() {
+            if let Some(alg) = algorithms
+                .iter()
+                .find(|alg| !JWKS_ALGORITHMS.contains(&alg.as_str()))
+            {
+                return Err(anyhow!("Algorithm '{alg}' is not supported with jwksUrl."));
+            }
+        } else {
+            // The secret only validates HMAC signatures, any other list would reject every token.
+            if let Some(alg) = algorithms
+                .iter()
+                .find(|alg| !SECRET_ALGORITHMS.contains(&alg.as_str()))
+            {
+                return Err(anyhow!("Algorithm '{alg}' is not supported with secret."));
+            }
+            if algorithms.is_empty() {
+                return Err(anyhow!("Algorithm HS256 must be allowed with secret."));
+            }
+        }
+
+        let leeway = match config.leeway {
+            Some(leeway) if leeway < 0 => return Err(anyhow!("Invalid leeway {leeway}.")),
+            leeway => Duration::seconds(leeway.unwrap_or_default()),
+        };
+
+        Ok(Self {
+            algorithms,
+            issuers: config.issuers.clone().unwrap_or_default(),
+            audiences: config.audiences.clone().unwrap_or_default(),
+            required_claims: config.required_claims.clone().unwrap_or_default(),
+            leeway,
+        })
+    }
+
+    /// Checks the algorithm of the token before validating its signature.
+    pub fn validate_header(&self, token: &str) -> Result<TokenHeader, TokenError> {
+        let header = TokenHeader::parse(token).map_err(|_| TokenError::MalformedToken)?;
+
+        if !self.algorithms.contains(&header.alg) {
+            return Err(TokenError::InvalidAlgorithm(header.alg));
+        }
+        Ok(header)
+    }
+
+    /// Checks the time, issuer, audience and required claims of a token with a valid signature.
+    pub fn validate_claims(
+        &self,
+        claims: &JWTClaims,
+        now: DateTime<Utc>,
+    ) -> Result<(), TokenError> {
+        let exp = claims.expiration().ok_or(TokenError::MissingExpiration)?;
+        if exp + self.leeway < now {
+            return Err(TokenError::Expired);
+        }
+
+        if claims
+            .not_before()
+            .is_some_and(|nbf| nbf - self.leeway > now)
+        {
+            return Err(TokenError::NotYetValid);
+        }
+
+        if claims
+            .issued_at()
+            .is_some_and(|iat| iat - self.leeway > now)
+        {
+            return Err(TokenError::IssuedInFuture);
+        }
+
+        if !self.issuers.is_empty()
+            && !claims
+                .issuer()
+                .is_some_and(|issuer| self.issuers.contains(&issuer))
+        {
+            return Err(TokenError::InvalidIssuer);
+        }
+
+        if !self.audiences.is_empty() {
+            let audiences = match claims.audience() {
+                Some(Ok(audiences)) => audiences,
+                _ => Vec::new(),
+            };
+            if !audiences.iter().any(|aud| self.audiences.contains(aud)) {
+                return Err(TokenError::InvalidAudience);
+            }
+        }
+
+        if let Some(missing) = self
+            .required_claims
+            .iter()
+            .find(|claim| !claims.has_claim(claim))
+        {
+            return Err(TokenError::MissingClaim(missing.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClaimsValidator, TokenError};
+    use chrono::{Duration, TimeZone, Utc};
+    use pdk::jwt::model::JWTClaims;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    fn validator() -> ClaimsValidator {
+        ClaimsValidator {
+            algorithms: vec!["HS256".to_string()],
+            issuers: vec!["Library".to_string()],
+            audiences: vec!["member-group".to_string()],
+            required_claims: vec!["sub".to_string()],
+            leeway: Duration::seconds(60),
+        }
+    }
+
+    fn claims(values: &[(&str, Value)]) -> JWTClaims {
+        let claims = values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        JWTClaims::new(None, None, None, claims, HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn standard_claims_are_validated() {
+        let now = Utc.timestamp_opt(1_000_000, 0).unwrap();
+        let validate = |values: &[(&str, Value)]| {
+            let mut all = vec![
+                ("exp", 1_000_100.into()),
+                ("iss", "Library".into()),
+                ("aud", vec!["other", "member-group"].into()),
+                ("sub", "12345".into()),
+            ];
+            all.retain(|(name, _)| !values.iter().any(|(other, _)| other == name));
+            all.extend(values.iter().cloned());
+            validator().validate_claims(&claims(&all), now)
+        };
+
+        assert_eq!(validate(&[]), Ok(()));
+        // Within the leeway.
+        assert_eq!(validate(&[("exp", 999_950.into())]), Ok(()));
+        assert_eq!(validate(&[("nbf", 1_000_050.into())]), Ok(()));
+
+        assert_eq!(
+            validate(&[("exp", 999_900.into())]),
+            Err(TokenError::Expired)
+        );
+        assert_eq!(
+            validate(&[("nbf", 1_000_100.into())]),
+            Err(TokenError::NotYetValid)
+        );
+        assert_eq!(
+            validate(&[("iat", 1_000_100.into())]),
+            Err(TokenError::IssuedInFuture)
+        );
+        assert_eq!(
+            validate(&[("iss", "Other".into())]),
+            Err(TokenError::InvalidIssuer)
+        );
+        assert_eq!(
+            validate(&[("aud", "other".into())]),
+            Err(TokenError::InvalidAudience)
+        );
+        assert_eq!(
+            validator().validate_claims(&claims(&[("exp", 1_000_100.into())]), now),
+            Err(TokenError::InvalidIssuer)
+        );
+    }
+
+    #[test]
+    fn unexpected_algorithms_are_rejected() {
+        let none = "eyJhbGciOiJub25lIn0.eyJzdWIiOiIxIn0.";
+        let rs256 = "eyJhbGciOiJSUzI1NiJ9.eyJzdWIiOiIxIn0.c2ln";
+
+        assert_eq!(
+            validator().validate_header(none).err(),
+            Some(TokenError::InvalidAlgorithm("none".to_string()))
+        );
+        assert_eq!(
+            validator().validate_header(rs256).err(),
+            Some(TokenError::InvalidAlgorithm("RS256".to_string()))
+        );
+        assert_eq!(
+            validator().validate_header("invalid.jwt.token").err(),
+            Some(TokenError::MalformedToken)
+        );
+    }
+
+    #[test]
+    fn errors_follow_rfc_6750() {
+        assert_eq!(TokenError::MissingToken.www_authenticate(), "Bearer");
+        assert_eq!(
+            TokenError::Expired.www_authenticate(),
+            r#"Bearer error="invalid_token", error_description="Expired token""#
+        );
+
+        let rule_failed = TokenError::RuleFailed("Only \"members\" allowed ✓".to_string());
+        assert_eq!(rule_failed.status_code(), 403);
+        assert_eq!(
+            rule_failed.www_authenticate(),
+            r#"Bearer error="insufficient_scope", error_description="Only members allowed ""#
+        );
+    }
+}
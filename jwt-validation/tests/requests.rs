@@ -2,7 +2,11 @@
 
 mod common;
 
+use std::collections::HashMap;
+
 use httpmock::MockServer;
+use pdk::jwt::model::{JWTClaims, SigningAlgorithm, SigningKeyLength};
+use pdk::jwt::JwtGenerator;
 use pdk_test::port::Port;
 use pdk_test::services::flex::{ApiConfig, Flex, FlexConfig, PolicyConfig};
 use pdk_test::services::httpmock::{HttpMock, HttpMockConfig};
@@ -112,7 +116,7 @@ async fn validate_token() -> anyhow::Result<()> {
     assert_request(
         flex_url.as_str(),
         &admin_token(),
-        StatusCode::BAD_REQUEST,
+        StatusCode::FORBIDDEN,
         "Invalid token: Only members are allowed",
     )
     .await?;
@@ -120,6 +124,99 @@ async fn validate_token() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[pdk_test]
+async fn validate_token_with_jwks() -> anyhow::Result<()> {
+    // The HttpMock service acts as both the upstream and the identity provider publishing the JWKS
+    let upstream_config = HttpMockConfig::builder()
+        .port(80)
+        .hostname("backend")
+        .build();
+
+    let policy_config = PolicyConfig::builder()
+        .name(POLICY_NAME)
+        .configuration(json!({
+            "jwksUrl": "http://backend/jwks.json",
+            "customRule": "#[vars.claimSet.role=='Member']"
+        }))
+        .build();
+
+    let api_config = ApiConfig::builder()
+        .name("ingress-http")
+        .upstream(&upstream_config)
+        .path("/anything/echo/")
+        .port(FLEX_PORT)
+        .policies([policy_config])
+        .build();
+
+    let flex_config = FlexConfig::builder()
+        .version("1.10.0")
+        .hostname("local-flex")
+        .with_api(api_config)
+        .config_mounts([(POLICY_DIR, "policy"), (COMMON_CONFIG_DIR, "common")])
+        .build();
+
+    let composite = TestComposite::builder()
+        .with_service(flex_config)
+        .with_service(upstream_config)
+        .build()
+        .await?;
+
+    let flex: Flex = composite.service()?;
+    let flex_url = flex.external_url(FLEX_PORT).unwrap();
+    let upstream: HttpMock = composite.service()?;
+    let upstream_server = MockServer::connect_async(upstream.socket()).await;
+
+    upstream_server
+        .mock_async(|when, then| {
+            when.path("/jwks.json");
+            then.status(200).body(include_str!("resources/jwks.json"));
+        })
+        .await;
+
+    upstream_server
+        .mock_async(|when, then| {
+            when.path_contains("/hello");
+            then.status(200).body("Hello World!");
+        })
+        .await;
+
+    assert_request(
+        flex_url.as_str(),
+        &jwks_token("rsa", "Member"),
+        StatusCode::OK,
+        "Hello World!",
+    )
+    .await?;
+
+    assert_request(
+        flex_url.as_str(),
+        &jwks_token("unknown", "Member"),
+        StatusCode::UNAUTHORIZED,
+        "Invalid token",
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Returns a token signed with the RSA key published in the test JWKS
+fn jwks_token(kid: &str, role: &str) -> String {
+    let claims = HashMap::from([
+        ("role".to_string(), role.into()),
+        ("exp".to_string(), 2704460407u64.into()),
+    ]);
+    let headers = HashMap::from([("kid".to_string(), kid.into())]);
+
+    JwtGenerator::new(
+        SigningAlgorithm::Rsa,
+        SigningKeyLength::Len256,
+        include_str!("resources/rsa.pem"),
+    )
+    .unwrap()
+    .jwt(JWTClaims::new(None, None, None, claims, headers).unwrap())
+    .unwrap()
+}
+
 /// Returns valid JWT token signed using the configured HMAC secret
 /// Headers
 /// {"alg": "HS256", "typ": "JWT", "classid": 439}
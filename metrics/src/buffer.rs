@@ -0,0 +1,170 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Bounded buffer of the request counters waiting to be published.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Series discarded when the buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Discards the series buffered first, such as the ones of failed publications.
+    Oldest,
+    /// Keeps the buffered series and discards the new ones.
+    Newest,
+}
+
+/// Counters of up to `capacity` series, ordered from the oldest to the newest.
+pub struct Counters<K> {
+    order: VecDeque<K>,
+    counts: HashMap<K, u64>,
+    capacity: usize,
+    policy: DropPolicy,
+    dropped: u64,
+}
+
+impl<K: Clone + Eq + Hash> Counters<K> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            order: VecDeque::new(),
+            counts: HashMap::new(),
+            capacity,
+            policy,
+            dropped: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Number of requests discarded since the buffer was created.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Counts new requests of a series.
+    pub fn add(&mut self, key: K, count: u64) {
+        if let Some(current) = self.counts.get_mut(&key) {
+            *current += count;
+            return;
+        }
+
+        if self.order.len() >= self.capacity {
+            let evicted = match self.policy {
+                DropPolicy::Oldest => self.order.pop_front(),
+                DropPolicy::Newest => {
+                    self.dropped += count;
+                    return;
+                }
+            };
+            self.evict(evicted);
+        }
+        self.order.push_back(key.clone());
+        self.counts.insert(key, count);
+    }
+
+    /// Merges back the counters of a failed publication, which are older than the buffered ones.
+    pub fn restore(&mut self, snapshot: HashMap<K, u64>) {
+        for (key, count) in snapshot {
+            if let Some(current) = self.counts.get_mut(&key) {
+                *current += count;
+                continue;
+            }
+
+            if self.order.len() >= self.capacity {
+                let evicted = match self.policy {
+                    DropPolicy::Oldest => {
+                        self.dropped += count;
+                        continue;
+                    }
+                    DropPolicy::Newest => self.order.pop_back(),
+                };
+                self.evict(evicted);
+            }
+            self.order.push_front(key.clone());
+            self.counts.insert(key, count);
+        }
+    }
+
+    /// Removes and returns the buffered counters.
+    pub fn take(&mut self) -> HashMap<K, u64> {
+        self.order.clear();
+        std::mem::take(&mut self.counts)
+    }
+
+    /// Removes and returns the buffered counters, ordered from the oldest to the newest.
+    pub fn drain(&mut self) -> Vec<(K, u64)> {
+        let mut counts = std::mem::take(&mut self.counts);
+        self.order
+            .drain(..)
+            .filter_map(|key| {
+                let count = counts.remove(&key)?;
+                Some((key, count))
+            })
+            .collect()
+    }
+
+    fn evict(&mut self, key: Option<K>) {
+        if let Some(count) = key.and_then(|key| self.counts.remove(&key)) {
+            self.dropped += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counters, DropPolicy};
+    use std::collections::HashMap;
+
+    #[test]
+    fn full_buffers_drop_the_oldest_series() {
+        let mut counters = Counters::new(2, DropPolicy::Oldest);
+        counters.add("get", 1);
+        counters.add("post", 2);
+        counters.add("get", 1);
+        counters.add("put", 4);
+
+        assert_eq!(counters.dropped(), 2);
+        let failed = counters.take();
+        assert_eq!(failed, HashMap::from([("post", 2), ("put", 4)]));
+        assert!(counters.is_empty());
+
+        // Failed counters are older than the new ones.
+        counters.add("put", 1);
+        counters.add("delete", 8);
+        counters.restore(failed);
+
+        assert_eq!(counters.dropped(), 4);
+        assert_eq!(counters.take(), HashMap::from([("put", 5), ("delete", 8)]));
+    }
+
+    #[test]
+    fn full_buffers_drop_the_newest_series() {
+        let mut counters = Counters::new(2, DropPolicy::Newest);
+        counters.add("get", 1);
+        counters.add("post", 2);
+        counters.add("put", 4);
+
+        assert_eq!(counters.dropped(), 4);
+        let failed = counters.take();
+
+        counters.add("delete", 8);
+        counters.add("patch", 16);
+        counters.restore(failed);
+
+        assert_eq!(counters.dropped(), 4 + 8 + 16);
+        assert_eq!(counters.take(), HashMap::from([("get", 1), ("post", 2)]));
+    }
+
+    #[test]
+    fn drained_series_keep_their_order() {
+        let mut counters = Counters::new(3, DropPolicy::Oldest);
+        counters.add("get", 1);
+        counters.add("post", 2);
+        counters.add("get", 1);
+        counters.restore(HashMap::from([("put", 4)]));
+
+        assert_eq!(counters.drain(), vec![("put", 4), ("get", 2), ("post", 2)]);
+        assert!(counters.is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Request counters merged by the workers of a gateway instance through the shared cache, so a
+//! single worker publishes them each period.
+
+use std::time::{Duration, SystemTime};
+
+use pdk::cache::Cache;
+use pdk::lock::TryLock;
+use pdk::logger;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{Counters, DropPolicy};
+use crate::Counts;
+
+/// Key for the cache entry that keeps the counters merged by the workers.
+const DATA_KEY: &str = "data";
+/// Key for the cache entry that keeps the time of the last report.
+const LAST_REPORT: &str = "lastReport";
+/// Key for the cache entry that keeps the node that identifies the gateway instance.
+const NODE_KEY: &str = "node";
+
+/// Counters shared between the workers.
+#[derive(Serialize, Deserialize, Default)]
+struct Shared {
+    /// Series ordered from the oldest to the newest.
+    counts: Vec<(String, u32, u64)>,
+    /// Requests dropped by the workers since the gateway instance started.
+    dropped: u64,
+}
+
+/// Counters of the gateway instance taken by the worker that publishes them.
+pub struct Aggregate {
+    pub node: String,
+    pub counts: Counts,
+    pub dropped: u64,
+}
+
+/// Counters of the gateway instance.
+pub struct Cluster<C> {
+    cache: C,
+    lock: TryLock,
+    period: Duration,
+    /// Maximum series of the shared counters, as the buffer of each worker.
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<C: Cache> Cluster<C> {
+    pub fn new(
+        cache: C,
+        lock: TryLock,
+        period: Duration,
+        capacity: usize,
+        policy: DropPolicy,
+    ) -> Self {
+        Self {
+            cache,
+            lock,
+            period,
+            capacity,
+            policy,
+        }
+    }
+
+    /// Merges the counters of the worker with the ones of the other workers. When the period
+    /// elapsed since the last report, this worker is elected to publish the next one and takes the
+    /// merged counters. The counters are returned back when another worker holds the lock.
+    pub fn merge(
+        &self,
+        node: &str,
+        counts: Counts,
+        dropped: u64,
+        now: SystemTime,
+    ) -> Result<Option<Aggregate>, Counts> {
+        // Acquire the lock to ensure only one worker modifies the shared counters at a time.
+        let Some(_acquired) = self.lock.try_lock() else {
+            return Err(counts);
+        };
+
+        let mut shared: Shared = self
+            .get(DATA_KEY)
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        // The shared counters are bounded like the buffer of each worker, whose series are newer.
+        let mut merged = Counters::new(self.capacity, self.policy);
+        for (method, status, count) in shared.counts.drain(..) {
+            merged.add((method, status), count);
+        }
+        for (key, count) in counts {
+            merged.add(key, count);
+        }
+        shared.dropped += dropped + merged.dropped();
+
+        // The workers tick around the same time each period, so the first one after half a period
+        // publishes the report.
+        let due = self
+            .get(LAST_REPORT)
+            .and_then(|data| serde_json::from_slice::<SystemTime>(&data).ok())
+            .and_then(|last| now.duration_since(last).ok())
+            .is_none_or(|elapsed| elapsed >= self.period / 2);
+
+        let aggregate = if due && !merged.is_empty() {
+            self.save(LAST_REPORT, serde_json::to_vec(&now).unwrap_or_default());
+            Some(Aggregate {
+                node: self.node(node),
+                counts: merged.take(),
+                dropped: shared.dropped,
+            })
+        } else {
+            shared.counts = merged
+                .drain()
+                .into_iter()
+                .map(|((method, status), count)| (method, status, count))
+                .collect();
+            None
+        };
+
+        self.save(DATA_KEY, serde_json::to_vec(&shared).unwrap_or_default());
+        Ok(aggregate)
+    }
+
+    /// Node of the gateway instance, which is the one of the first worker that publishes.
+    fn node(&self, node: &str) -> String {
+        if let Some(node) = self.get(NODE_KEY) {
+            return String::from_utf8_lossy(&node).to_string();
+        }
+        self.save(NODE_KEY, node.as_bytes().to_vec());
+        node.to_string()
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.cache.get(key)
+    }
+
+    fn save(&self, key: &str, value: Vec<u8>) {
+        if let Err(error) = self.cache.save(key, value) {
+            logger::warn!("Unexpected error sharing the metrics with the other workers: {error}.");
+        }
+    }
+}
@@ -1,12 +1,28 @@
 use serde::Deserialize;
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(alias = "aggregateWorkers")]
+    pub aggregate_workers: Option<bool>,
+    #[serde(alias = "bufferSize")]
+    pub buffer_size: Option<i64>,
+    #[serde(alias = "dropPolicy")]
+    pub drop_policy: Option<String>,
+    #[serde(alias = "exportFormat")]
+    pub export_format: Option<String>,
+    #[serde(alias = "latencyBuckets")]
+    pub latency_buckets: Option<Vec<f64>>,
     #[serde(alias = "maxRetries")]
     pub max_retries: Option<i64>,
     #[serde(alias = "metricsSink", deserialize_with = "pdk::serde::deserialize_service")]
     pub metrics_sink: pdk::hl::Service,
+    #[serde(alias = "pathTemplates")]
+    pub path_templates: Option<Vec<String>>,
     #[serde(alias = "pushFrequency")]
     pub push_frequency: i64,
+    #[serde(alias = "scrapePath")]
+    pub scrape_path: Option<String>,
+    #[serde(alias = "temporality")]
+    pub temporality: Option<String>,
 }
 #[pdk::hl::entrypoint_flex]
 fn init(abi: &dyn pdk::flex_abi::api::FlexAbi) -> Result<(), anyhow::Error> {
@@ -1,21 +1,82 @@
 // Copyright 2023 Salesforce, Inc. All rights reserved.
+mod buffer;
+mod cluster;
 mod generated;
+mod otlp;
+mod prometheus;
+mod templates;
 
 use anyhow::{anyhow, Result};
 use futures::join;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::ops::{Deref, DerefMut};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
+use pdk::authentication::{Authentication, AuthenticationHandler};
+use pdk::cache::{Cache, CacheBuilder};
 use pdk::hl::timer::{Clock, Timer};
 use pdk::hl::*;
+use pdk::lock::LockBuilder;
 use pdk::logger;
+use pdk::metadata::Metadata;
+use rand::Rng;
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 
+use crate::buffer::{Counters, DropPolicy};
+use crate::cluster::{Aggregate, Cluster};
 use crate::generated::config::Config;
+use crate::otlp::{Encoding, Exporter, Temporality};
+use crate::prometheus::{Labels, Observation, Registry, CONTENT_TYPE, DEFAULT_BUCKETS};
+use crate::templates::PathTemplates;
+
+/// Identifier for the cache and the lock to share the metrics between workers.
+const ID: &str = "metrics";
+
+/// Label of the requests without an authenticated consumer.
+const ANONYMOUS: &str = "anonymous";
+
+/// Export format of the policy's own JSON payloads.
+const JSON_FORMAT: &str = "json";
+
+/// Export format of the OTLP/HTTP payloads encoded with protobuf.
+const OTLP_PROTOBUF_FORMAT: &str = "otlpProtobuf";
+
+/// Export format of the OTLP/HTTP payloads encoded with JSON.
+const OTLP_JSON_FORMAT: &str = "otlpJson";
+
+/// Temporality of the OTLP payloads that carry the totals since the policy started.
+const CUMULATIVE: &str = "cumulative";
+
+/// Temporality of the OTLP payloads that carry the increments since the previous export.
+const DELTA: &str = "delta";
+
+/// Content type of the policy's own JSON payloads.
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Drop policy that discards the series buffered first.
+const DROP_OLDEST: &str = "oldest";
+
+/// Drop policy that discards the new series.
+const DROP_NEWEST: &str = "newest";
+
+/// Maximum series buffered when no buffer size is configured.
+const DEFAULT_BUFFER_SIZE: usize = 1000;
+
+/// Delay before the first retry of a failed publication.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Request counters of each method and status code.
+type Counts = HashMap<(String, u32), u64>;
+
+/// Data collected from the request to track it along with its response.
+struct RequestInfo {
+    method: String,
+    path: String,
+    started: SystemTime,
+    request_bytes: u64,
+}
 
 /// This struct will collect the data of the incoming requests and serializes them to send to the metrics service.
 /// It uses interior mutability pattern to hide the complexity of metrics collection and consumption across different scopes.
@@ -23,21 +84,24 @@ struct Metrics {
     node: String,
     // Each worker is single threaded so no need for locking mechanism, as long as the mutable
     // reference is released before the next 'await' directive.
-    data: RefCell<HashMap<(String, u32), u64>>,
+    data: RefCell<Counters<(String, u32)>>,
+    // Requests dropped by the worker that were merged with the other workers.
+    merged_dropped: Cell<u64>,
 }
 
 impl Metrics {
-    /// Create a new metrics collector with an id.
-    pub fn new(node: String) -> Self {
+    /// Create a new metrics collector with an id, which buffers up to `capacity` series.
+    pub fn new(node: String, capacity: usize, policy: DropPolicy) -> Self {
         Self {
             node,
-            data: RefCell::new(HashMap::new()),
+            data: RefCell::new(Counters::new(capacity, policy)),
+            merged_dropped: Cell::new(0),
         }
     }
 
     /// Indicates that a new request was made with the specific method and status code response.
     pub fn track(&self, method: String, status: u32) {
-        Self::increment(self.data.borrow_mut().deref_mut(), (method, status), 1);
+        self.data.borrow_mut().add((method, status), 1);
     }
 
     /// returns true if there is at information of at least 1 tracked request.
@@ -45,9 +109,43 @@ impl Metrics {
         self.data.borrow().is_empty()
     }
 
-    /// Clears the data collected to start collecting new one.
-    pub fn clear(&self) {
-        self.data.replace(HashMap::new());
+    /// Removes the data collected to start collecting new one.
+    pub fn take(&self) -> Counts {
+        self.data.borrow_mut().take()
+    }
+
+    /// Merges back the data of a failed publication to send it in the next one.
+    pub fn restore(&self, counts: Counts) {
+        self.data.borrow_mut().restore(counts);
+    }
+
+    /// Serializes the collected data to json format.
+    pub fn report(&self, counts: &Counts) -> Vec<u8> {
+        Report {
+            node: &self.node,
+            counts,
+            dropped: self.data.borrow().dropped(),
+        }
+        .to_vec()
+    }
+
+    /// Merges the collected data with the one of the other workers of the gateway instance.
+    /// Returns the merged data when this worker is elected to publish it.
+    pub fn aggregate(&self, cluster: &Cluster<impl Cache>, now: SystemTime) -> Option<Aggregate> {
+        let dropped = self.data.borrow().dropped();
+        let unmerged = dropped - self.merged_dropped.get();
+
+        match cluster.merge(&self.node, self.take(), unmerged, now) {
+            Ok(aggregate) => {
+                self.merged_dropped.set(dropped);
+                aggregate
+            }
+            // Another worker is merging its data, so we keep ours for the next cycle.
+            Err(counts) => {
+                self.restore(counts);
+                None
+            }
+        }
     }
 
     /// A function that given a map, a key and a number will increment the value stored in it.
@@ -57,29 +155,43 @@ impl Metrics {
         Key: Hash,
     {
         let entry = map.entry(key).or_insert_with(|| 0);
-        *entry = *entry + count;
+        *entry += count;
+    }
+}
+
+/// Data collected during one or more cycles, along with the number of requests dropped because
+/// the buffer was full.
+struct Report<'a> {
+    node: &'a str,
+    counts: &'a Counts,
+    dropped: u64,
+}
+
+impl Report<'_> {
+    fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_else(|_| b"{}".to_vec())
     }
 }
 
 /// Implement serialize for the collected metrics.
 /// Here we process the data to adapt to the metrics server.
-impl Serialize for Metrics {
+impl Serialize for Report<'_> {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let data = self.data.borrow();
         let mut method_count: HashMap<&str, u64> = HashMap::new();
         let mut status_count: HashMap<u32, u64> = HashMap::new();
-        for ((method, status), count) in data.deref() {
-            Self::increment(&mut method_count, method, *count);
-            Self::increment(&mut status_count, *status, *count);
+        for ((method, status), count) in self.counts {
+            Metrics::increment(&mut method_count, method, *count);
+            Metrics::increment(&mut status_count, *status, *count);
         }
 
-        let mut s = serializer.serialize_struct("Metrics", 3)?;
-        s.serialize_field("node", &self.node)?;
+        let mut s = serializer.serialize_struct("Metrics", 4)?;
+        s.serialize_field("node", self.node)?;
         s.serialize_field("methods", &method_count)?;
         s.serialize_field("status_codes", &status_count)?;
+        s.serialize_field("dropped", &self.dropped)?;
 
         s.end()
     }
@@ -87,19 +199,31 @@ impl Serialize for Metrics {
 
 /// Function that will send the provided serialized body to the metrics server. Returns true if
 /// the request was successful or false otherwise.
-async fn publish_metrics(client: &HttpClient, config: &Config, body: &str) -> bool {
+async fn publish_metrics(
+    client: &HttpClient,
+    config: &Config,
+    body: &[u8],
+    content_type: &str,
+) -> bool {
     let response = client
         .request(&config.metrics_sink)
         .timeout(Duration::from_secs(10))
-        .body(body.as_bytes())
-        .headers(vec![("content-type", "application/json")])
+        .body(body)
+        .headers(vec![("content-type", content_type)])
         .post()
         .await;
 
     match response {
         Ok(resp) => {
             if [200, 202, 204].contains(&resp.status_code()) {
-                logger::debug!("Metrics posted successfully! {}", body);
+                if content_type == Encoding::Protobuf.content_type() {
+                    logger::debug!("Metrics posted successfully! {} bytes", body.len());
+                } else {
+                    logger::debug!(
+                        "Metrics posted successfully! {}",
+                        String::from_utf8_lossy(body)
+                    );
+                }
                 true
             } else {
                 logger::warn!(
@@ -117,53 +241,194 @@ async fn publish_metrics(client: &HttpClient, config: &Config, body: &str) -> bo
     }
 }
 
+/// Serialized metrics, along with the data needed to merge them back if they can't be published.
+struct Payload {
+    body: Vec<u8>,
+    content_type: &'static str,
+    /// Data of the policy's own JSON payloads. The OTLP exporter keeps its own.
+    counts: Option<Counts>,
+}
+
+/// Serializes the metrics collected since the previous cycle. Returns `None` when there is
+/// nothing to send.
+fn payload(
+    metrics: &Metrics,
+    registry: &Registry,
+    exporter: Option<&Exporter>,
+    cluster: Option<&Cluster<impl Cache>>,
+    now: SystemTime,
+) -> Option<Payload> {
+    // The OTLP payloads are built from the registry.
+    if let Some(exporter) = exporter {
+        return Some(Payload {
+            body: exporter.export(registry.snapshot(), registry.buckets())?,
+            content_type: exporter.content_type(),
+            counts: None,
+        });
+    }
+
+    // A single worker publishes the metrics of the gateway instance.
+    if let Some(cluster) = cluster {
+        let aggregate = metrics.aggregate(cluster, now)?;
+        let body = Report {
+            node: &aggregate.node,
+            counts: &aggregate.counts,
+            dropped: aggregate.dropped,
+        }
+        .to_vec();
+        return Some(Payload {
+            body,
+            content_type: JSON_CONTENT_TYPE,
+            counts: Some(aggregate.counts),
+        });
+    }
+
+    // If there are no metrics to send skip the cycle.
+    if metrics.is_empty() {
+        return None;
+    }
+
+    // Take the collected metrics to start collecting the ones of the next cycle.
+    let counts = metrics.take();
+    Some(Payload {
+        body: metrics.report(&counts),
+        content_type: JSON_CONTENT_TYPE,
+        counts: Some(counts),
+    })
+}
+
+/// Delay before the given retry. It grows exponentially up to the push frequency, with a random
+/// jitter so the workers don't retry in lockstep.
+fn backoff(config: &Config, retry: u32) -> Duration {
+    let max = Duration::from_secs(config.push_frequency.max(1) as u64);
+    let delay = INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+        .min(max);
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
 /// Function that will periodically publish collected metrics to the server.
-async fn publish_loop(timer: &Timer, client: &HttpClient, config: &Config, metrics: &Metrics) {
+async fn publish_loop(
+    timer: &Timer,
+    client: &HttpClient,
+    config: &Config,
+    metrics: &Metrics,
+    registry: &Registry,
+    exporter: Option<&Exporter>,
+    cluster: Option<&Cluster<impl Cache>>,
+) {
     // While the policy is still running.
     // Wait for the next cycle.
     while timer.next_tick().await {
-        // If there are no metrics to send skip the cycle.
-        if metrics.is_empty() {
+        let Some(payload) = payload(metrics, registry, exporter, cluster, timer.now()) else {
             continue;
-        }
-
-        // Serialize the metrics to json format.
-        let body = serde_json::to_string(metrics).unwrap_or_else(|_| "{}".to_string());
-
-        // Clear the collected metrics to remove the ones that we are sending.
-        metrics.clear();
+        };
 
         let mut retry = 0; // Counter to keep track of retries.
-        while !publish_metrics(client, config, body.as_str()).await // If the metrics were not published
-            && config.max_retries.map(|max| retry <= max).unwrap_or(true)
-        // and we haven't reached the maximum amount of retries
-        {
+        let published = loop {
+            if publish_metrics(client, config, &payload.body, payload.content_type).await {
+                break true;
+            }
+            // If we reached the maximum amount of retries
+            if config
+                .max_retries
+                .is_some_and(|max| i64::from(retry) >= max)
+            {
+                break false;
+            }
+
             retry += 1;
             // We do an increasing backoff before retrying.
-            if !timer
-                .sleep(Duration::from_secs((config.push_frequency * retry) as u64))
-                .await
-            {
+            if !timer.sleep(backoff(config, retry)).await {
                 // If the sleep method failed it means that no more ticks will arrive and the policy is stopping.
-                break;
+                break false;
+            }
+        };
+
+        // Merge back the metrics that were not published to send them in the next cycle.
+        if !published {
+            match payload.counts {
+                Some(counts) => metrics.restore(counts),
+                None => exporter.into_iter().for_each(Exporter::restore),
             }
         }
     }
 }
 
+/// Size of a message body, as declared by its `Content-Length` header. Chunked and streamed
+/// bodies without the header count as empty, as reading them would buffer the whole body.
+fn content_length(handler: &dyn HeadersHandler) -> u64 {
+    handler
+        .header("content-length")
+        .and_then(|length| length.trim().parse().ok())
+        .unwrap_or_default()
+}
+
 /// Function that will handle the request part of the requests.
-async fn request_filter(state: RequestState) -> Flow<String> {
+async fn request_filter(
+    state: RequestState,
+    config: &Config,
+    registry: &Registry,
+) -> Flow<RequestInfo> {
     let state = state.into_headers_state().await;
+    let path = state.path();
+
+    // Serve the metrics of this worker on the scrape path.
+    if let Some(scrape_path) = &config.scrape_path {
+        if path.split('?').next() == Some(scrape_path.as_str()) && state.method() == "GET" {
+            return Flow::Break(
+                Response::new(200)
+                    .with_headers(vec![("Content-Type".to_string(), CONTENT_TYPE.to_string())])
+                    .with_body(registry.render()),
+            );
+        }
+    }
+
     // Collect data from the request and forward the data to the response filter.
-    Flow::Continue(state.method().to_lowercase())
+    Flow::Continue(RequestInfo {
+        method: state.method(),
+        path,
+        started: SystemTime::now(),
+        request_bytes: content_length(state.handler()),
+    })
 }
 
 /// Function that will handle the response part of the requests.
-async fn response_filter(state: ResponseState, data: RequestData<String>, metrics: &Metrics) {
-    if let RequestData::Continue(method) = data {
+async fn response_filter(
+    state: ResponseState,
+    data: RequestData<RequestInfo>,
+    authentication: Authentication,
+    metrics: Option<&Metrics>,
+    registry: &Registry,
+    templates: &PathTemplates,
+) {
+    if let RequestData::Continue(request) = data {
         let state = state.into_headers_state().await;
+        let status = state.status_code();
+
         // Collect data from the response and track along with the request data.
-        metrics.track(method, state.status_code());
+        if let Some(metrics) = metrics {
+            metrics.track(request.method.to_lowercase(), status);
+        }
+
+        // The consumer is known once the authentication policies have processed the request.
+        let consumer = authentication
+            .authentication()
+            .and_then(|auth| auth.client_id)
+            .unwrap_or_else(|| ANONYMOUS.to_string());
+
+        let labels = Labels {
+            method: request.method,
+            status,
+            path: templates.label(&request.path).to_string(),
+            consumer,
+        };
+        let observation = Observation {
+            latency: request.started.elapsed().unwrap_or_default().as_secs_f64(),
+            request_bytes: request.request_bytes,
+            response_bytes: content_length(state.handler()),
+        };
+        registry.observe(labels, observation);
     }
 }
 
@@ -173,6 +438,9 @@ async fn configure(
     Configuration(bytes): Configuration,
     clock: Clock, // Inject the clock to be able to launch async tasks.
     client: HttpClient,
+    metadata: Metadata,
+    cache: CacheBuilder, // Inject the cache to be able to share the metrics between the workers.
+    lock: LockBuilder,   // Inject the lock to be able to synchronise the workers.
 ) -> Result<()> {
     let config: Config = serde_json::from_slice(&bytes).map_err(|err| {
         anyhow!(
@@ -182,21 +450,94 @@ async fn configure(
         )
     })?;
 
+    let encoding = match config.export_format.as_deref().unwrap_or(JSON_FORMAT) {
+        JSON_FORMAT => None,
+        OTLP_PROTOBUF_FORMAT => Some(Encoding::Protobuf),
+        OTLP_JSON_FORMAT => Some(Encoding::Json),
+        format => return Err(anyhow!("Invalid export format: {format}")),
+    };
+    let aggregate = config.aggregate_workers.unwrap_or_default();
+    if aggregate && encoding.is_some() {
+        return Err(anyhow!(
+            "Only the json export format aggregates the workers"
+        ));
+    }
+    let policy = match config.drop_policy.as_deref().unwrap_or(DROP_OLDEST) {
+        DROP_OLDEST => DropPolicy::Oldest,
+        DROP_NEWEST => DropPolicy::Newest,
+        policy => return Err(anyhow!("Invalid drop policy: {policy}")),
+    };
+    let temporality = match config.temporality.as_deref().unwrap_or(CUMULATIVE) {
+        CUMULATIVE => Temporality::Cumulative,
+        DELTA => Temporality::Delta,
+        temporality => return Err(anyhow!("Invalid temporality: {temporality}")),
+    };
+
     // set the period between ticks of the timer.
     let timer = clock.period(Duration::from_secs(config.push_frequency as u64));
 
     // Create the object that will handle the business logic of the policy.
-    let metrics = Metrics::new(uuid::Uuid::new_v4().to_string());
+    let capacity = config
+        .buffer_size
+        .map_or(DEFAULT_BUFFER_SIZE, |size| size.max(1) as usize);
+    let metrics = Metrics::new(uuid::Uuid::new_v4().to_string(), capacity, policy);
+
+    // Create the cumulative metrics exposed on the scrape path, labeled with the worker.
+    let registry = Registry::new(
+        &metrics.node,
+        config.latency_buckets.as_deref().unwrap_or(DEFAULT_BUCKETS),
+    );
+    let templates = PathTemplates::new(config.path_templates.as_deref().unwrap_or_default());
+
+    // Create the exporter of the OTLP payloads, which identify the worker and the API.
+    let exporter = encoding.map(|encoding| {
+        let mut resource = vec![("node.id", metrics.node.clone())];
+        if let Some(api) = &metadata.api_metadata.id {
+            resource.push(("api.id", api.clone()));
+        }
+        Exporter::new(encoding, temporality, resource)
+    });
+
+    // Create the counters merged by the workers of the gateway instance. The lock expires with a
+    // value bigger than the time it is held, this way, if some worker stops responding, the others
+    // will be able to recover the lock and continue working as expected.
+    let cluster = aggregate.then(|| {
+        let lock = lock
+            .new(ID.to_string())
+            .expiration(Duration::from_secs(5))
+            .build();
+        Cluster::new(
+            cache.new(ID.to_string()).build(),
+            lock,
+            Duration::from_secs(config.push_frequency as u64),
+            capacity,
+            policy,
+        )
+    });
 
     // Create the future tasks.
     // Note: We don't do individual 'await's here because we want both task to progress their execution.
 
     // Future that will publish the metrics periodically
-    let publish = publish_loop(&timer, &client, &config, &metrics);
+    let publish = publish_loop(
+        &timer,
+        &client,
+        &config,
+        &metrics,
+        &registry,
+        exporter.as_ref(),
+        cluster.as_ref(),
+    );
+
+    // The JSON counters are only published without an exporter, which reads the registry instead.
+    let tracked = exporter.is_none().then_some(&metrics);
 
     // Future that will handle the requests
-    let launched = launcher
-        .launch(on_request(request_filter).on_response(|rs, rd| response_filter(rs, rd, &metrics)));
+    let launched = launcher.launch(
+        on_request(|rs| request_filter(rs, &config, &registry)).on_response(|rs, rd, auth| {
+            response_filter(rs, rd, auth, tracked, &registry, &templates)
+        }),
+    );
 
     // Await for both futures to finish
     // Note: Proxy-Wasm Guarantees that they won't be executed in a parallel fashion. Only one tas will
@@ -296,4 +637,303 @@ mod tests {
         assert!(String::from_utf8_lossy(sent.body())
             .contains("\"methods\":{\"get\":1},\"status_codes\":{\"200\":1}"));
     }
+
+    #[test]
+    fn failed_metrics_are_merged_back() {
+        let metric_server = Rc::new(TraceBackend::new(MetricsBackend::new(2)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://metrics-sink",
+                    "pushFrequency": 60,
+                    "maxRetries": 1
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("metrics-sink", Rc::clone(&metric_server))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get());
+        tester.sleep(Duration::from_secs(61));
+
+        // Both attempts of the first cycle were rejected.
+        assert!(metric_server.next().is_some());
+        assert!(metric_server.next().is_some());
+        assert!(metric_server.next().is_none());
+
+        tester.request(UnitHttpRequest::post());
+        tester.sleep(Duration::from_secs(60));
+
+        let sent = metric_server.next().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(sent.body()).unwrap();
+        assert_eq!(body["methods"], json!({"get": 1, "post": 1}));
+        assert_eq!(body["status_codes"], json!({"200": 2}));
+        assert_eq!(body["dropped"], 0);
+    }
+
+    #[test]
+    fn full_buffers_drop_metrics() {
+        let metric_server = Rc::new(TraceBackend::new(MetricsBackend::new(0)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://metrics-sink",
+                    "pushFrequency": 60,
+                    "bufferSize": 1,
+                    "dropPolicy": "newest"
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("metrics-sink", Rc::clone(&metric_server))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get());
+        tester.request(UnitHttpRequest::post());
+        tester.request(UnitHttpRequest::get());
+        tester.sleep(Duration::from_secs(60));
+
+        let sent = metric_server.next().unwrap();
+        let body: serde_json::Value = serde_json::from_slice(sent.body()).unwrap();
+        assert_eq!(body["methods"], json!({"get": 2}));
+        assert_eq!(body["dropped"], 1);
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let config: crate::Config = serde_json::from_str(&config()).unwrap();
+
+        for _ in 0..100 {
+            let first = crate::backoff(&config, 1);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+            let third = crate::backoff(&config, 3);
+            assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+            let tenth = crate::backoff(&config, 10);
+            assert!(tenth >= Duration::from_secs(30) && tenth <= Duration::from_secs(60));
+        }
+    }
+
+    fn aggregated_config() -> String {
+        json!({
+            "metricsSink": "http://metrics-sink",
+            "pushFrequency": 60,
+            "maxRetries": 0,
+            "aggregateWorkers": true
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn workers_publish_an_aggregated_report() {
+        let metric_server = Rc::new(TraceBackend::new(MetricsBackend::new(0)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(aggregated_config())
+            .with_http_upstream_from_authority("metrics-sink", Rc::clone(&metric_server))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get());
+        tester.request(UnitHttpRequest::get());
+        tester.sleep(Duration::from_secs(60));
+
+        let first: serde_json::Value =
+            serde_json::from_slice(metric_server.next().unwrap().body()).unwrap();
+        assert_eq!(first["methods"], json!({"get": 2}));
+        assert_eq!(first["dropped"], 0);
+
+        // Nothing is published without new requests.
+        tester.sleep(Duration::from_secs(60));
+        assert!(metric_server.next().is_none());
+
+        tester.request(UnitHttpRequest::post());
+        tester.sleep(Duration::from_secs(60));
+
+        let second: serde_json::Value =
+            serde_json::from_slice(metric_server.next().unwrap().body()).unwrap();
+        assert_eq!(second["methods"], json!({"post": 1}));
+        // The report identifies the gateway instance.
+        assert_eq!(first["node"], second["node"]);
+    }
+
+    #[test]
+    fn failed_aggregated_reports_are_merged_back() {
+        let metric_server = Rc::new(TraceBackend::new(MetricsBackend::new(1)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(aggregated_config())
+            .with_http_upstream_from_authority("metrics-sink", Rc::clone(&metric_server))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get());
+        tester.sleep(Duration::from_secs(60));
+        assert!(metric_server.next().is_some());
+
+        tester.request(UnitHttpRequest::post());
+        tester.sleep(Duration::from_secs(60));
+
+        let sent: serde_json::Value =
+            serde_json::from_slice(metric_server.next().unwrap().body()).unwrap();
+        assert_eq!(sent["methods"], json!({"get": 1, "post": 1}));
+    }
+
+    #[test]
+    fn otlp_payloads_are_not_aggregated() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://collector",
+                    "pushFrequency": 60,
+                    "exportFormat": "otlpJson",
+                    "aggregateWorkers": true
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("collector", MetricsBackend::new(0))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get());
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn otlp_metrics_sent_to_collector() {
+        let collector = Rc::new(TraceBackend::new(MetricsBackend::new(0)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://collector/v1/metrics",
+                    "pushFrequency": 60,
+                    "exportFormat": "otlpJson",
+                    "temporality": "delta"
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("collector", Rc::clone(&collector))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get().with_path("/users"));
+        tester.request(UnitHttpRequest::get().with_path("/users"));
+        tester.sleep(Duration::from_secs(60));
+
+        let sent = collector.next().unwrap();
+        assert_eq!(sent.header(":path"), Some("/v1/metrics"));
+        assert_eq!(sent.header("content-type"), Some("application/json"));
+
+        let body: serde_json::Value = serde_json::from_slice(sent.body()).unwrap();
+        let resource_metrics = &body["resourceMetrics"][0];
+        assert!(resource_metrics["resource"]["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"key": "api.id", "value": {"stringValue": "1"}})));
+        let requests = &resource_metrics["scopeMetrics"][0]["metrics"][0];
+        assert_eq!(requests["name"], "http.server.request.count");
+        assert_eq!(requests["sum"]["aggregationTemporality"], 1);
+        assert_eq!(requests["sum"]["dataPoints"][0]["asInt"], "2");
+
+        // Without new requests there are no increments to send.
+        tester.sleep(Duration::from_secs(60));
+        assert!(collector.next().is_none());
+    }
+
+    #[test]
+    fn otlp_protobuf_payloads_sent_to_collector() {
+        let collector = Rc::new(TraceBackend::new(MetricsBackend::new(0)));
+
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://collector/v1/metrics",
+                    "pushFrequency": 60,
+                    "exportFormat": "otlpProtobuf"
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("collector", Rc::clone(&collector))
+            .with_entrypoint(crate::configure);
+
+        tester.request(UnitHttpRequest::get());
+        tester.sleep(Duration::from_secs(60));
+
+        let sent = collector.next().unwrap();
+        assert_eq!(sent.header("content-type"), Some("application/x-protobuf"));
+        let body = String::from_utf8_lossy(sent.body());
+        assert!(body.contains("http.server.request.duration"));
+        assert!(body.contains("api.id"));
+
+        // Cumulative totals are sent every cycle.
+        tester.sleep(Duration::from_secs(60));
+        assert!(collector.next().is_some());
+    }
+
+    #[test]
+    fn invalid_export_formats_are_rejected() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://collector",
+                    "pushFrequency": 60,
+                    "exportFormat": "xml"
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("collector", MetricsBackend::new(0))
+            .with_entrypoint(crate::configure);
+
+        let response = tester.request(UnitHttpRequest::get());
+        assert_eq!(response.status_code(), 503);
+    }
+
+    #[test]
+    fn scrape_endpoint_serves_prometheus_metrics() {
+        let mut tester = UnitTestBuilder::default()
+            .with_config(
+                json!({
+                    "metricsSink": "http://metrics-sink",
+                    "pushFrequency": 60,
+                    "scrapePath": "/metrics",
+                    "pathTemplates": ["/users/{id}"],
+                    "latencyBuckets": [0.5, 1]
+                })
+                .to_string(),
+            )
+            .with_http_upstream_from_authority("metrics-sink", MetricsBackend::new(0))
+            .with_entrypoint(crate::configure);
+
+        tester.request(
+            UnitHttpRequest::post()
+                .with_path("/users/42")
+                .with_header("content-length", "5")
+                .with_body("Hello"),
+        );
+        tester.request(UnitHttpRequest::get().with_path("/users/42/avatar"));
+
+        let response = tester.request(UnitHttpRequest::get().with_path("/metrics"));
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            response.header("content-type"),
+            Some("text/plain; version=0.0.4; charset=utf-8")
+        );
+
+        let body = String::from_utf8_lossy(response.body()).to_string();
+        let series = r#"method="POST",status="200",path="/users/{id}",consumer="anonymous""#;
+        let worker = body
+            .split("http_requests_total{worker=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap();
+        assert!(uuid::Uuid::parse_str(worker).is_ok());
+        let series = format!("worker=\"{worker}\",{series}");
+        assert!(body.contains(&format!("http_requests_total{{{series}}} 1\n")));
+        assert!(body.contains(&format!(
+            "http_request_duration_seconds_bucket{{{series},le=\"0.5\"}} 1\n"
+        )));
+        assert!(body.contains(&format!(
+            "http_request_content_length_bytes_total{{{series}}} 5\n"
+        )));
+        assert!(body.contains(r#"path="other""#));
+        // The scrapes are not tracked.
+        assert!(!body.contains(r#"path="/metrics""#));
+    }
 }
@@ -0,0 +1,677 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Request metrics exported as OTLP/HTTP `ExportMetricsServiceRequest` payloads, in the protobuf
+//! or the JSON encoding, so they can be ingested by any OpenTelemetry collector.
+
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::prometheus::{Labels, Series, Snapshot};
+
+/// Name of the instrumentation scope of the exported metrics.
+const SCOPE_NAME: &str = "metrics";
+
+/// Encoding of the exported payloads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "application/x-protobuf",
+            Encoding::Json => "application/json",
+        }
+    }
+}
+
+/// Aggregation temporality of the exported values, numbered as in OTLP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Temporality {
+    /// Each export carries the increments since the previous one.
+    Delta = 1,
+    /// Each export carries the totals since the policy started.
+    Cumulative = 2,
+}
+
+/// Kind of an exported metric.
+enum Kind {
+    /// Monotonic sum of the given value of each series.
+    Sum(fn(&Series) -> u64),
+    /// Latency histogram.
+    Histogram,
+}
+
+/// Metric exported for every series.
+struct Definition {
+    name: &'static str,
+    description: &'static str,
+    unit: &'static str,
+    kind: Kind,
+}
+
+const METRICS: &[Definition] = &[
+    Definition {
+        name: "http.server.request.count",
+        description: "Number of requests.",
+        unit: "{request}",
+        kind: Kind::Sum(requests),
+    },
+    Definition {
+        name: "http.server.request.duration",
+        description: "Latency of the requests.",
+        unit: "s",
+        kind: Kind::Histogram,
+    },
+    Definition {
+        name: "http.server.request.body.size",
+        description: "Bytes declared by the Content-Length headers of the requests, bodies without the header are not counted.",
+        unit: "By",
+        kind: Kind::Sum(request_bytes),
+    },
+    Definition {
+        name: "http.server.response.body.size",
+        description: "Bytes declared by the Content-Length headers of the responses, bodies without the header are not counted.",
+        unit: "By",
+        kind: Kind::Sum(response_bytes),
+    },
+];
+
+fn requests(series: &Series) -> u64 {
+    series.requests
+}
+
+fn request_bytes(series: &Series) -> u64 {
+    series.request_bytes
+}
+
+fn response_bytes(series: &Series) -> u64 {
+    series.response_bytes
+}
+
+/// Value of an attribute.
+enum AnyValue<'a> {
+    String(&'a str),
+    Int(i64),
+}
+
+/// Attributes of the data points of a series.
+fn attributes(labels: &Labels) -> [(&'static str, AnyValue<'_>); 4] {
+    [
+        ("http.request.method", AnyValue::String(&labels.method)),
+        (
+            "http.response.status_code",
+            AnyValue::Int(labels.status.into()),
+        ),
+        ("http.route", AnyValue::String(&labels.path)),
+        ("consumer.id", AnyValue::String(&labels.consumer)),
+    ]
+}
+
+/// Values exported in a payload.
+struct Batch<'a> {
+    start: u64,
+    time: u64,
+    bounds: &'a [f64],
+    series: &'a Snapshot,
+}
+
+/// Builds the payloads with the metrics of the worker.
+pub struct Exporter {
+    encoding: Encoding,
+    temporality: Temporality,
+    resource: Vec<(&'static str, String)>,
+    started: u64,
+    // Time and values of the previous export. Each worker is single threaded so no need for
+    // locking mechanism.
+    previous: RefCell<(u64, Snapshot)>,
+    // Time and values replaced by the last export, until it is published.
+    replaced: RefCell<Option<(u64, Snapshot)>>,
+}
+
+impl Exporter {
+    /// Creates an exporter that describes the worker with the given resource attributes.
+    pub fn new(
+        encoding: Encoding,
+        temporality: Temporality,
+        resource: Vec<(&'static str, String)>,
+    ) -> Self {
+        let started = unix_nanos(SystemTime::now());
+        Self {
+            encoding,
+            temporality,
+            resource,
+            started,
+            previous: RefCell::new((started, Snapshot::new())),
+            replaced: RefCell::new(None),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.encoding.content_type()
+    }
+
+    /// Builds the payload with the current values of the registry, or `None` when there is
+    /// nothing to export.
+    pub fn export(&self, snapshot: Snapshot, bounds: &[f64]) -> Option<Vec<u8>> {
+        self.export_at(snapshot, bounds, unix_nanos(SystemTime::now()))
+    }
+
+    fn export_at(&self, snapshot: Snapshot, bounds: &[f64], now: u64) -> Option<Vec<u8>> {
+        let mut previous = self.previous.borrow_mut();
+        let (start, series) = match self.temporality {
+            Temporality::Cumulative => (self.started, snapshot.clone()),
+            Temporality::Delta => (previous.0, delta(&snapshot, &previous.1)),
+        };
+        let replaced = std::mem::replace(&mut *previous, (now, snapshot));
+        self.replaced.replace(Some(replaced));
+
+        if series.is_empty() {
+            return None;
+        }
+
+        let batch = Batch {
+            start,
+            time: now,
+            bounds,
+            series: &series,
+        };
+        Some(match self.encoding {
+            Encoding::Protobuf => self.protobuf(&batch),
+            Encoding::Json => self.json(&batch),
+        })
+    }
+
+    /// Reverts the last export after failing to publish it, so its increments are exported again
+    /// along with the next ones. The cumulative payloads already carry them.
+    pub fn restore(&self) {
+        if let Some(replaced) = self.replaced.take() {
+            self.previous.replace(replaced);
+        }
+    }
+
+    /// Encodes the batch with the protobuf schema of OTLP.
+    fn protobuf(&self, batch: &Batch) -> Vec<u8> {
+        let mut resource = Message::default();
+        for (key, value) in &self.resource {
+            resource.message(1, key_value(key, &AnyValue::String(value)));
+        }
+
+        let mut scope = Message::default();
+        scope.string(1, SCOPE_NAME);
+        scope.string(2, env!("CARGO_PKG_VERSION"));
+
+        let mut scope_metrics = Message::default();
+        scope_metrics.message(1, scope);
+        for definition in METRICS {
+            let mut data = Message::default();
+            for (labels, series) in batch.series {
+                let mut point = Message::default();
+                point.fixed64(2, batch.start);
+                point.fixed64(3, batch.time);
+                match definition.kind {
+                    Kind::Sum(value) => {
+                        point.fixed64(6, value(series));
+                        for (key, value) in attributes(labels) {
+                            point.message(7, key_value(key, &value));
+                        }
+                    }
+                    Kind::Histogram => {
+                        point.fixed64(4, series.requests);
+                        point.double(5, series.latency_sum);
+                        point.packed_fixed64(6, &bucket_counts(series));
+                        point.packed_double(7, batch.bounds);
+                        for (key, value) in attributes(labels) {
+                            point.message(9, key_value(key, &value));
+                        }
+                    }
+                }
+                data.message(1, point);
+            }
+            data.varint(2, self.temporality as u64);
+
+            let mut metric = Message::default();
+            metric.string(1, definition.name);
+            metric.string(2, definition.description);
+            metric.string(3, definition.unit);
+            match definition.kind {
+                Kind::Sum(_) => {
+                    data.varint(3, true as u64);
+                    metric.message(7, data);
+                }
+                Kind::Histogram => metric.message(9, data),
+            }
+            scope_metrics.message(2, metric);
+        }
+
+        let mut resource_metrics = Message::default();
+        resource_metrics.message(1, resource);
+        resource_metrics.message(2, scope_metrics);
+
+        let mut request = Message::default();
+        request.message(1, resource_metrics);
+        request.0
+    }
+
+    /// Encodes the batch with the JSON mapping of OTLP, where the 64 bit integers are strings.
+    fn json(&self, batch: &Batch) -> Vec<u8> {
+        let resource: Vec<Value> = self
+            .resource
+            .iter()
+            .map(|(key, value)| json_key_value(key, &AnyValue::String(value)))
+            .collect();
+
+        let metrics: Vec<Value> = METRICS
+            .iter()
+            .map(|definition| {
+                let points: Vec<Value> = batch
+                    .series
+                    .iter()
+                    .map(|(labels, series)| {
+                        let mut point = json!({
+                            "attributes": attributes(labels)
+                                .iter()
+                                .map(|(key, value)| json_key_value(key, value))
+                                .collect::<Vec<Value>>(),
+                            "startTimeUnixNano": batch.start.to_string(),
+                            "timeUnixNano": batch.time.to_string(),
+                        });
+                        match definition.kind {
+                            Kind::Sum(value) => point["asInt"] = json!(value(series).to_string()),
+                            Kind::Histogram => {
+                                point["count"] = json!(series.requests.to_string());
+                                point["sum"] = json!(series.latency_sum);
+                                point["bucketCounts"] =
+                                    bucket_counts(series).iter().map(u64::to_string).collect();
+                                point["explicitBounds"] = json!(batch.bounds);
+                            }
+                        }
+                        point
+                    })
+                    .collect();
+
+                let mut metric = json!({
+                    "name": definition.name,
+                    "description": definition.description,
+                    "unit": definition.unit,
+                });
+                match definition.kind {
+                    Kind::Sum(_) => {
+                        metric["sum"] = json!({
+                            "dataPoints": points,
+                            "aggregationTemporality": self.temporality as u64,
+                            "isMonotonic": true,
+                        })
+                    }
+                    Kind::Histogram => {
+                        metric["histogram"] = json!({
+                            "dataPoints": points,
+                            "aggregationTemporality": self.temporality as u64,
+                        })
+                    }
+                }
+                metric
+            })
+            .collect();
+
+        let request = json!({
+            "resourceMetrics": [{
+                "resource": {"attributes": resource},
+                "scopeMetrics": [{
+                    "scope": {"name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION")},
+                    "metrics": metrics,
+                }],
+            }],
+        });
+        serde_json::to_vec(&request).unwrap_or_default()
+    }
+}
+
+/// Increments of the series since the previous snapshot. The series without new requests are
+/// left out.
+fn delta(current: &Snapshot, previous: &Snapshot) -> Snapshot {
+    current
+        .iter()
+        .filter_map(|(labels, series)| {
+            let delta = match previous.get(labels) {
+                Some(previous) => Series {
+                    requests: series.requests - previous.requests,
+                    request_bytes: series.request_bytes - previous.request_bytes,
+                    response_bytes: series.response_bytes - previous.response_bytes,
+                    buckets: series
+                        .buckets
+                        .iter()
+                        .zip(&previous.buckets)
+                        .map(|(current, previous)| current - previous)
+                        .collect(),
+                    latency_sum: series.latency_sum - previous.latency_sum,
+                },
+                None => series.clone(),
+            };
+            (delta.requests > 0).then(|| (labels.clone(), delta))
+        })
+        .collect()
+}
+
+/// Observations in each bucket, including the one above the last bound.
+fn bucket_counts(series: &Series) -> Vec<u64> {
+    let bounded: u64 = series.buckets.iter().sum();
+    let mut counts = series.buckets.clone();
+    counts.push(series.requests - bounded);
+    counts
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+fn key_value(key: &str, value: &AnyValue) -> Message {
+    let mut any_value = Message::default();
+    match value {
+        AnyValue::String(value) => any_value.string(1, value),
+        AnyValue::Int(value) => any_value.varint(3, *value as u64),
+    }
+
+    let mut key_value = Message::default();
+    key_value.string(1, key);
+    key_value.message(2, any_value);
+    key_value
+}
+
+fn json_key_value(key: &str, value: &AnyValue) -> Value {
+    let value = match value {
+        AnyValue::String(value) => json!({ "stringValue": value }),
+        AnyValue::Int(value) => json!({ "intValue": value.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Protobuf message being encoded.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    const VARINT: u32 = 0;
+    const I64: u32 = 1;
+    const LEN: u32 = 2;
+
+    fn tag(&mut self, field: u32, wire_type: u32) {
+        self.raw_varint(u64::from(field << 3 | wire_type));
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn varint(&mut self, field: u32, value: u64) {
+        self.tag(field, Self::VARINT);
+        self.raw_varint(value);
+    }
+
+    fn fixed64(&mut self, field: u32, value: u64) {
+        self.tag(field, Self::I64);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn double(&mut self, field: u32, value: f64) {
+        self.fixed64(field, value.to_bits());
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        self.tag(field, Self::LEN);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed_fixed64(&mut self, field: u32, values: &[u64]) {
+        let packed: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        self.bytes(field, &packed);
+    }
+
+    fn packed_double(&mut self, field: u32, values: &[f64]) {
+        let bits: Vec<u64> = values.iter().map(|value| value.to_bits()).collect();
+        self.packed_fixed64(field, &bits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, Exporter, Temporality};
+    use crate::prometheus::{Labels, Observation, Registry};
+    use serde_json::Value;
+    use std::convert::TryInto;
+
+    /// Value of a decoded protobuf field.
+    #[derive(Debug, PartialEq)]
+    enum Field {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    fn varint(bytes: &[u8], position: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*position];
+            *position += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Decodes the fields of a protobuf message.
+    fn decode(bytes: &[u8]) -> Vec<(u64, Field)> {
+        let mut fields = Vec::new();
+        let mut position = 0;
+        while position < bytes.len() {
+            let tag = varint(bytes, &mut position);
+            let field = match tag & 0x7 {
+                0 => Field::Varint(varint(bytes, &mut position)),
+                1 => {
+                    let value = bytes[position..position + 8].try_into().unwrap();
+                    position += 8;
+                    Field::Fixed64(u64::from_le_bytes(value))
+                }
+                2 => {
+                    let length = varint(bytes, &mut position) as usize;
+                    position += length;
+                    Field::Bytes(bytes[position - length..position].to_vec())
+                }
+                wire_type => panic!("Unexpected wire type {}", wire_type),
+            };
+            fields.push((tag >> 3, field));
+        }
+        fields
+    }
+
+    /// Messages of the given field.
+    fn messages(bytes: &[u8], number: u64) -> Vec<Vec<u8>> {
+        decode(bytes)
+            .into_iter()
+            .filter_map(|(field, value)| match value {
+                Field::Bytes(bytes) if field == number => Some(bytes),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn field(bytes: &[u8], number: u64) -> Field {
+        decode(bytes)
+            .into_iter()
+            .find(|(field, _)| *field == number)
+            .map(|(_, value)| value)
+            .unwrap()
+    }
+
+    fn observe(registry: &Registry, status: u32, latency: f64) {
+        let labels = Labels {
+            method: "GET".to_string(),
+            status,
+            path: "/users/{id}".to_string(),
+            consumer: "anonymous".to_string(),
+        };
+        let observation = Observation {
+            latency,
+            request_bytes: 10,
+            response_bytes: 100,
+        };
+        registry.observe(labels, observation);
+    }
+
+    fn exporter(encoding: Encoding, temporality: Temporality) -> Exporter {
+        let resource = vec![
+            ("node.id", "node-1".to_string()),
+            ("api.id", "api-1".to_string()),
+        ];
+        Exporter::new(encoding, temporality, resource)
+    }
+
+    #[test]
+    fn protobuf_payloads_follow_the_otlp_schema() {
+        let registry = Registry::new("worker-1", &[0.1, 1.0]);
+        observe(&registry, 200, 0.05);
+        observe(&registry, 200, 5.0);
+
+        let exporter = exporter(Encoding::Protobuf, Temporality::Cumulative);
+        let payload = exporter
+            .export_at(registry.snapshot(), registry.buckets(), 1000)
+            .unwrap();
+
+        let resource_metrics = &messages(&payload, 1)[0];
+        let resource = &messages(resource_metrics, 1)[0];
+        let node = &messages(resource, 1)[0];
+        assert_eq!(field(node, 1), Field::Bytes(b"node.id".to_vec()));
+        assert_eq!(
+            field(&messages(node, 2)[0], 1),
+            Field::Bytes(b"node-1".to_vec())
+        );
+
+        let scope_metrics = &messages(resource_metrics, 2)[0];
+        let metrics = messages(scope_metrics, 2);
+        assert_eq!(metrics.len(), 4);
+
+        let count = &metrics[0];
+        assert_eq!(
+            field(count, 1),
+            Field::Bytes(b"http.server.request.count".to_vec())
+        );
+        let sum = &messages(count, 7)[0];
+        assert_eq!(field(sum, 2), Field::Varint(2));
+        assert_eq!(field(sum, 3), Field::Varint(1));
+        let point = &messages(sum, 1)[0];
+        assert_eq!(field(point, 3), Field::Fixed64(1000));
+        assert_eq!(field(point, 6), Field::Fixed64(2));
+        let status = &messages(point, 7)[1];
+        assert_eq!(field(&messages(status, 2)[0], 3), Field::Varint(200));
+
+        let histogram = &messages(&metrics[1], 9)[0];
+        let point = &messages(histogram, 1)[0];
+        assert_eq!(field(point, 4), Field::Fixed64(2));
+        assert_eq!(field(point, 5), Field::Fixed64(5.05f64.to_bits()));
+        let buckets: Vec<u64> = messages(point, 6)[0]
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(buckets, vec![1, 0, 1]);
+        assert_eq!(messages(point, 7)[0].len(), 16);
+    }
+
+    #[test]
+    fn delta_payloads_carry_the_increments() {
+        let registry = Registry::new("worker-1", &[1.0]);
+        observe(&registry, 200, 0.5);
+        observe(&registry, 500, 0.5);
+
+        let exporter = exporter(Encoding::Json, Temporality::Delta);
+        let first = exporter
+            .export_at(registry.snapshot(), registry.buckets(), 1000)
+            .unwrap();
+        let first: Value = serde_json::from_slice(&first).unwrap();
+        let points = &first["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0]["sum"];
+        assert_eq!(points["aggregationTemporality"], 1);
+        assert_eq!(points["dataPoints"].as_array().unwrap().len(), 2);
+
+        // Nothing changed since the previous export.
+        assert!(exporter
+            .export_at(registry.snapshot(), registry.buckets(), 2000)
+            .is_none());
+
+        // The increments of failed exports are exported again.
+        observe(&registry, 200, 2.0);
+        exporter.export_at(registry.snapshot(), registry.buckets(), 2500);
+        exporter.restore();
+        let second = exporter
+            .export_at(registry.snapshot(), registry.buckets(), 3000)
+            .unwrap();
+        let second: Value = serde_json::from_slice(&second).unwrap();
+        let metrics = &second["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+
+        let points = metrics[0]["sum"]["dataPoints"].as_array().unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0]["asInt"], "1");
+        assert_eq!(points[0]["startTimeUnixNano"], "2000");
+        assert_eq!(points[0]["timeUnixNano"], "3000");
+        assert_eq!(
+            points[0]["attributes"][1]["value"]["intValue"],
+            Value::from("200")
+        );
+
+        let histogram = &metrics[1]["histogram"]["dataPoints"][0];
+        assert_eq!(histogram["count"], "1");
+        assert_eq!(histogram["bucketCounts"], serde_json::json!(["0", "1"]));
+        assert_eq!(metrics[2]["sum"]["dataPoints"][0]["asInt"], "10");
+    }
+
+    #[test]
+    fn cumulative_payloads_carry_the_totals() {
+        let registry = Registry::new("worker-1", &[1.0]);
+        let exporter = exporter(Encoding::Json, Temporality::Cumulative);
+        assert!(exporter
+            .export_at(registry.snapshot(), registry.buckets(), 1000)
+            .is_none());
+
+        observe(&registry, 200, 0.5);
+        exporter.export_at(registry.snapshot(), registry.buckets(), 2000);
+        observe(&registry, 200, 0.5);
+        let payload = exporter
+            .export_at(registry.snapshot(), registry.buckets(), 3000)
+            .unwrap();
+        let payload: Value = serde_json::from_slice(&payload).unwrap();
+        let metric = &payload["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0];
+
+        assert_eq!(metric["sum"]["aggregationTemporality"], 2);
+        assert_eq!(metric["sum"]["dataPoints"][0]["asInt"], "2");
+        assert_eq!(
+            metric["sum"]["dataPoints"][0]["startTimeUnixNano"],
+            exporter.started.to_string()
+        );
+        assert_eq!(
+            payload["resourceMetrics"][0]["resource"]["attributes"][1],
+            serde_json::json!({"key": "api.id", "value": {"stringValue": "api-1"}})
+        );
+    }
+}
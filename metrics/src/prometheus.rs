@@ -0,0 +1,269 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Cumulative request metrics rendered in the Prometheus text exposition format.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Default latency buckets in seconds, the same as the Prometheus client libraries.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Labels that identify a series.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Labels {
+    pub method: String,
+    pub status: u32,
+    pub path: String,
+    pub consumer: String,
+}
+
+impl Labels {
+    fn render(&self) -> String {
+        format!(
+            "method=\"{}\",status=\"{}\",path=\"{}\",consumer=\"{}\"",
+            escape(&self.method),
+            self.status,
+            escape(&self.path),
+            escape(&self.consumer)
+        )
+    }
+}
+
+/// Observation of a single request.
+pub struct Observation {
+    pub latency: f64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+}
+
+/// Values of a series.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Series {
+    pub requests: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// Observations in each bucket, not cumulative. The observations above the last bound are
+    /// only counted in `requests`.
+    pub buckets: Vec<u64>,
+    pub latency_sum: f64,
+}
+
+/// Values of every series at some point in time.
+pub type Snapshot = BTreeMap<Labels, Series>;
+
+/// Cumulative metrics of the requests handled by the worker.
+pub struct Registry {
+    /// Label that identifies the worker, as each one serves only its own metrics.
+    worker: String,
+    buckets: Vec<f64>,
+    // Each worker is single threaded so no need for locking mechanism, as long as the mutable
+    // reference is released before the next 'await' directive.
+    series: RefCell<BTreeMap<Labels, Series>>,
+}
+
+impl Registry {
+    /// Creates the registry of a worker with the given upper bounds of the latency buckets, in
+    /// seconds.
+    pub fn new(worker: &str, buckets: &[f64]) -> Self {
+        let mut buckets: Vec<f64> = buckets.iter().copied().filter(|b| b.is_finite()).collect();
+        buckets.sort_by(|a, b| a.total_cmp(b));
+        buckets.dedup();
+
+        Self {
+            worker: format!("worker=\"{}\"", escape(worker)),
+            buckets,
+            series: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Records a request.
+    pub fn observe(&self, labels: Labels, observation: Observation) {
+        let mut series = self.series.borrow_mut();
+        let series = series.entry(labels).or_insert_with(|| Series {
+            buckets: vec![0; self.buckets.len()],
+            ..Series::default()
+        });
+
+        series.requests += 1;
+        series.request_bytes += observation.request_bytes;
+        series.response_bytes += observation.response_bytes;
+        series.latency_sum += observation.latency;
+        if let Some(bucket) = self
+            .buckets
+            .iter()
+            .position(|bound| observation.latency <= *bound)
+        {
+            series.buckets[bucket] += 1;
+        }
+    }
+
+    /// Upper bounds of the latency buckets, in seconds.
+    pub fn buckets(&self) -> &[f64] {
+        &self.buckets
+    }
+
+    /// Current values of every series.
+    pub fn snapshot(&self) -> Snapshot {
+        self.series.borrow().clone()
+    }
+
+    /// Renders the metrics in the text exposition format.
+    pub fn render(&self) -> String {
+        let series = self.series.borrow();
+        let series: Vec<(String, &Series)> = series
+            .iter()
+            .map(|(labels, series)| (format!("{},{}", self.worker, labels.render()), series))
+            .collect();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Total number of requests.",
+        );
+        for (labels, series) in &series {
+            sample(&mut out, "http_requests_total", labels, series.requests);
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Latency of the requests in seconds.",
+        );
+        for (labels, series) in &series {
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(&series.buckets) {
+                cumulative += count;
+                let labels = format!("{labels},le=\"{bound}\"");
+                sample(
+                    &mut out,
+                    "http_request_duration_seconds_bucket",
+                    &labels,
+                    cumulative,
+                );
+            }
+            let labels_inf = format!("{labels},le=\"+Inf\"");
+            sample(
+                &mut out,
+                "http_request_duration_seconds_bucket",
+                &labels_inf,
+                series.requests,
+            );
+            sample(
+                &mut out,
+                "http_request_duration_seconds_sum",
+                labels,
+                series.latency_sum,
+            );
+            sample(
+                &mut out,
+                "http_request_duration_seconds_count",
+                labels,
+                series.requests,
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_content_length_bytes_total",
+            "counter",
+            "Total bytes declared by the Content-Length headers of the requests, bodies without the header are not counted.",
+        );
+        for (labels, series) in &series {
+            sample(
+                &mut out,
+                "http_request_content_length_bytes_total",
+                labels,
+                series.request_bytes,
+            );
+        }
+
+        header(
+            &mut out,
+            "http_response_content_length_bytes_total",
+            "counter",
+            "Total bytes declared by the Content-Length headers of the responses, bodies without the header are not counted.",
+        );
+        for (labels, series) in &series {
+            sample(
+                &mut out,
+                "http_response_content_length_bytes_total",
+                labels,
+                series.response_bytes,
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{name}{{{labels}}} {value}");
+}
+
+/// Escapes a label value as defined by the exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Labels, Observation, Registry};
+
+    fn labels(path: &str) -> Labels {
+        Labels {
+            method: "GET".to_string(),
+            status: 200,
+            path: path.to_string(),
+            consumer: "app \"1\"".to_string(),
+        }
+    }
+
+    #[test]
+    fn histograms_are_cumulative() {
+        let registry = Registry::new("worker-1", &[1.0, 0.1, 0.5]);
+        for latency in [0.0625, 0.25, 0.25, 2.0] {
+            let observation = Observation {
+                latency,
+                request_bytes: 10,
+                response_bytes: 100,
+            };
+            registry.observe(labels("/users/{id}"), observation);
+        }
+
+        let rendered = registry.render();
+        let series = r#"worker="worker-1",method="GET",status="200",path="/users/{id}",consumer="app \"1\"""#;
+
+        assert!(rendered.contains("# TYPE http_request_duration_seconds histogram\n"));
+        assert!(rendered.contains(&format!("http_requests_total{{{series}}} 4\n")));
+        for (bound, count) in [("0.1", 1), ("0.5", 3), ("1", 3), ("+Inf", 4)] {
+            assert!(rendered.contains(&format!(
+                "http_request_duration_seconds_bucket{{{series},le=\"{bound}\"}} {count}\n"
+            )));
+        }
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_sum{{{series}}} 2.5625\n"
+        )));
+        assert!(rendered.contains(&format!(
+            "http_request_content_length_bytes_total{{{series}}} 40\n"
+        )));
+        assert!(rendered.contains(&format!(
+            "http_response_content_length_bytes_total{{{series}}} 400\n"
+        )));
+    }
+}
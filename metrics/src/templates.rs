@@ -0,0 +1,78 @@
+// Copyright 2023 Salesforce, Inc. All rights reserved.
+//! Path templates that bound the cardinality of the `path` label.
+
+/// Label of the paths that don't match any template.
+pub const UNMATCHED_PATH: &str = "other";
+
+/// Configured templates, such as `/users/{id}/orders`. A `{name}` segment matches any single
+/// segment, and a trailing `*` segment matches any remaining segments.
+pub struct PathTemplates {
+    templates: Vec<(String, Vec<String>)>,
+}
+
+impl PathTemplates {
+    pub fn new(templates: &[String]) -> Self {
+        Self {
+            templates: templates
+                .iter()
+                .map(|template| (template.clone(), segments(template)))
+                .collect(),
+        }
+    }
+
+    /// Template of the path, or [`UNMATCHED_PATH`] when no template matches. The first matching
+    /// template wins.
+    pub fn label(&self, path: &str) -> &str {
+        let path = segments(path.split('?').next().unwrap_or_default());
+
+        self.templates
+            .iter()
+            .find(|(_, template)| matches(template, &path))
+            .map(|(template, _)| template.as_str())
+            .unwrap_or(UNMATCHED_PATH)
+    }
+}
+
+fn segments(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn matches(template: &[String], path: &[String]) -> bool {
+    match (template.split_first(), path.split_first()) {
+        (Some((wildcard, [])), _) if wildcard == "*" => true,
+        (Some((expected, template)), Some((segment, path))) => {
+            let variable = expected.starts_with('{') && expected.ends_with('}');
+            (variable || expected == segment) && matches(template, path)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PathTemplates, UNMATCHED_PATH};
+
+    #[test]
+    fn paths_are_labeled_with_their_template() {
+        let templates = PathTemplates::new(&[
+            "/users/{id}".to_string(),
+            "/users/{id}/orders".to_string(),
+            "/static/*".to_string(),
+            "/".to_string(),
+        ]);
+
+        assert_eq!(templates.label("/users/42"), "/users/{id}");
+        assert_eq!(
+            templates.label("/users/42/orders?page=1"),
+            "/users/{id}/orders"
+        );
+        assert_eq!(templates.label("/static/css/site.css"), "/static/*");
+        assert_eq!(templates.label("/"), "/");
+        assert_eq!(templates.label("/users"), UNMATCHED_PATH);
+        assert_eq!(templates.label("/users/42/invoices"), UNMATCHED_PATH);
+    }
+}